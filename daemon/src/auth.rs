@@ -0,0 +1,374 @@
+//! Pairing codes and bearer tokens for the local HTTP API.
+//!
+//! The daemon issues a short-lived, single-use pairing code (`savebutton-daemon pair`).
+//! The extension trades that code for a bearer token via `POST /pair`, and sends the
+//! token on every mutating request. Only SHA-256 hashes of tokens are kept on disk.
+//!
+//! The Kaya-Server-compatible API also accepts HTTP Basic auth, checked against a
+//! PBKDF2 hash of the password from `savebutton-daemon api-user`. A
+//! [`PasswordGuard`] remembers recently verified passwords, so PBKDF2 runs once per
+//! client session rather than once per request, and refuses a client after too
+//! many failures.
+
+use crate::store::{self, StoreError};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use chrono::{DateTime, Duration, Utc};
+use rand::{Rng, RngCore};
+use ring::digest::{digest, SHA256};
+use ring::pbkdf2;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Instant;
+
+/// How long a pairing code stays valid after it is issued.
+pub const PAIRING_CODE_TTL_MINUTES: i64 = 10;
+/// Failed redemptions allowed before a pairing code is discarded.
+pub const PAIRING_MAX_ATTEMPTS: u32 = 5;
+/// How long a verified API password is accepted without running PBKDF2 again.
+pub const PASSWORD_CACHE_SECS: u64 = 300;
+/// Failed API logins allowed per client before it is refused for the rest of
+/// [`LOGIN_WINDOW_SECS`].
+pub const LOGIN_MAX_FAILURES: u32 = 5;
+pub const LOGIN_WINDOW_SECS: u64 = 60;
+
+// No 0/O or 1/I, so codes survive being read aloud or retyped.
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const CODE_LEN: usize = 8;
+const TOKEN_BYTES: usize = 32;
+const PASSWORD_ITERATIONS: u32 = 100_000;
+const PASSWORD_SALT_BYTES: usize = 16;
+const PASSWORD_HASH_BYTES: usize = 32;
+
+/// Held while `.tokens` or `.pairing` is read, changed and saved, so concurrent
+/// pairings cannot drop each other's tokens.
+static FILES_LOCK: Mutex<()> = Mutex::new(());
+
+fn lock_files() -> MutexGuard<'static, ()> {
+    FILES_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TokenRecord {
+    pub id: String,
+    pub label: String,
+    pub hash: String,
+    pub created: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct TokenFile {
+    #[serde(default)]
+    token: Vec<TokenRecord>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PairingFile {
+    code: String,
+    expires: DateTime<Utc>,
+    attempts: u32,
+}
+
+fn tokens_path(kaya_dir: &Path) -> PathBuf {
+    kaya_dir.join(".tokens")
+}
+
+fn pairing_path(kaya_dir: &Path) -> PathBuf {
+    kaya_dir.join(".pairing")
+}
+
+fn invalid_data(e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+fn read_toml<T: Default + for<'de> Deserialize<'de>>(path: &Path) -> io::Result<T> {
+    match fs::read_to_string(path) {
+        Ok(content) => toml::from_str(&content).map_err(invalid_data),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Saves `value` through a temp file renamed into place, so readers never see a
+/// partly written file.
+fn write_toml<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    let (Some(dir), Some(filename)) = (path.parent(), path.file_name()) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "not a file path",
+        ));
+    };
+    let content = toml::to_string(value).map_err(invalid_data)?;
+    store::write_streamed(
+        dir,
+        &filename.to_string_lossy(),
+        content.as_bytes(),
+        content.len() as u64,
+    )
+    .map_err(|e| match e {
+        StoreError::Io(e) => e,
+        e => io::Error::other(e),
+    })?;
+    Ok(())
+}
+
+/// Hex-encoded SHA-256 of a bearer token, as stored in `~/.kaya/.tokens`.
+pub fn hash_token(token: &str) -> String {
+    digest(&SHA256, token.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Issues a fresh pairing code, replacing any outstanding one.
+pub fn issue_pairing_code(kaya_dir: &Path) -> io::Result<String> {
+    let mut rng = rand::thread_rng();
+    let code: String = (0..CODE_LEN)
+        .map(|_| CODE_ALPHABET[rng.gen_range(0..CODE_ALPHABET.len())] as char)
+        .collect();
+
+    let _lock = lock_files();
+    let pairing = PairingFile {
+        code: code.clone(),
+        expires: Utc::now() + Duration::minutes(PAIRING_CODE_TTL_MINUTES),
+        attempts: 0,
+    };
+    write_toml(&pairing_path(kaya_dir), &pairing)?;
+    Ok(code)
+}
+
+/// Trades a pairing code for a new bearer token.
+///
+/// Returns `Ok(None)` if there is no outstanding code, it has expired, or `code` does
+/// not match. The code is consumed on success and after too many failed attempts.
+pub fn redeem_pairing_code(kaya_dir: &Path, code: &str, label: &str) -> io::Result<Option<String>> {
+    let _lock = lock_files();
+    let path = pairing_path(kaya_dir);
+    let content = match fs::read_to_string(&path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut pairing: PairingFile = toml::from_str(&content).map_err(invalid_data)?;
+
+    if Utc::now() > pairing.expires {
+        fs::remove_file(&path)?;
+        return Ok(None);
+    }
+
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    if normalized != pairing.code {
+        pairing.attempts += 1;
+        if pairing.attempts >= PAIRING_MAX_ATTEMPTS {
+            fs::remove_file(&path)?;
+        } else {
+            write_toml(&path, &pairing)?;
+        }
+        return Ok(None);
+    }
+
+    fs::remove_file(&path)?;
+
+    let mut bytes = [0u8; TOKEN_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token = URL_SAFE_NO_PAD.encode(bytes);
+    let hash = hash_token(&token);
+
+    let mut file: TokenFile = read_toml(&tokens_path(kaya_dir))?;
+    file.token.push(TokenRecord {
+        id: hash[..8].to_string(),
+        label: label.to_string(),
+        hash,
+        created: Utc::now(),
+    });
+    write_toml(&tokens_path(kaya_dir), &file)?;
+
+    Ok(Some(token))
+}
+
+/// Returns true if `token` belongs to a paired (and not revoked) client.
+pub fn verify_token(kaya_dir: &Path, token: &str) -> io::Result<bool> {
+    if token.is_empty() {
+        return Ok(false);
+    }
+    let hash = hash_token(token);
+    let file: TokenFile = read_toml(&tokens_path(kaya_dir))?;
+    Ok(file.token.iter().any(|t| t.hash == hash))
+}
+
+pub fn list_tokens(kaya_dir: &Path) -> io::Result<Vec<TokenRecord>> {
+    let file: TokenFile = read_toml(&tokens_path(kaya_dir))?;
+    Ok(file.token)
+}
+
+/// Revokes the token with the given id. Returns false if no such token exists.
+pub fn revoke_token(kaya_dir: &Path, id: &str) -> io::Result<bool> {
+    let _lock = lock_files();
+    let mut file: TokenFile = read_toml(&tokens_path(kaya_dir))?;
+    let before = file.token.len();
+    file.token.retain(|t| t.id != id);
+    if file.token.len() == before {
+        return Ok(false);
+    }
+    write_toml(&tokens_path(kaya_dir), &file)?;
+    Ok(true)
+}
+
+/// Extracts the token from an `Authorization: Bearer <token>` header value.
+pub fn bearer_token(header_value: &str) -> Option<&str> {
+    let (scheme, token) = header_value.trim().split_once(' ')?;
+    if scheme.eq_ignore_ascii_case("Bearer") {
+        Some(token.trim())
+    } else {
+        None
+    }
+}
+
+/// A salted PBKDF2-HMAC-SHA256 hash of `password`, as
+/// `pbkdf2-sha256${iterations}${salt}${hash}` with base64 salt and hash.
+pub fn hash_password(password: &str) -> String {
+    let mut salt = [0u8; PASSWORD_SALT_BYTES];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let mut hash = [0u8; PASSWORD_HASH_BYTES];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(PASSWORD_ITERATIONS).expect("iterations are non-zero"),
+        &salt,
+        password.as_bytes(),
+        &mut hash,
+    );
+    format!(
+        "pbkdf2-sha256${}${}${}",
+        PASSWORD_ITERATIONS,
+        STANDARD.encode(salt),
+        STANDARD.encode(hash)
+    )
+}
+
+/// Returns true if `password` matches a hash from [`hash_password`].
+pub fn verify_password(stored: &str, password: &str) -> bool {
+    let mut fields = stored.split('$');
+    let (Some("pbkdf2-sha256"), Some(iterations), Some(salt), Some(hash), None) = (
+        fields.next(),
+        fields.next(),
+        fields.next(),
+        fields.next(),
+        fields.next(),
+    ) else {
+        return false;
+    };
+    let (Some(iterations), Ok(salt), Ok(hash)) = (
+        iterations.parse().ok().and_then(NonZeroU32::new),
+        STANDARD.decode(salt),
+        STANDARD.decode(hash),
+    ) else {
+        return false;
+    };
+    pbkdf2::verify(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        iterations,
+        &salt,
+        password.as_bytes(),
+        &hash,
+    )
+    .is_ok()
+}
+
+/// What [`PasswordGuard::check`] made of a password.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Login {
+    Accepted,
+    Refused,
+    /// The client failed too often recently; the password was not checked.
+    Throttled,
+}
+
+/// Checks API passwords with [`verify_password`], caching successes and bounding
+/// failures per client.
+#[derive(Debug, Default)]
+pub struct PasswordGuard {
+    state: Mutex<GuardState>,
+}
+
+#[derive(Debug, Default)]
+struct GuardState {
+    /// SHA-256 of the stored hash and password, with when it was verified.
+    verified: HashMap<Vec<u8>, Instant>,
+    /// Failures per client, with when its window started.
+    failures: HashMap<IpAddr, (u32, Instant)>,
+}
+
+fn elapsed_secs(since: Instant, now: Instant) -> u64 {
+    now.saturating_duration_since(since).as_secs()
+}
+
+impl PasswordGuard {
+    /// Whether `password` from `client` matches `stored`, a hash from
+    /// [`hash_password`], at `now`.
+    pub fn check(&self, client: IpAddr, stored: &str, password: &str, now: Instant) -> Login {
+        // Keyed by the stored hash too, so changing the password drops the cache
+        let key = digest(&SHA256, format!("{}\n{}", stored, password).as_bytes())
+            .as_ref()
+            .to_vec();
+        {
+            let state = self.lock();
+            if state
+                .verified
+                .get(&key)
+                .is_some_and(|&at| elapsed_secs(at, now) < PASSWORD_CACHE_SECS)
+            {
+                return Login::Accepted;
+            }
+            if state.failures.get(&client).is_some_and(|&(count, start)| {
+                count >= LOGIN_MAX_FAILURES && elapsed_secs(start, now) < LOGIN_WINDOW_SECS
+            }) {
+                return Login::Throttled;
+            }
+        }
+
+        // PBKDF2 runs without the lock, so other clients are not held up
+        let matches = verify_password(stored, password);
+        let mut state = self.lock();
+        if matches {
+            state
+                .verified
+                .retain(|_, at| elapsed_secs(*at, now) < PASSWORD_CACHE_SECS);
+            state.verified.insert(key, now);
+            state.failures.remove(&client);
+            return Login::Accepted;
+        }
+        state
+            .failures
+            .retain(|_, (_, start)| elapsed_secs(*start, now) < LOGIN_WINDOW_SECS);
+        state.failures.entry(client).or_insert((0, now)).0 += 1;
+        Login::Refused
+    }
+
+    fn lock(&self) -> MutexGuard<'_, GuardState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Extracts the user and password from an `Authorization: Basic ...` header value.
+pub fn basic_credentials(header_value: &str) -> Option<(String, String)> {
+    let (scheme, encoded) = header_value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Basic") {
+        return None;
+    }
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
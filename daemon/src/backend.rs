@@ -0,0 +1,373 @@
+//! Where sync sends and fetches files.
+//!
+//! A [`SyncBackend`] stores the same tree as `~/.kaya`: flat collections
+//! (`anga`, `meta`, `smart`) and nested ones (`words/{anga}`, `cache/{bookmark}`).
+//! Paths are given as segments, e.g. `["words", anga, filename]`, spelled the way
+//! the backend listed them; each backend encodes them for its own storage.
+//!
+//! * [`KayaServer`] talks to the Kaya Server HTTP API (ADR 0002).
+//! * [`Directory`] mirrors into a plain directory, e.g. a USB stick or NAS mount.
+//! * [`crate::peer::Peer`] talks to another daemon on the LAN.
+
+use reqwest::blocking::Client;
+use reqwest::StatusCode;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::codec;
+use crate::download::{DownloadError, Downloaded, PartialStore};
+use crate::filename::mime_type_for;
+use crate::integrity::{self, Mismatch};
+use crate::listing::{self, Listing, ListingError};
+use crate::peer::PeerAuthError;
+use crate::sanitize::{self, UnsafeName};
+use crate::store::{self, StoreError, WriteOutcome};
+use crate::transfer::{ThrottledReader, TokenBucket};
+
+/// Slowest upload rate assumed when sizing upload timeouts, in bytes per second.
+const MIN_EXPECTED_UPLOAD_RATE: u64 = 64 * 1024;
+
+/// Every collection in `~/.kaya`, in sync order.
+pub const COLLECTIONS: [&str; 5] = ["anga", "meta", "smart", "words", "cache"];
+
+#[derive(Error, Debug)]
+pub enum BackendError {
+    #[error("server responded with {0}")]
+    Status(StatusCode),
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("download failed: {0}")]
+    Download(#[from] DownloadError),
+    #[error("listing failed: {0}")]
+    Listing(#[from] ListingError),
+    #[error("{} does not match the remote copy (local {}, remote {})", .0.path, .0.local, .0.server)]
+    DigestMismatch(Mismatch),
+    #[error("unsafe path: {0}")]
+    UnsafeName(#[from] UnsafeName),
+    #[error("unauthenticated peer response: {0}")]
+    PeerAuth(#[from] PeerAuthError),
+}
+
+impl BackendError {
+    /// Whether trying again may succeed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            BackendError::Status(status) => {
+                status.is_server_error()
+                    || *status == StatusCode::REQUEST_TIMEOUT
+                    || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            BackendError::Http(e) => {
+                e.is_timeout() || e.is_connect() || e.is_request() || e.is_body()
+            }
+            BackendError::Download(e) => e.is_transient(),
+            BackendError::Listing(e) => e.is_transient(),
+            BackendError::Io(_)
+            | BackendError::DigestMismatch(_)
+            | BackendError::UnsafeName(_)
+            | BackendError::PeerAuth(_) => false,
+        }
+    }
+}
+
+/// How a backend took a [`SyncBackend::put`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PutOutcome {
+    Stored,
+    /// The backend already has a file at this path, with this SHA-256 if known.
+    Exists(Option<String>),
+}
+
+pub trait SyncBackend: Send + Sync {
+    /// Names directly under `dir`, e.g. `["anga"]` or `["words", anga]`. `since`
+    /// is a cursor from an earlier listing; backends without incremental listings
+    /// ignore it and return everything.
+    fn list(&self, dir: &[&str], since: Option<&str>) -> Result<Listing, BackendError>;
+
+    /// Fetches the file at `path` into `dest`, which only appears once complete.
+    /// Never replaces an existing file in an immutable collection (see
+    /// [`store::is_immutable`]): the same bytes are kept, different ones are a
+    /// [`DownloadError::Conflict`].
+    fn get(&self, path: &[&str], dest: &Path) -> Result<Downloaded, BackendError>;
+
+    /// Stores the local file `src`, whose SHA-256 is `sha256`, at `path`. Never
+    /// replaces an existing file.
+    fn put(&self, path: &[&str], src: &Path, sha256: &str) -> Result<PutOutcome, BackendError>;
+
+    /// The SHA-256 the backend holds for `path`, if it can tell.
+    fn digest(&self, path: &[&str]) -> Result<Option<String>, BackendError>;
+
+    /// Whether `collection` may be written with [`SyncBackend::put`]. Sync only
+    /// downloads collections that the backend generates itself.
+    fn accepts_uploads(&self, collection: &str) -> bool;
+
+    /// The collections sync exchanges with this backend, from [`COLLECTIONS`].
+    fn collections(&self) -> &'static [&'static str];
+}
+
+/// Per-request timeout for an upload of `len` bytes. A client's default timeout
+/// covers the whole request, which a large or throttled upload can legitimately
+/// exceed.
+pub fn upload_timeout(bandwidth: &TokenBucket, len: u64) -> Duration {
+    let rate = bandwidth
+        .rate()
+        .unwrap_or(MIN_EXPECTED_UPLOAD_RATE)
+        .min(MIN_EXPECTED_UPLOAD_RATE);
+    Duration::from_secs(30 + len / rate)
+}
+
+/// The Kaya Server HTTP API, `/api/v1/{email}/...` with Basic auth.
+pub struct KayaServer {
+    client: Client,
+    base: String,
+    email: String,
+    password: String,
+    bandwidth: Arc<TokenBucket>,
+    partials: PartialStore,
+}
+
+impl KayaServer {
+    /// A backend for `email` on `server`. Every byte transferred is charged to
+    /// `bandwidth`; interrupted downloads resume from `kaya_dir/.partial`.
+    pub fn new(
+        client: Client,
+        server: &str,
+        email: &str,
+        password: &str,
+        bandwidth: Arc<TokenBucket>,
+        kaya_dir: &Path,
+    ) -> Self {
+        KayaServer {
+            client,
+            base: format!(
+                "{}/api/v1/{}",
+                server.trim_end_matches('/'),
+                codec::url_segment(email)
+            ),
+            email: email.to_string(),
+            password: password.to_string(),
+            bandwidth,
+            partials: PartialStore::new(kaya_dir),
+        }
+    }
+
+    pub fn url(&self, path: &[&str]) -> String {
+        let segments: Vec<String> = path.iter().map(|s| codec::url_segment(s)).collect();
+        format!("{}/{}", self.base, segments.join("/"))
+    }
+}
+
+impl SyncBackend for KayaServer {
+    fn list(&self, dir: &[&str], since: Option<&str>) -> Result<Listing, BackendError> {
+        Ok(listing::fetch(
+            &self.client,
+            &self.url(dir),
+            &self.email,
+            &self.password,
+            since,
+            listing::PAGE_SIZE,
+        )?)
+    }
+
+    fn get(&self, path: &[&str], dest: &Path) -> Result<Downloaded, BackendError> {
+        let request = self
+            .client
+            .get(self.url(path))
+            .basic_auth(&self.email, Some(&self.password))
+            .build()?;
+        Ok(download(
+            &self.partials,
+            &self.client,
+            request,
+            path,
+            dest,
+            &self.bandwidth,
+            None,
+        )?)
+    }
+
+    fn put(&self, path: &[&str], src: &Path, sha256: &str) -> Result<PutOutcome, BackendError> {
+        let filename = path.last().copied().unwrap_or_default();
+        let file = File::open(src)?;
+        let len = file.metadata()?.len();
+
+        let body = ThrottledReader::new(file, self.bandwidth.clone());
+        let part = reqwest::blocking::multipart::Part::reader_with_length(body, len)
+            .file_name(filename.to_string())
+            .mime_str(&mime_type_for(filename))
+            .expect("mime types are valid");
+        let form = reqwest::blocking::multipart::Form::new().part("file", part);
+
+        let response = self
+            .client
+            .post(self.url(path))
+            .basic_auth(&self.email, Some(&self.password))
+            .timeout(upload_timeout(&self.bandwidth, len))
+            .multipart(form)
+            .send()?;
+
+        let server_sha256 = integrity::server_digest(response.headers());
+        if response.status() == StatusCode::CONFLICT {
+            return Ok(PutOutcome::Exists(server_sha256));
+        }
+        if !response.status().is_success() {
+            return Err(BackendError::Status(response.status()));
+        }
+        match server_sha256 {
+            Some(server) if server != sha256 => Err(BackendError::DigestMismatch(Mismatch {
+                path: path.join("/"),
+                local: sha256.to_string(),
+                server,
+            })),
+            _ => Ok(PutOutcome::Stored),
+        }
+    }
+
+    fn digest(&self, path: &[&str]) -> Result<Option<String>, BackendError> {
+        let response = self
+            .client
+            .head(self.url(path))
+            .basic_auth(&self.email, Some(&self.password))
+            .send()?;
+        if !response.status().is_success() {
+            return Err(BackendError::Status(response.status()));
+        }
+        Ok(integrity::server_digest(response.headers()))
+    }
+
+    /// Smart lists and the cache are generated by the server.
+    fn accepts_uploads(&self, collection: &str) -> bool {
+        !matches!(collection, "smart" | "cache")
+    }
+
+    fn collections(&self) -> &'static [&'static str] {
+        &COLLECTIONS
+    }
+}
+
+/// A plain directory laid out like `~/.kaya`.
+pub struct Directory {
+    root: PathBuf,
+}
+
+impl Directory {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Directory { root: root.into() }
+    }
+
+    /// The path for `segments`, refusing any that would leave the root.
+    fn path(&self, segments: &[&str]) -> Result<PathBuf, UnsafeName> {
+        segments
+            .iter()
+            .try_fold(self.root.clone(), |dir, s| sanitize::safe_join(&dir, s))
+    }
+}
+
+impl SyncBackend for Directory {
+    fn list(&self, dir: &[&str], _since: Option<&str>) -> Result<Listing, BackendError> {
+        let dir = self.path(dir)?;
+        let names = match fs::read_dir(&dir) {
+            Ok(entries) => entries
+                .filter_map(|e| e.ok())
+                .filter_map(|e| e.file_name().into_string().ok())
+                .filter(|n| !n.starts_with('.'))
+                .collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Default::default(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Listing {
+            names,
+            cursor: None,
+            delta: false,
+        })
+    }
+
+    fn get(&self, path: &[&str], dest: &Path) -> Result<Downloaded, BackendError> {
+        let src = File::open(self.path(path)?)?;
+        let dir = dest.parent().unwrap_or(Path::new("."));
+        fs::create_dir_all(dir)?;
+        let filename = dest
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        let size = if path.first().is_some_and(|c| store::is_immutable(c)) {
+            match store::write_immutable(dir, filename, src, u64::MAX) {
+                Ok(_) => fs::metadata(dest)?.len(),
+                Err(StoreError::Conflict(_)) => {
+                    return Err(DownloadError::Conflict(dest.display().to_string()).into())
+                }
+                Err(e) => return Err(store_error(e)),
+            }
+        } else {
+            store::write_streamed(dir, filename, src, u64::MAX).map_err(store_error)?
+        };
+        Ok(Downloaded {
+            size,
+            sha256: integrity::sha256_file(dest)?,
+            verified: false,
+        })
+    }
+
+    fn put(&self, path: &[&str], src: &Path, sha256: &str) -> Result<PutOutcome, BackendError> {
+        let dest = self.path(path)?;
+        let dir = dest.parent().unwrap_or(&self.root);
+        fs::create_dir_all(dir)?;
+        let filename = path.last().copied().unwrap_or_default();
+        match store::write_immutable(dir, filename, File::open(src)?, u64::MAX) {
+            Ok(WriteOutcome::Created) => Ok(PutOutcome::Stored),
+            Ok(WriteOutcome::Unchanged) => Ok(PutOutcome::Exists(Some(sha256.to_string()))),
+            Err(StoreError::Conflict(_)) => {
+                Ok(PutOutcome::Exists(Some(integrity::sha256_file(&dest)?)))
+            }
+            Err(e) => Err(store_error(e)),
+        }
+    }
+
+    fn digest(&self, path: &[&str]) -> Result<Option<String>, BackendError> {
+        let path = self.path(path)?;
+        if !path.is_file() {
+            return Ok(None);
+        }
+        Ok(Some(integrity::sha256_file(&path)?))
+    }
+
+    fn accepts_uploads(&self, _collection: &str) -> bool {
+        true
+    }
+
+    fn collections(&self) -> &'static [&'static str] {
+        &COLLECTIONS
+    }
+}
+
+/// Downloads `request` for the file at `path` into `dest`, without replacing an
+/// existing file if the collection is immutable. A body that does not match
+/// `expected_sha256`, when given, is discarded.
+pub(crate) fn download(
+    partials: &PartialStore,
+    client: &Client,
+    request: reqwest::blocking::Request,
+    path: &[&str],
+    dest: &Path,
+    bandwidth: &Arc<TokenBucket>,
+    expected_sha256: Option<&str>,
+) -> Result<Downloaded, DownloadError> {
+    if path.first().is_some_and(|c| store::is_immutable(c)) {
+        partials.download_immutable(client, request, dest, bandwidth, expected_sha256)
+    } else {
+        partials.download(client, request, dest, bandwidth, expected_sha256)
+    }
+}
+
+fn store_error(e: StoreError) -> BackendError {
+    match e {
+        StoreError::Io(e) => BackendError::Io(e),
+        e => BackendError::Io(io::Error::other(e.to_string())),
+    }
+}
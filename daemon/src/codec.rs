@@ -0,0 +1,170 @@
+//! The one filename codec shared by sync, the local HTTP API and disk writes.
+//!
+//! Three forms of a name exist:
+//!
+//! * **wire** -- a line in a server listing, percent-encoded once by the server
+//!   (`India%20Income%20Tax.pdf`).
+//! * **server name** -- the name the server actually stores: the wire form decoded
+//!   once. URLs are built from it with [`url_segment`], which encodes exactly once.
+//! * **canonical** -- the name on disk in `~/.kaya`: fully decoded
+//!   (`India Income Tax.pdf`). This is also what the extension stores in OPFS.
+//!
+//! Older daemons wrote wire names to disk and re-encoded them on upload, so the
+//! same anga can exist locally and on the server as `X Y.pdf`, `X%20Y.pdf` and
+//! `X%2520Y.pdf`. [`canonical`] maps all of them to `X Y.pdf`, and
+//! [`migrate_dir`] merges such duplicates on disk.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::sanitize::check_component;
+use crate::store::files_equal;
+
+// Guards against pathological inputs; real names are encoded at most a few times.
+const MAX_DECODE_ROUNDS: usize = 8;
+
+fn decode_once(name: &str) -> Option<String> {
+    if !name.contains('%') {
+        return None;
+    }
+    match urlencoding::decode(name) {
+        Ok(decoded) if decoded != name => Some(decoded.into_owned()),
+        _ => None,
+    }
+}
+
+/// The server's name for a listing line: the wire form decoded once.
+pub fn server_name(wire: &str) -> String {
+    decode_once(wire).unwrap_or_else(|| wire.to_string())
+}
+
+/// The canonical on-disk form of a name: percent-decoded until stable.
+pub fn canonical(name: &str) -> String {
+    let mut current = name.to_string();
+    for _ in 0..MAX_DECODE_ROUNDS {
+        match decode_once(&current) {
+            Some(next) => current = next,
+            None => break,
+        }
+    }
+    current
+}
+
+/// A single URL path segment for a server or canonical name, encoded exactly once.
+pub fn url_segment(name: &str) -> String {
+    urlencoding::encode(name).into_owned()
+}
+
+/// Maps each canonical name in a server listing to the server name to fetch it by.
+///
+/// When several listing lines collapse onto one canonical name (server-side
+/// duplicates left by double encoding), the first in sorted order wins, so the
+/// choice is stable between sync runs.
+pub fn canonical_listing(wire_names: impl IntoIterator<Item = String>) -> HashMap<String, String> {
+    let mut sorted: Vec<String> = wire_names.into_iter().collect();
+    sorted.sort();
+
+    let mut listing = HashMap::new();
+    for wire in sorted {
+        let server = server_name(&wire);
+        listing.entry(canonical(&server)).or_insert(server);
+    }
+    listing
+}
+
+/// What [`migrate_dir`] did to one directory.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// Non-canonical names renamed to their canonical form.
+    pub renamed: Vec<String>,
+    /// Non-canonical duplicates removed because the canonical file has identical content.
+    pub merged: Vec<String>,
+    /// Non-canonical names left alone because the canonical file differs, or the
+    /// canonical form is not a safe filename.
+    pub conflicts: Vec<String>,
+}
+
+/// Renames every non-canonical entry in `dir` to its canonical name, merging
+/// duplicates.
+///
+/// Files with identical content are merged by deleting the non-canonical copy.
+/// Directories (words) are merged by moving entries across recursively. Anything
+/// that would lose data is left in place and reported as a conflict.
+pub fn migrate_dir(dir: &Path) -> io::Result<MigrationReport> {
+    let mut report = MigrationReport::default();
+    if !dir.exists() {
+        return Ok(report);
+    }
+
+    let mut names: Vec<String> = fs::read_dir(dir)?
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().into_string().ok())
+        .filter(|n| !n.starts_with('.'))
+        .collect();
+    names.sort();
+
+    for name in names {
+        let target = canonical(&name);
+        if target == name {
+            continue;
+        }
+        if check_component(&target).is_err() {
+            report.conflicts.push(name);
+            continue;
+        }
+
+        let from = dir.join(&name);
+        let to = dir.join(&target);
+        if !to.exists() {
+            fs::rename(&from, &to)?;
+            report.renamed.push(name);
+        } else if from.is_dir() && to.is_dir() {
+            let inner = migrate_into(&from, &to)?;
+            if inner {
+                fs::remove_dir(&from)?;
+                report.merged.push(name);
+            } else {
+                report.conflicts.push(name);
+            }
+        } else if from.is_file() && to.is_file() && files_equal(&from, &to)? {
+            fs::remove_file(&from)?;
+            report.merged.push(name);
+        } else {
+            report.conflicts.push(name);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Moves the entries of `from` into `to`, canonicalising names on the way.
+/// Returns true if `from` ended up empty.
+fn migrate_into(from: &Path, to: &Path) -> io::Result<bool> {
+    let mut empty = true;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let name = match entry.file_name().into_string() {
+            Ok(n) => n,
+            Err(_) => {
+                empty = false;
+                continue;
+            }
+        };
+        let target = canonical(&name);
+        let dest = to.join(&target);
+        let src = entry.path();
+
+        if check_component(&target).is_err() || !src.is_file() {
+            empty = false;
+        } else if !dest.exists() {
+            fs::rename(&src, &dest)?;
+        } else if files_equal(&src, &dest)? {
+            fs::remove_file(&src)?;
+        } else {
+            empty = false;
+        }
+    }
+    Ok(empty)
+}
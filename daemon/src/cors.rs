@@ -0,0 +1,34 @@
+//! Origin allowlist for the local HTTP API.
+//!
+//! Browsers attach an `Origin` header to every cross-origin request, and web pages
+//! cannot forge it. The daemon only answers origins on its allowlist, so ordinary
+//! web pages are refused outright while paired extensions keep working.
+
+/// URL schemes used by extension pages across Chrome/Edge, Firefox and Safari.
+pub const EXTENSION_SCHEMES: &[&str] = &[
+    "chrome-extension://",
+    "moz-extension://",
+    "safari-web-extension://",
+];
+
+/// True if `origin` belongs to a browser extension rather than a web page.
+pub fn is_extension_origin(origin: &str) -> bool {
+    EXTENSION_SCHEMES.iter().any(|scheme| {
+        origin
+            .strip_prefix(scheme)
+            .is_some_and(|host| !host.is_empty() && !host.contains('/'))
+    })
+}
+
+/// True if `origin` matches an allowlist entry.
+///
+/// Entries are exact origins (`moz-extension://0c1f…`), or a scheme wildcard such as
+/// `moz-extension://*`, which matches any extension origin using that scheme.
+pub fn origin_allowed(origin: &str, allowlist: &[String]) -> bool {
+    allowlist.iter().any(|entry| match entry.strip_suffix('*') {
+        Some(scheme) if scheme.ends_with("://") => {
+            origin.starts_with(scheme) && is_extension_origin(origin)
+        }
+        _ => entry.trim_end_matches('/') == origin,
+    })
+}
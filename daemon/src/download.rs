@@ -0,0 +1,389 @@
+//! Atomic, resumable downloads.
+//!
+//! A download is streamed into `~/.kaya/.partial/{key}.partial`, next to a small
+//! JSON sidecar recording the URL and the server's validator (`ETag` or
+//! `Last-Modified`). When a connection drops partway through, the next attempt
+//! resumes from the end of the partial file with an HTTP `Range` request. The file
+//! is renamed into its destination only once its length matches what the server
+//! announced, and its SHA-256 matches the server's digest when one is given, so a
+//! truncated or corrupted file never appears in `~/.kaya`.
+//!
+//! [`PartialStore::download_immutable`] never replaces a file that appeared at the
+//! destination meanwhile (an anga or meta upload, say), as [`crate::store`] does.
+
+use reqwest::blocking::{Client, Request};
+use reqwest::header::{HeaderValue, CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE};
+use reqwest::StatusCode;
+use ring::digest::{digest, SHA256};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tempfile::TempPath;
+use thiserror::Error;
+
+use crate::integrity::{server_digest, sha256_file};
+use crate::store::files_equal;
+use crate::transfer::{ThrottledReader, TokenBucket};
+
+#[derive(Error, Debug)]
+pub enum DownloadError {
+    #[error("server responded with {0}")]
+    Status(StatusCode),
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("download incomplete: {received} of {expected} bytes")]
+    Incomplete { received: u64, expected: u64 },
+    #[error("received {received} bytes, more than the {expected} announced")]
+    Oversized { received: u64, expected: u64 },
+    #[error("unexpected Content-Range: {0:?}")]
+    ContentRange(String),
+    #[error("SHA-256 {actual} does not match the server's digest {expected}")]
+    DigestMismatch { expected: String, actual: String },
+    #[error("{0} already exists with different content")]
+    Conflict(String),
+}
+
+/// A completed download.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Downloaded {
+    pub size: u64,
+    /// Hex-encoded SHA-256 of the file as written.
+    pub sha256: String,
+    /// True if the hash was checked against a digest from the server.
+    pub verified: bool,
+}
+
+impl DownloadError {
+    /// Whether trying again may succeed. Partial progress is kept, so a retry
+    /// after a dropped connection resumes rather than restarts.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            DownloadError::Status(status) => {
+                status.is_server_error()
+                    || *status == StatusCode::REQUEST_TIMEOUT
+                    || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            DownloadError::Http(e) => {
+                e.is_timeout() || e.is_connect() || e.is_request() || e.is_body()
+            }
+            // Disk errors are not worth retrying; a body that broke off is
+            DownloadError::Io(e) => {
+                matches!(
+                    e.kind(),
+                    io::ErrorKind::TimedOut
+                        | io::ErrorKind::ConnectionReset
+                        | io::ErrorKind::ConnectionAborted
+                        | io::ErrorKind::UnexpectedEof
+                ) || e
+                    .get_ref()
+                    .is_some_and(|inner| inner.is::<reqwest::Error>())
+            }
+            // An incomplete body resumes; the others discarded the partial file
+            // and the next attempt starts over
+            DownloadError::Incomplete { .. }
+            | DownloadError::Oversized { .. }
+            | DownloadError::ContentRange(_)
+            | DownloadError::DigestMismatch { .. } => true,
+            DownloadError::Conflict(_) => false,
+        }
+    }
+}
+
+/// What is known about a partial download, stored beside it.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct PartialInfo {
+    url: String,
+    /// A strong `ETag` or `Last-Modified`, sent as `If-Range` when resuming so a
+    /// changed file restarts instead of being spliced.
+    validator: Option<String>,
+    /// Full length of the file, if the server announced it.
+    total: Option<u64>,
+    /// The server's SHA-256 of the whole file, hex-encoded, if it sent one.
+    #[serde(default)]
+    sha256: Option<String>,
+}
+
+/// The directory of in-progress downloads, `~/.kaya/.partial`.
+pub struct PartialStore {
+    dir: PathBuf,
+}
+
+impl PartialStore {
+    pub fn new(kaya_dir: &Path) -> Self {
+        PartialStore {
+            dir: kaya_dir.join(".partial"),
+        }
+    }
+
+    /// The partial file and sidecar for `dest`. Names are derived from a hash of
+    /// the destination so they stay short and cannot collide across collections.
+    fn paths(&self, dest: &Path) -> (PathBuf, PathBuf) {
+        let key: String = digest(&SHA256, dest.to_string_lossy().as_bytes())
+            .as_ref()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        (
+            self.dir.join(format!("{}.partial", key)),
+            self.dir.join(format!("{}.json", key)),
+        )
+    }
+
+    /// Bytes already downloaded towards `dest` from `url`, if a resumable partial
+    /// file exists.
+    pub fn progress(&self, url: &str, dest: &Path) -> Option<u64> {
+        let (partial, info) = self.paths(dest);
+        let info: PartialInfo = serde_json::from_str(&fs::read_to_string(info).ok()?).ok()?;
+        if info.url != url {
+            return None;
+        }
+        fs::metadata(partial).ok().map(|m| m.len())
+    }
+
+    /// Downloads `request` into `dest`, resuming an earlier partial download of
+    /// the same URL. Replaces any existing file. Every byte received is charged
+    /// to `bandwidth`.
+    ///
+    /// `expected_sha256` is a digest the caller already trusts, e.g. one a peer
+    /// signed; the file must match it whatever the response's headers say.
+    pub fn download(
+        &self,
+        client: &Client,
+        request: Request,
+        dest: &Path,
+        bandwidth: &Arc<TokenBucket>,
+        expected_sha256: Option<&str>,
+    ) -> Result<Downloaded, DownloadError> {
+        let downloaded = self.fetch(client, request, dest, bandwidth, expected_sha256)?;
+        let (partial_path, info_path) = self.paths(dest);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&partial_path, dest)?;
+        let _ = fs::remove_file(&info_path);
+        Ok(downloaded)
+    }
+
+    /// Like [`PartialStore::download`], but never replaces an existing file.
+    ///
+    /// A file already at `dest` with the same bytes is kept as it is; different
+    /// bytes are a [`DownloadError::Conflict`], and the download is discarded.
+    pub fn download_immutable(
+        &self,
+        client: &Client,
+        request: Request,
+        dest: &Path,
+        bandwidth: &Arc<TokenBucket>,
+        expected_sha256: Option<&str>,
+    ) -> Result<Downloaded, DownloadError> {
+        let downloaded = self.fetch(client, request, dest, bandwidth, expected_sha256)?;
+        let (partial_path, info_path) = self.paths(dest);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // persist_noclobber is atomic, so an upload landing at the same moment
+        // cannot be overwritten
+        let persisted = TempPath::from_path(&partial_path).persist_noclobber(dest);
+        let _ = fs::remove_file(&info_path);
+        match persisted {
+            Ok(()) => Ok(downloaded),
+            Err(e) if e.error.kind() == io::ErrorKind::AlreadyExists => {
+                if files_equal(&e.path, dest)? {
+                    Ok(downloaded)
+                } else {
+                    Err(DownloadError::Conflict(dest.display().to_string()))
+                }
+            }
+            Err(e) => Err(e.error.into()),
+        }
+    }
+
+    /// Downloads `request` into the partial file for `dest` and checks it, leaving
+    /// it there for the caller to move into place.
+    fn fetch(
+        &self,
+        client: &Client,
+        mut request: Request,
+        dest: &Path,
+        bandwidth: &Arc<TokenBucket>,
+        trusted_sha256: Option<&str>,
+    ) -> Result<Downloaded, DownloadError> {
+        fs::create_dir_all(&self.dir)?;
+        let (partial_path, info_path) = self.paths(dest);
+        let url = request.url().to_string();
+
+        let mut offset = 0;
+        let mut validator = None;
+        let mut expected_sha256 = None;
+        match self.progress(&url, dest) {
+            Some(len) if len > 0 => {
+                offset = len;
+                if let Some(info) = read_info(&info_path) {
+                    validator = info.validator;
+                    expected_sha256 = info.sha256;
+                }
+                request.headers_mut().insert(
+                    RANGE,
+                    HeaderValue::from_str(&format!("bytes={}-", offset))
+                        .expect("range header is ASCII"),
+                );
+                if let Some(v) = validator
+                    .as_deref()
+                    .and_then(|v| HeaderValue::from_str(v).ok())
+                {
+                    request.headers_mut().insert(IF_RANGE, v);
+                }
+            }
+            _ => self.discard(dest),
+        }
+
+        let response = client.execute(request)?;
+        let status = response.status();
+
+        let total = match status {
+            StatusCode::PARTIAL_CONTENT if offset > 0 => {
+                let range = header_str(&response, CONTENT_RANGE);
+                match parse_content_range(&range) {
+                    Some((start, total)) if start == offset => total,
+                    _ => {
+                        self.discard(dest);
+                        return Err(DownloadError::ContentRange(range));
+                    }
+                }
+            }
+            s if s.is_success() => {
+                // A full body: the server ignored the range, or the file changed
+                offset = 0;
+                validator = response_validator(&response);
+                expected_sha256 = None;
+                response.content_length()
+            }
+            StatusCode::RANGE_NOT_SATISFIABLE => {
+                self.discard(dest);
+                return Err(DownloadError::ContentRange(header_str(
+                    &response,
+                    CONTENT_RANGE,
+                )));
+            }
+            s => return Err(DownloadError::Status(s)),
+        };
+
+        if let Some(digest) = server_digest(response.headers()) {
+            expected_sha256 = Some(digest);
+        }
+        if let Some(trusted) = trusted_sha256 {
+            expected_sha256 = Some(trusted.to_string());
+        }
+        let info = PartialInfo {
+            url,
+            validator,
+            total,
+            sha256: expected_sha256.clone(),
+        };
+        fs::write(
+            &info_path,
+            serde_json::to_string(&info).map_err(io::Error::from)?,
+        )?;
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(offset == 0)
+            .append(offset > 0)
+            .open(&partial_path)?;
+        let copied = io::copy(
+            &mut ThrottledReader::new(response, bandwidth.clone()),
+            &mut file,
+        );
+        // Keep whatever arrived, even if the body broke off, so a retry resumes
+        file.flush()?;
+        file.sync_all()?;
+        copied?;
+
+        let received = file.metadata()?.len();
+        drop(file);
+        if let Some(expected) = total {
+            if received < expected {
+                return Err(DownloadError::Incomplete { received, expected });
+            }
+            if received > expected {
+                self.discard(dest);
+                return Err(DownloadError::Oversized { received, expected });
+            }
+        }
+
+        let sha256 = sha256_file(&partial_path)?;
+        if let Some(expected) = &expected_sha256 {
+            if *expected != sha256 {
+                self.discard(dest);
+                return Err(DownloadError::DigestMismatch {
+                    expected: expected.clone(),
+                    actual: sha256,
+                });
+            }
+        }
+
+        Ok(Downloaded {
+            size: received,
+            verified: expected_sha256.is_some(),
+            sha256,
+        })
+    }
+
+    /// Removes any partial download for `dest`.
+    pub fn discard(&self, dest: &Path) {
+        let (partial, info) = self.paths(dest);
+        let _ = fs::remove_file(partial);
+        let _ = fs::remove_file(info);
+    }
+}
+
+fn read_info(path: &Path) -> Option<PartialInfo> {
+    serde_json::from_reader(File::open(path).ok()?).ok()
+}
+
+fn header_str(response: &reqwest::blocking::Response, name: reqwest::header::HeaderName) -> String {
+    response
+        .headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+        .to_string()
+}
+
+/// A validator usable in `If-Range`: a strong `ETag`, else `Last-Modified`.
+fn response_validator(response: &reqwest::blocking::Response) -> Option<String> {
+    let headers = response.headers();
+    headers
+        .get(ETAG)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.starts_with("W/"))
+        .or_else(|| headers.get(LAST_MODIFIED).and_then(|v| v.to_str().ok()))
+        .map(str::to_string)
+}
+
+/// Parses `bytes start-end/total` into `(start, Some(total))`, or
+/// `(start, None)` when the total is `*`.
+pub fn parse_content_range(value: &str) -> Option<(u64, Option<u64>)> {
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    let (start, end): (u64, u64) = (start.trim().parse().ok()?, end.trim().parse().ok()?);
+    if end < start {
+        return None;
+    }
+    let total = match total.trim() {
+        "*" => None,
+        t => {
+            let t: u64 = t.parse().ok()?;
+            if t <= end {
+                return None;
+            }
+            Some(t)
+        }
+    };
+    Some((start, total))
+}
@@ -0,0 +1,117 @@
+//! Parsing of anga and meta filenames, and the MIME types their extensions stand
+//! for.
+//!
+//! Both follow `YYYY-mm-ddTHHMMSS[_SSSSSSSSS]-slug.ext` (ADR 0001, ADR 0003): a UTC
+//! timestamp, optional nanoseconds for sub-second collisions, then a slug and an
+//! extension.
+
+use chrono::{DateTime, NaiveDateTime, TimeDelta, Utc};
+use thiserror::Error;
+
+const TIMESTAMP_LEN: usize = "YYYY-mm-ddTHHMMSS".len();
+const NANOS_LEN: usize = 9;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum FilenameError {
+    #[error("filename does not start with a YYYY-mm-ddTHHMMSS timestamp")]
+    Timestamp,
+    #[error("nanosecond suffix must be exactly 9 digits")]
+    Nanoseconds,
+    #[error("timestamp must be followed by '-' and a slug")]
+    MissingSlug,
+    #[error("filename has no extension")]
+    MissingExtension,
+    #[error("filename contains a path separator or control character")]
+    InvalidCharacter,
+}
+
+/// The parts of a well-formed anga or meta filename, borrowed from the original.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AngaFilename<'a> {
+    /// When the anga was recorded, including the nanosecond suffix if present.
+    pub timestamp: DateTime<Utc>,
+    pub slug: &'a str,
+    pub extension: &'a str,
+}
+
+/// Parses `YYYY-mm-ddTHHMMSS[_SSSSSSSSS]-slug.ext`.
+///
+/// The slug is taken as-is, so both plain (`India Income Tax`) and percent-encoded
+/// (`India%20Income%20Tax`) forms are accepted.
+pub fn parse_anga_filename(name: &str) -> Result<AngaFilename<'_>, FilenameError> {
+    if name
+        .chars()
+        .any(|c| c == '/' || c == '\\' || c.is_control())
+    {
+        return Err(FilenameError::InvalidCharacter);
+    }
+
+    let prefix = name.get(..TIMESTAMP_LEN).ok_or(FilenameError::Timestamp)?;
+    if !prefix.is_ascii() {
+        return Err(FilenameError::Timestamp);
+    }
+    let naive = NaiveDateTime::parse_from_str(prefix, "%Y-%m-%dT%H%M%S")
+        .map_err(|_| FilenameError::Timestamp)?;
+    let mut timestamp = naive.and_utc();
+
+    let mut rest = &name[TIMESTAMP_LEN..];
+    if let Some(after) = rest.strip_prefix('_') {
+        let nanos = after.get(..NANOS_LEN).ok_or(FilenameError::Nanoseconds)?;
+        if !nanos.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(FilenameError::Nanoseconds);
+        }
+        timestamp += TimeDelta::nanoseconds(nanos.parse().map_err(|_| FilenameError::Nanoseconds)?);
+        rest = &after[NANOS_LEN..];
+    }
+
+    let rest = rest.strip_prefix('-').ok_or(FilenameError::MissingSlug)?;
+    let (slug, extension) = rest
+        .rsplit_once('.')
+        .ok_or(FilenameError::MissingExtension)?;
+    if slug.is_empty() {
+        return Err(FilenameError::MissingSlug);
+    }
+    if extension.is_empty() {
+        return Err(FilenameError::MissingExtension);
+    }
+
+    Ok(AngaFilename {
+        timestamp,
+        slug,
+        extension,
+    })
+}
+
+/// Validates a meta filename: an anga-style name with a `.toml` extension.
+pub fn parse_meta_filename(name: &str) -> Result<AngaFilename<'_>, FilenameError> {
+    let parsed = parse_anga_filename(name)?;
+    if !parsed.extension.eq_ignore_ascii_case("toml") {
+        return Err(FilenameError::MissingExtension);
+    }
+    Ok(parsed)
+}
+
+/// The UTC timestamp encoded in an anga or meta filename, if it is well-formed.
+pub fn anga_timestamp(name: &str) -> Option<DateTime<Utc>> {
+    parse_anga_filename(name).ok().map(|p| p.timestamp)
+}
+
+/// The MIME type a file's extension stands for, e.g. when uploading it.
+pub fn mime_type_for(filename: &str) -> String {
+    let ext = filename.rsplit('.').next().unwrap_or("").to_lowercase();
+    match ext.as_str() {
+        "md" => "text/markdown",
+        "url" | "txt" => "text/plain",
+        "json" => "application/json",
+        "toml" => "application/toml",
+        "pdf" => "application/pdf",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "html" | "htm" => "text/html",
+        _ => "application/octet-stream",
+    }
+    .to_string()
+}
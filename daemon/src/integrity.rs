@@ -0,0 +1,194 @@
+//! Content hashes for files in `~/.kaya`.
+//!
+//! Every file in anga, meta and words has its SHA-256 tracked in
+//! `~/.kaya/.hashes`, keyed by its path relative to `~/.kaya`. Entries remember
+//! the size and modification time they were computed from, so unchanged files are
+//! never re-read. Hashes are compared with the digests a server returns (`Repr-Digest`,
+//! `Digest`, or an `ETag` that is itself a SHA-256) after downloads and uploads,
+//! and in full by `savebutton-daemon verify`.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, ETAG};
+use ring::digest::{Context, SHA256};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tempfile::NamedTempFile;
+
+/// Hex-encoded SHA-256 of a file, read in chunks.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut context = Context::new(&SHA256);
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        context.update(&buf[..n]);
+    }
+    Ok(hex(context.finish().as_ref()))
+}
+
+/// Hex-encoded SHA-256 of `bytes`.
+pub fn sha256_bytes(bytes: &[u8]) -> String {
+    hex(ring::digest::digest(&SHA256, bytes).as_ref())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The server's SHA-256 of the full file, hex-encoded, if its headers carry one.
+///
+/// Accepts `Repr-Digest: sha-256=:<base64>:` (RFC 9530), `Digest: SHA-256=<base64>`
+/// (RFC 3230) and an `ETag` of 64 hex digits. These all describe the whole file,
+/// so they hold for `206` range responses too.
+pub fn server_digest(headers: &HeaderMap) -> Option<String> {
+    for name in ["repr-digest", "digest"] {
+        for value in headers.get_all(name) {
+            if let Some(digest) = value.to_str().ok().and_then(parse_sha256_field) {
+                return Some(digest);
+            }
+        }
+    }
+
+    let etag = headers.get(ETAG)?.to_str().ok()?;
+    let etag = etag.strip_prefix("W/").unwrap_or(etag).trim_matches('"');
+    (etag.len() == 64 && etag.bytes().all(|b| b.is_ascii_hexdigit()))
+        .then(|| etag.to_ascii_lowercase())
+}
+
+/// A `Repr-Digest` header value for a hex-encoded SHA-256, the inverse of
+/// [`parse_sha256_field`].
+pub fn repr_digest(sha256: &str) -> String {
+    let bytes: Vec<u8> = (0..sha256.len() / 2)
+        .filter_map(|i| u8::from_str_radix(sha256.get(2 * i..2 * i + 2)?, 16).ok())
+        .collect();
+    format!("sha-256=:{}:", BASE64.encode(bytes))
+}
+
+/// Finds the `sha-256` member of a digest header value and returns it as hex.
+pub fn parse_sha256_field(value: &str) -> Option<String> {
+    value.split(',').find_map(|member| {
+        let (algorithm, encoded) = member.trim().split_once('=')?;
+        if !algorithm.trim().eq_ignore_ascii_case("sha-256") {
+            return None;
+        }
+        let bytes = BASE64.decode(encoded.trim().trim_matches(':')).ok()?;
+        (bytes.len() == 32).then(|| hex(&bytes))
+    })
+}
+
+/// A tracked hash and the file state it was computed from.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct HashEntry {
+    sha256: String,
+    size: u64,
+    modified: SystemTime,
+}
+
+/// The persistent hash index, `~/.kaya/.hashes`.
+pub struct HashIndex {
+    root: PathBuf,
+    entries: BTreeMap<String, HashEntry>,
+}
+
+impl HashIndex {
+    /// Loads the index for `kaya_dir`. A missing or unreadable index is empty;
+    /// every hash in it can be recomputed.
+    pub fn load(kaya_dir: &Path) -> Self {
+        let entries = fs::read_to_string(kaya_dir.join(".hashes"))
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        HashIndex {
+            root: kaya_dir.to_path_buf(),
+            entries,
+        }
+    }
+
+    /// Writes the index back, dropping entries whose files no longer exist.
+    pub fn save(&mut self) -> io::Result<()> {
+        let root = self.root.clone();
+        self.entries.retain(|rel, _| root.join(rel).is_file());
+
+        fs::create_dir_all(&self.root)?;
+        let json = serde_json::to_string_pretty(&self.entries)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut temp = NamedTempFile::new_in(&self.root)?;
+        temp.write_all(json.as_bytes())?;
+        temp.persist(self.root.join(".hashes"))
+            .map_err(|e| e.error)?;
+        Ok(())
+    }
+
+    /// The SHA-256 of `rel` (relative to `~/.kaya`), recomputed only if the file's
+    /// size or modification time changed since it was last hashed.
+    pub fn hash(&mut self, rel: &str) -> io::Result<String> {
+        if let Some(sha256) = self.fresh(rel) {
+            return Ok(sha256);
+        }
+        let sha256 = sha256_file(&self.root.join(rel))?;
+        self.record(rel, &sha256)?;
+        Ok(sha256)
+    }
+
+    /// The tracked hash of `rel`, if the file is unchanged since it was hashed.
+    /// Lets callers that share the index hash stale files without holding it.
+    pub fn fresh(&self, rel: &str) -> Option<String> {
+        let entry = self.entries.get(rel)?;
+        let meta = fs::metadata(self.root.join(rel)).ok()?;
+        (entry.size == meta.len() && meta.modified().ok()? == entry.modified)
+            .then(|| entry.sha256.clone())
+    }
+
+    /// Records an already-computed hash for `rel`, e.g. one checked during a
+    /// download.
+    pub fn record(&mut self, rel: &str, sha256: &str) -> io::Result<()> {
+        let meta = fs::metadata(self.root.join(rel))?;
+        self.entries.insert(
+            rel.to_string(),
+            HashEntry {
+                sha256: sha256.to_string(),
+                size: meta.len(),
+                modified: meta.modified()?,
+            },
+        );
+        Ok(())
+    }
+}
+
+/// A file whose local hash differs from the server's digest.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Mismatch {
+    /// Path relative to `~/.kaya`, e.g. `anga/2026-01-27T171207-note.md`.
+    pub path: String,
+    pub local: String,
+    pub server: String,
+}
+
+/// The result of `savebutton-daemon verify`, also saved to `~/.kaya/.verify-report`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct VerifyReport {
+    pub time: DateTime<Utc>,
+    /// Files hashed locally.
+    pub checked: usize,
+    /// Files whose hash matched a server digest.
+    pub verified: usize,
+    /// Files the server gave no digest for, or that could not be checked.
+    pub unverified: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl VerifyReport {
+    pub fn save(&self, kaya_dir: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(kaya_dir.join(".verify-report"), json)
+    }
+}
@@ -0,0 +1,226 @@
+//! The sync-state journal, `~/.kaya/.sync-state`.
+//!
+//! Records what each sync run transferred, when, and with which hash, plus enough
+//! state about each collection to recognise when nothing has changed since the
+//! last clean run:
+//!
+//! * anga and meta remember a fingerprint of the combined server listing and the
+//!   local directory's modification time. If both match, the local `read_dir` and
+//!   diff are skipped.
+//! * nested collections (`words`, `cache`) remember which subdirectories were
+//!   fully synced, with a fingerprint of the server's listing of each and its
+//!   local modification time, so untouched ones are not re-read and diffed every
+//!   cycle. Each is still listed, incrementally where the server can, so files
+//!   the server gains in a synced directory are found.
+//!
+//! It also keeps each server listing and the cursor it was fetched up to, so
+//! servers with incremental listings (see `listing`) only send what is new.
+//!
+//! A full rescan (`savebutton-daemon sync --full`) ignores the journal and rebuilds
+//! it.
+
+use chrono::{DateTime, Utc};
+use ring::digest::{Context, SHA256};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tempfile::NamedTempFile;
+
+/// A fingerprint of a set of names, independent of their order.
+pub fn fingerprint<'a>(names: impl IntoIterator<Item = &'a String>) -> String {
+    let mut sorted: Vec<&String> = names.into_iter().collect();
+    sorted.sort();
+    let mut context = Context::new(&SHA256);
+    for name in sorted {
+        context.update(name.as_bytes());
+        context.update(b"\n");
+    }
+    context
+        .finish()
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// The state of a collection after its last clean sync.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct CollectionState {
+    /// [`fingerprint`] of every name present on both sides after the sync.
+    names: String,
+    /// Modification time of the local directory after the sync.
+    local_modified: Option<SystemTime>,
+    synced: DateTime<Utc>,
+}
+
+/// The state of a nested directory after its last full sync.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct DirState {
+    /// [`fingerprint`] of every name present on both sides after the sync.
+    names: String,
+    local_modified: SystemTime,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Download,
+    Upload,
+}
+
+/// What is known to be on the server for one listing.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ServerListing {
+    /// Cursor to resume an incremental listing from; `None` for servers that only
+    /// list in full.
+    pub cursor: Option<String>,
+    /// Names as the server spells them.
+    pub names: BTreeSet<String>,
+}
+
+/// The last transfer of one file.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct FileRecord {
+    direction: Direction,
+    time: DateTime<Utc>,
+    sha256: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct JournalState {
+    #[serde(default)]
+    collections: BTreeMap<String, CollectionState>,
+    /// Nested directories, e.g. `words/{anga}`, that were fully synced.
+    #[serde(default)]
+    dirs: BTreeMap<String, DirState>,
+    /// Keyed by listing path, e.g. `anga` or `words`.
+    #[serde(default)]
+    listings: BTreeMap<String, ServerListing>,
+    /// Keyed by path relative to `~/.kaya`.
+    #[serde(default)]
+    files: BTreeMap<String, FileRecord>,
+}
+
+pub struct SyncJournal {
+    path: PathBuf,
+    state: JournalState,
+}
+
+impl SyncJournal {
+    /// Loads the journal from `kaya_dir`. A missing or unreadable journal is empty,
+    /// which only costs one full rescan.
+    pub fn load(kaya_dir: &Path) -> Self {
+        let path = kaya_dir.join(".sync-state");
+        let state = fs::read_to_string(&path)
+            .ok()
+            .and_then(|s| serde_json::from_str(&s).ok())
+            .unwrap_or_default();
+        SyncJournal { path, state }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let dir = self.path.parent().unwrap_or(Path::new("."));
+        fs::create_dir_all(dir)?;
+        let json = serde_json::to_string_pretty(&self.state)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut temp = NamedTempFile::new_in(dir)?;
+        temp.write_all(json.as_bytes())?;
+        temp.persist(&self.path).map_err(|e| e.error)?;
+        Ok(())
+    }
+
+    /// True if `collection` was last synced cleanly with the same server names and
+    /// an untouched local directory.
+    pub fn is_unchanged(
+        &self,
+        collection: &str,
+        names: &str,
+        local_modified: Option<SystemTime>,
+    ) -> bool {
+        self.state.collections.get(collection).is_some_and(|c| {
+            c.names == names && local_modified.is_some() && c.local_modified == local_modified
+        })
+    }
+
+    /// Records a clean sync of `collection`: every name in `names` is now on both
+    /// sides.
+    pub fn mark_synced(
+        &mut self,
+        collection: &str,
+        names: String,
+        local_modified: Option<SystemTime>,
+        now: DateTime<Utc>,
+    ) {
+        self.state.collections.insert(
+            collection.to_string(),
+            CollectionState {
+                names,
+                local_modified,
+                synced: now,
+            },
+        );
+    }
+
+    pub fn server_listing(&self, collection: &str) -> Option<&ServerListing> {
+        self.state.listings.get(collection)
+    }
+
+    /// Replaces what is known to be on the server for `collection`.
+    pub fn set_server_listing(&mut self, collection: &str, listing: ServerListing) {
+        self.state.listings.insert(collection.to_string(), listing);
+    }
+
+    /// Notes a name now on the server, e.g. after an upload, so the next
+    /// incremental listing need not return it.
+    pub fn add_server_name(&mut self, collection: &str, name: &str) {
+        if let Some(listing) = self.state.listings.get_mut(collection) {
+            listing.names.insert(name.to_string());
+        }
+    }
+
+    /// Whether `dir` (relative to `~/.kaya`, e.g. `words/{anga}`) was fully synced
+    /// with the server listing the same `names`, and has not been touched locally
+    /// since.
+    pub fn is_clean(&self, dir: &str, names: &str, local_modified: Option<SystemTime>) -> bool {
+        self.state
+            .dirs
+            .get(dir)
+            .is_some_and(|d| d.names == names && local_modified == Some(d.local_modified))
+    }
+
+    /// Records a full sync of `dir`, after which both sides held `names` and the
+    /// local directory had `local_modified`.
+    pub fn mark_clean(&mut self, dir: &str, names: String, local_modified: Option<SystemTime>) {
+        match local_modified {
+            Some(local_modified) => self.state.dirs.insert(
+                dir.to_string(),
+                DirState {
+                    names,
+                    local_modified,
+                },
+            ),
+            None => self.state.dirs.remove(dir),
+        };
+    }
+
+    /// Records a completed transfer of `rel` (relative to `~/.kaya`).
+    pub fn record_transfer(
+        &mut self,
+        rel: &str,
+        direction: Direction,
+        sha256: &str,
+        now: DateTime<Utc>,
+    ) {
+        self.state.files.insert(
+            rel.to_string(),
+            FileRecord {
+                direction,
+                time: now,
+                sha256: sha256.to_string(),
+            },
+        );
+    }
+}
@@ -1,3 +1,23 @@
+pub mod auth;
+pub mod backend;
+pub mod codec;
+pub mod cors;
+pub mod download;
+pub mod filename;
+pub mod integrity;
+pub mod journal;
+pub mod limits;
+pub mod listing;
+pub mod multipart;
+pub mod peer;
+pub mod query;
+pub mod retry;
+pub mod sanitize;
+pub mod search;
+pub mod store;
+pub mod text;
+pub mod transfer;
+
 use std::collections::HashSet;
 
 pub fn parse_server_file_listing(body: &str) -> HashSet<String> {
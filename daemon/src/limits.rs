@@ -0,0 +1,83 @@
+//! Resource limits for the local HTTP server.
+
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A counting limit on concurrent operations that fails fast instead of queueing.
+///
+/// Used to cap in-flight uploads, so large bodies can never tie up every worker
+/// thread and starve cheap requests like `GET /health`.
+#[derive(Clone, Debug)]
+pub struct InFlightLimit {
+    max: usize,
+    current: Arc<AtomicUsize>,
+}
+
+/// Releases its slot in an [`InFlightLimit`] when dropped.
+#[derive(Debug)]
+pub struct InFlightGuard {
+    current: Arc<AtomicUsize>,
+}
+
+impl InFlightLimit {
+    pub fn new(max: usize) -> Self {
+        InFlightLimit {
+            max,
+            current: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Takes a slot, or returns `None` if `max` operations are already in flight.
+    pub fn try_acquire(&self) -> Option<InFlightGuard> {
+        self.current
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < self.max).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| InFlightGuard {
+                current: self.current.clone(),
+            })
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.current.load(Ordering::Acquire)
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.current.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// A reader that fails with `ErrorKind::TimedOut` once its deadline has passed.
+///
+/// The deadline is checked before every read, so a client trickling a body in
+/// slowly is cut off even though each individual read succeeds.
+pub struct DeadlineReader<R> {
+    inner: R,
+    deadline: Instant,
+}
+
+impl<R: Read> DeadlineReader<R> {
+    pub fn new(inner: R, timeout: Duration) -> Self {
+        DeadlineReader {
+            inner,
+            deadline: Instant::now() + timeout,
+        }
+    }
+}
+
+impl<R: Read> Read for DeadlineReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if Instant::now() >= self.deadline {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "request body not received before deadline",
+            ));
+        }
+        self.inner.read(buf)
+    }
+}
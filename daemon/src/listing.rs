@@ -0,0 +1,123 @@
+//! Server listings, full or incremental.
+//!
+//! A listing request carries `?limit=N` and, once the daemon holds a cursor,
+//! `since=<cursor>`. A server that supports incremental listings answers with a
+//! page of names and these headers:
+//!
+//! * `Kaya-Cursor` -- an opaque cursor positioned after this page. The server
+//!   chooses what "newer" means; for timestamp-ordered names it is simply the last
+//!   name returned.
+//! * `Kaya-More: true` -- further pages follow; request again with the new cursor.
+//!
+//! A server without the capability ignores the query and returns its whole
+//! listing without a `Kaya-Cursor`, which is treated as a full listing.
+
+use reqwest::blocking::Client;
+use reqwest::StatusCode;
+use std::collections::HashSet;
+use thiserror::Error;
+
+use crate::parse_server_file_listing;
+
+pub const CURSOR_HEADER: &str = "kaya-cursor";
+pub const MORE_HEADER: &str = "kaya-more";
+/// Names requested per page from servers that paginate.
+pub const PAGE_SIZE: usize = 1000;
+
+#[derive(Error, Debug)]
+pub enum ListingError {
+    #[error("server responded with {0}")]
+    Status(StatusCode),
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("server repeated listing cursor {0:?}")]
+    StalledCursor(String),
+}
+
+impl ListingError {
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ListingError::Status(status) => {
+                status.is_server_error()
+                    || *status == StatusCode::REQUEST_TIMEOUT
+                    || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            ListingError::Http(e) => {
+                e.is_timeout() || e.is_connect() || e.is_request() || e.is_body()
+            }
+            ListingError::StalledCursor(_) => false,
+        }
+    }
+}
+
+/// The names a listing returned, as the server spells them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listing {
+    pub names: HashSet<String>,
+    /// Cursor to send as `since` next time, if the server listed incrementally.
+    pub cursor: Option<String>,
+    /// True if `names` holds only entries newer than the `since` cursor sent,
+    /// rather than everything on the server.
+    pub delta: bool,
+}
+
+/// Lists `url`, following pages until the server has no more. `since` asks for
+/// only the entries after an earlier cursor.
+pub fn fetch(
+    client: &Client,
+    url: &str,
+    email: &str,
+    password: &str,
+    since: Option<&str>,
+    page_size: usize,
+) -> Result<Listing, ListingError> {
+    let mut names = HashSet::new();
+    let mut cursor = since.map(str::to_string);
+    let limit = page_size.max(1).to_string();
+
+    loop {
+        let mut query = vec![("limit", limit.as_str())];
+        if let Some(c) = &cursor {
+            query.push(("since", c));
+        }
+        let response = client
+            .get(url)
+            .query(&query)
+            .basic_auth(email, Some(password))
+            .send()?;
+        if !response.status().is_success() {
+            return Err(ListingError::Status(response.status()));
+        }
+
+        let headers = response.headers();
+        let next = headers
+            .get(CURSOR_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let more = headers
+            .get(MORE_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.trim().eq_ignore_ascii_case("true"));
+        names.extend(parse_server_file_listing(&response.text()?));
+
+        let Some(next) = next else {
+            // No incremental support: this was the whole listing
+            return Ok(Listing {
+                names,
+                cursor: None,
+                delta: false,
+            });
+        };
+        if more && cursor.as_deref() == Some(next.as_str()) {
+            return Err(ListingError::StalledCursor(next));
+        }
+        cursor = Some(next);
+        if !more {
+            return Ok(Listing {
+                names,
+                cursor,
+                delta: since.is_some(),
+            });
+        }
+    }
+}
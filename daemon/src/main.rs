@@ -1,24 +1,59 @@
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use chrono::Utc;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use rand::RngCore;
 use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM};
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs;
-use std::io;
-use std::path::PathBuf;
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 use thiserror::Error;
 use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
 
-use savebutton_daemon::parse_server_file_listing;
+use savebutton_daemon::auth::{self, Login, PasswordGuard};
+use savebutton_daemon::backend::{BackendError, Directory, KayaServer, PutOutcome, SyncBackend};
+use savebutton_daemon::codec;
+use savebutton_daemon::cors;
+use savebutton_daemon::download::{DownloadError, Downloaded};
+use savebutton_daemon::filename::{parse_anga_filename, parse_meta_filename, FilenameError};
+use savebutton_daemon::integrity::{self, HashIndex, Mismatch, VerifyReport};
+use savebutton_daemon::journal::{self, Direction, ServerListing, SyncJournal};
+use savebutton_daemon::limits::{DeadlineReader, InFlightLimit};
+use savebutton_daemon::multipart;
+use savebutton_daemon::peer::{self, NonceCache, Peer, PeerHeaders};
+use savebutton_daemon::query::{Query, QueryError};
+use savebutton_daemon::retry::{FailureLog, RetryPolicy};
+use savebutton_daemon::sanitize::{self, Quarantine, UnsafeName};
+use savebutton_daemon::search::SearchIndex;
+use savebutton_daemon::store::{self, StoreError, WriteOutcome};
+use savebutton_daemon::transfer::{map_parallel, TokenBucket};
 
 const DEFAULT_PORT: u16 = 21420;
+const DEFAULT_WORKERS: usize = 8;
+const DEFAULT_MAX_UPLOADS: usize = 2;
+const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 300;
+const DEFAULT_MAX_BODY_MB: u64 = 512;
+const DEFAULT_SYNC_TRANSFERS: usize = 4;
+/// Worker threads serving each remote listener (`--peer-listen`, `--api-listen`).
+const LISTENER_WORKERS: usize = 2;
+const BEACON_INTERVAL: Duration = Duration::from_secs(30);
+/// How long a discovered peer is synced with after its last beacon.
+const DISCOVERY_EXPIRY: Duration = Duration::from_secs(300);
 const NONCE_LEN: usize = 12;
+/// Retries for one file transfer within a sync run; see `retry` for the
+/// cross-run cooldown that follows.
+const SYNC_RETRY: RetryPolicy = RetryPolicy {
+    max_attempts: 4,
+    base_delay: Duration::from_millis(500),
+    max_delay: Duration::from_secs(8),
+};
 const KEY_LEN: usize = 32;
 
 #[derive(Parser)]
@@ -28,6 +63,137 @@ struct Cli {
     /// Port to listen on
     #[arg(long, default_value_t = DEFAULT_PORT)]
     port: u16,
+
+    /// Directory holding the collections and daemon state, instead of ~/.kaya
+    #[arg(long, global = true, value_name = "DIR")]
+    root: Option<PathBuf>,
+
+    /// Address to serve paired daemons on, e.g. 0.0.0.0:21421; off by default
+    #[arg(long, value_name = "ADDR")]
+    peer_listen: Option<String>,
+
+    /// Find paired daemons on the LAN with UDP broadcasts
+    #[arg(long)]
+    discover: bool,
+
+    /// Address to serve the Kaya Server API on, e.g. 0.0.0.0:21423, so the
+    /// extension or another daemon can sync with this one; off by default
+    #[arg(long, value_name = "ADDR")]
+    api_listen: Option<String>,
+
+    /// Number of worker threads serving HTTP requests
+    #[arg(long, default_value_t = DEFAULT_WORKERS)]
+    workers: usize,
+
+    /// Maximum number of uploads processed at once; further uploads get 503
+    #[arg(long, default_value_t = DEFAULT_MAX_UPLOADS)]
+    max_uploads: usize,
+
+    /// Seconds a client has to deliver a request body
+    #[arg(long, default_value_t = DEFAULT_REQUEST_TIMEOUT_SECS)]
+    request_timeout: u64,
+
+    /// Largest request body accepted, in MiB; larger uploads get 413
+    #[arg(long, default_value_t = DEFAULT_MAX_BODY_MB)]
+    max_body_mb: u64,
+
+    /// Maximum concurrent sync transfers across anga, meta and words
+    #[arg(long, default_value_t = DEFAULT_SYNC_TRANSFERS)]
+    sync_transfers: usize,
+
+    /// Bandwidth cap shared by all sync transfers, in KiB/s; 0 means unlimited
+    #[arg(long, default_value_t = 0)]
+    sync_bandwidth_kb: u64,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Issue a one-time code for pairing the browser extension with this daemon
+    Pair,
+    /// Manage bearer tokens held by paired extensions
+    Tokens {
+        #[command(subcommand)]
+        action: TokenAction,
+    },
+    /// Manage the extension origins allowed to call the local HTTP API
+    Origins {
+        #[command(subcommand)]
+        action: OriginAction,
+    },
+    /// List server-supplied names that sync refused to write
+    Quarantine,
+    /// List files whose last sync attempt failed, and when they will be retried
+    Failures,
+    /// Rename percent-encoded filenames in ~/.kaya to their canonical form,
+    /// merging duplicates (runs automatically once on first start)
+    MigrateFilenames,
+    /// Hash every file in ~/.kaya and compare it with the server's digest
+    Verify,
+    /// Set the account the Kaya Server API (--api-listen) accepts; reads the
+    /// password from stdin
+    ApiUser { email: String },
+    /// Manage daemons to sync with directly over the LAN
+    Peers {
+        #[command(subcommand)]
+        action: PeerAction,
+    },
+    /// Sync with the server and paired daemons once and exit
+    Sync {
+        /// Ignore the sync-state journal and rescan every collection and words
+        /// directory, e.g. after files were changed or removed behind the daemon
+        #[arg(long)]
+        full: bool,
+        /// Sync with another ~/.kaya-shaped directory, e.g. on a USB stick,
+        /// instead of the server
+        #[arg(long, value_name = "DIR")]
+        to: Option<PathBuf>,
+    },
+    /// Search words, notes and meta, e.g. `tag:podcast after:2026-01-01 "exact
+    /// phrase" -excluded`
+    Search {
+        #[arg(required = true)]
+        query: Vec<String>,
+        /// Most results to show
+        #[arg(long, default_value_t = DEFAULT_SEARCH_LIMIT)]
+        limit: usize,
+        /// Print results as JSON, as `GET /search` returns them
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum TokenAction {
+    /// List paired tokens
+    List,
+    /// Revoke a paired token by id
+    Revoke { id: String },
+}
+
+#[derive(Subcommand)]
+enum PeerAction {
+    /// List configured peers
+    List,
+    /// Sync with the daemon at a URL, e.g. `http://192.168.1.20:21421`
+    Add { url: String },
+    /// Stop syncing with a peer
+    Remove { url: String },
+    /// Print the secret shared by paired daemons, generating one if needed, or set
+    /// it to the one printed on another daemon
+    Secret { secret: Option<String> },
+}
+
+#[derive(Subcommand)]
+enum OriginAction {
+    /// List allowed origins
+    List,
+    /// Allow an origin, e.g. `chrome-extension://<id>` or `moz-extension://*`
+    Add { origin: String },
+    /// Remove an allowed origin
+    Remove { origin: String },
 }
 
 fn setup_logging() {
@@ -74,6 +240,22 @@ enum KayaError {
     Config(String),
     #[error("Encryption error: {0}")]
     Encryption(String),
+    #[error("{} does not match the server (local {}, server {})", .0.path, .0.local, .0.server)]
+    DigestMismatch(Mismatch),
+    #[error("{0} file(s) do not match the server; see ~/.kaya/.verify-report")]
+    Integrity(usize),
+    #[error("{0} file(s) or collection(s) did not sync; see ~/.kaya/daemon-log")]
+    Incomplete(usize),
+    #[error("Download error: {0}")]
+    Download(#[from] DownloadError),
+    #[error("Sync error: {0}")]
+    Backend(#[from] BackendError),
+    #[error("Store error: {0}")]
+    Store(#[from] StoreError),
+    #[error("Unsafe name: {0}")]
+    UnsafeName(#[from] UnsafeName),
+    #[error("Invalid query: {0}")]
+    Query(#[from] QueryError),
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
@@ -82,9 +264,28 @@ struct Config {
     email: Option<String>,
     encrypted_password: Option<String>,
     encryption_key: Option<String>,
+    /// Extension origins allowed to call the local HTTP API. Pairing adds the
+    /// pairing extension's origin automatically.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    allowed_origins: Vec<String>,
+    /// Secret shared with paired daemons; peer requests are signed with it.
+    peer_secret: Option<String>,
+    /// Base URLs of daemons to sync with directly.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    peers: Vec<String>,
+    /// Account served by the Kaya-Server-compatible API (`--api-listen`).
+    api_email: Option<String>,
+    /// [`auth::hash_password`] of that account's password.
+    api_password_hash: Option<String>,
 }
 
+/// Set from `--root` before anything touches the disk.
+static KAYA_ROOT: OnceLock<PathBuf> = OnceLock::new();
+
 fn get_kaya_dir() -> PathBuf {
+    if let Some(root) = KAYA_ROOT.get() {
+        return root.clone();
+    }
     dirs::home_dir()
         .expect("Could not find home directory")
         .join(".kaya")
@@ -102,14 +303,44 @@ fn get_words_dir() -> PathBuf {
     get_kaya_dir().join("words")
 }
 
+fn get_cache_dir() -> PathBuf {
+    get_kaya_dir().join("cache")
+}
+
+fn get_smart_dir() -> PathBuf {
+    get_kaya_dir().join("smart")
+}
+
+/// Collections holding one directory per anga: `words/{anga}/{file}` and
+/// `cache/{bookmark}/{file}`.
+const NESTED_COLLECTIONS: [&str; 2] = ["words", "cache"];
+
+/// The local directory for a collection named in a route or server URL.
+fn collection_dir(collection: &str) -> PathBuf {
+    match collection {
+        "anga" => get_anga_dir(),
+        "meta" => get_meta_dir(),
+        "words" => get_words_dir(),
+        "cache" => get_cache_dir(),
+        "smart" => get_smart_dir(),
+        _ => unreachable!("unknown collection {}", collection),
+    }
+}
+
 fn get_config_path() -> PathBuf {
     get_kaya_dir().join(".config")
 }
 
+fn get_migration_marker_path() -> PathBuf {
+    get_kaya_dir().join(".canonical-filenames")
+}
+
 fn ensure_directories() -> io::Result<()> {
     fs::create_dir_all(get_anga_dir())?;
     fs::create_dir_all(get_meta_dir())?;
     fs::create_dir_all(get_words_dir())?;
+    fs::create_dir_all(get_cache_dir())?;
+    fs::create_dir_all(get_smart_dir())?;
     Ok(())
 }
 
@@ -178,24 +409,206 @@ fn save_config(config: &Config) -> Result<(), KayaError> {
     let content = toml::to_string(config)
         .map_err(|e| KayaError::Config(format!("Failed to serialize: {}", e)))?;
     fs::write(get_config_path(), content)?;
+    *ALLOWED_ORIGINS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner) = None;
+    Ok(())
+}
+
+/// When the config file was last written, as far as the filesystem tells.
+type ConfigStamp = Option<(Option<SystemTime>, u64)>;
+
+/// The origin allowlist as last read, with the config's stamp at the time.
+static ALLOWED_ORIGINS: Mutex<Option<(ConfigStamp, Arc<Vec<String>>)>> = Mutex::new(None);
+
+/// Origins allowed to call the local HTTP API. The config is only re-read when
+/// it changes, since every response needs the allowlist.
+fn allowed_origins() -> Arc<Vec<String>> {
+    let stamp = fs::metadata(get_config_path())
+        .ok()
+        .map(|m| (m.modified().ok(), m.len()));
+    let mut cached = ALLOWED_ORIGINS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    if let Some((read_at, origins)) = cached.as_ref() {
+        if *read_at == stamp {
+            return origins.clone();
+        }
+    }
+    match load_config() {
+        Ok(config) => {
+            let origins = Arc::new(config.allowed_origins);
+            *cached = Some((stamp, origins.clone()));
+            origins
+        }
+        Err(e) => {
+            log::error!("Failed to load config for origin check: {}", e);
+            Arc::default()
+        }
+    }
+}
+
+/// Merges double-encoded and decoded duplicates left by older daemons into their
+/// canonical names (see `codec`), then records that the migration has run.
+fn migrate_filenames() -> Result<(), KayaError> {
+    for collection in ["anga", "meta", "words", "cache", "smart"] {
+        let report = codec::migrate_dir(&collection_dir(collection))?;
+        for name in &report.renamed {
+            log::info!(
+                "Renamed {} {:?} to {:?}",
+                collection,
+                name,
+                codec::canonical(name)
+            );
+        }
+        for name in &report.merged {
+            log::info!("Merged duplicate {} {:?}", collection, name);
+        }
+        for name in &report.conflicts {
+            log::warn!(
+                "Left {} {:?} in place: {:?} already exists with different content",
+                collection,
+                name,
+                codec::canonical(name)
+            );
+        }
+    }
+
+    fs::write(get_migration_marker_path(), Utc::now().to_rfc3339())?;
     Ok(())
 }
 
 // ---------------------------------------------------------------------------
-// Server sync logic
+// Integrity verification
 // ---------------------------------------------------------------------------
 
-fn sync_with_server() -> Result<(), KayaError> {
-    let config = load_config()?;
+/// Every synced file, as a path relative to `~/.kaya`: `anga/{f}`, `meta/{f}`,
+/// `smart/{f}`, `words/{anga}/{f}` and `cache/{bookmark}/{f}`.
+fn tracked_files() -> io::Result<Vec<String>> {
+    fn names(dir: &Path, files: bool) -> io::Result<Vec<String>> {
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut names: Vec<String> = fs::read_dir(dir)?
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_file() == files)
+            .filter_map(|e| e.file_name().into_string().ok())
+            .filter(|n| !n.starts_with('.'))
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    let mut files = Vec::new();
+    for collection in ["anga", "meta", "smart"] {
+        for name in names(&collection_dir(collection), true)? {
+            files.push(format!("{}/{}", collection, name));
+        }
+    }
+    for collection in NESTED_COLLECTIONS {
+        let dir = collection_dir(collection);
+        for parent in names(&dir, false)? {
+            for name in names(&dir.join(&parent), true)? {
+                files.push(format!("{}/{}/{}", collection, parent, name));
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Hashes every tracked file and compares it with the digest the server reports
+/// for it, saving the result to `~/.kaya/.verify-report`.
+fn verify() -> Result<VerifyReport, KayaError> {
+    let kaya_dir = get_kaya_dir();
+    let mut hashes = HashIndex::load(&kaya_dir);
+    let mut local = Vec::new();
+    for rel in tracked_files()? {
+        let sha256 = hashes.hash(&rel)?;
+        local.push((rel, sha256));
+    }
+    hashes.save()?;
+
+    let server_digests = match server_credentials(&load_config()?)? {
+        Some((server, email, password)) => {
+            let engine = SyncEngine::new(DEFAULT_SYNC_TRANSFERS, 0)?;
+            let backend = engine.kaya_server(&server, &email, &password);
+            map_parallel(&local, engine.transfers, |(rel, _)| {
+                let path: Vec<&str> = rel.split('/').collect();
+                backend.digest(&path).ok().flatten()
+            })
+        }
+        None => vec![None; local.len()],
+    };
 
-    let server = match config.server {
-        Some(s) => s,
-        None => return Ok(()),
+    let mut report = VerifyReport {
+        time: Utc::now(),
+        checked: local.len(),
+        verified: 0,
+        unverified: 0,
+        mismatches: Vec::new(),
     };
+    for ((rel, sha256), server) in local.into_iter().zip(server_digests) {
+        match server {
+            Some(server) if server == sha256 => report.verified += 1,
+            Some(server) => report.mismatches.push(Mismatch {
+                path: rel,
+                local: sha256,
+                server,
+            }),
+            None => report.unverified += 1,
+        }
+    }
+    report.save(&kaya_dir)?;
+    Ok(report)
+}
+
+// ---------------------------------------------------------------------------
+// Server sync logic
+// ---------------------------------------------------------------------------
+
+/// State shared by every sync run. It is built once at startup, so the pooled
+/// HTTP connections are reused across runs as well as within them.
+struct SyncEngine {
+    client: reqwest::blocking::Client,
+    /// Maximum number of file transfers in flight at once.
+    transfers: usize,
+    /// Global bandwidth cap shared by every download and upload.
+    bandwidth: Arc<TokenBucket>,
+}
+
+impl SyncEngine {
+    fn new(transfers: usize, bandwidth_bytes_per_sec: u64) -> Result<Self, KayaError> {
+        let transfers = transfers.max(1);
+        let client = reqwest::blocking::Client::builder()
+            .pool_max_idle_per_host(transfers)
+            .build()?;
+        Ok(SyncEngine {
+            client,
+            transfers,
+            bandwidth: Arc::new(TokenBucket::new(bandwidth_bytes_per_sec)),
+        })
+    }
+
+    /// The Kaya Server backend for these credentials, sharing the engine's
+    /// connection pool and bandwidth cap.
+    fn kaya_server(&self, server: &str, email: &str, password: &str) -> KayaServer {
+        KayaServer::new(
+            self.client.clone(),
+            server,
+            email,
+            password,
+            self.bandwidth.clone(),
+            &get_kaya_dir(),
+        )
+    }
+}
 
-    let email = match config.email {
-        Some(e) => e,
-        None => return Ok(()),
+/// The configured server, email and decrypted password, or `None` if sync is
+/// not set up.
+fn server_credentials(config: &Config) -> Result<Option<(String, String, String)>, KayaError> {
+    let (server, email) = match (&config.server, &config.email) {
+        (Some(s), Some(e)) => (s.clone(), e.clone()),
+        _ => return Ok(None),
     };
 
     let password = match (&config.encrypted_password, &config.encryption_key) {
@@ -206,289 +619,832 @@ fn sync_with_server() -> Result<(), KayaError> {
                 .map_err(|_| KayaError::Encryption("Invalid key length".to_string()))?;
             decrypt_password(enc, &key)?
         }
-        _ => return Ok(()),
+        _ => return Ok(None),
     };
 
-    let client = reqwest::blocking::Client::new();
+    Ok(Some((server, email, password)))
+}
+
+/// Per-run state shared by every transfer in a sync run.
+struct SyncRun {
+    failures: Mutex<FailureLog>,
+    hashes: Mutex<HashIndex>,
+    journal: Mutex<SyncJournal>,
+    /// Rescan everything instead of skipping what the journal says is unchanged.
+    full: bool,
+}
 
-    let (anga_downloaded, anga_uploaded) =
-        sync_collection(&client, &server, &email, &password, "anga")?;
-    let (meta_downloaded, meta_uploaded) =
-        sync_collection(&client, &server, &email, &password, "meta")?;
-    let words_downloaded = sync_words(&client, &server, &email, &password)?;
+impl SyncRun {
+    /// The SHA-256 of `rel` (relative to `~/.kaya`). A stale or missing entry is
+    /// hashed without holding the index, so parallel uploads don't queue on it.
+    fn local_hash(&self, rel: &str) -> io::Result<String> {
+        if let Some(sha256) = self
+            .hashes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .fresh(rel)
+        {
+            return Ok(sha256);
+        }
+        let sha256 = integrity::sha256_file(&get_kaya_dir().join(rel))?;
+        self.hashes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .record(rel, &sha256)?;
+        Ok(sha256)
+    }
 
-    let total_downloaded = anga_downloaded + meta_downloaded + words_downloaded;
-    let total_uploaded = anga_uploaded + meta_uploaded;
+    /// Tracks the hash of a file that has just been downloaded.
+    fn record_download(&self, rel: &str, downloaded: &Downloaded) -> io::Result<()> {
+        self.hashes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .record(rel, &downloaded.sha256)?;
+        self.journal
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .record_transfer(rel, Direction::Download, &downloaded.sha256, Utc::now());
+        index_file(rel);
+        Ok(())
+    }
 
-    if total_downloaded > 0 || total_uploaded > 0 {
-        log::info!(
-            "Sync complete: {} downloaded, {} uploaded",
-            total_downloaded,
-            total_uploaded
+    /// Records an upload, and that the server now has the file.
+    fn record_upload(&self, collection: &str, filename: &str, sha256: &str) {
+        let mut journal = self.journal.lock().unwrap_or_else(PoisonError::into_inner);
+        journal.record_transfer(
+            &format!("{}/{}", collection, filename),
+            Direction::Upload,
+            sha256,
+            Utc::now(),
         );
+        journal.add_server_name(collection, filename);
     }
-
-    Ok(())
 }
 
-fn sync_collection(
-    client: &reqwest::blocking::Client,
-    server: &str,
-    email: &str,
-    password: &str,
+/// Every name the backend holds in `collection`, with its listing kept in the
+/// journal. A server with incremental listings is only asked for what is new
+/// since the journal's cursor; other backends are listed in full.
+fn list_server(
+    backend: &dyn SyncBackend,
     collection: &str,
-) -> Result<(usize, usize), KayaError> {
-    let url = format!(
-        "{}/api/v1/{}/{}",
-        server.trim_end_matches('/'),
-        urlencoding::encode(email),
-        collection
-    );
+    run: &SyncRun,
+) -> Result<BTreeSet<String>, KayaError> {
+    list_server_dir(backend, &[collection], collection, run)
+}
+
+/// Like [`list_server`], for the directory at `path` on the backend, whose
+/// listing is kept in the journal under `key`.
+fn list_server_dir(
+    backend: &dyn SyncBackend,
+    path: &[&str],
+    key: &str,
+    run: &SyncRun,
+) -> Result<BTreeSet<String>, KayaError> {
+    let known = if run.full {
+        None
+    } else {
+        run.journal
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .server_listing(key)
+            .cloned()
+    };
+    let since = known.as_ref().and_then(|k| k.cursor.as_deref());
+    let listing = backend.list(path, since)?;
+
+    let mut names: BTreeSet<String> = listing.names.into_iter().collect();
+    if listing.delta {
+        names.extend(known.map(|k| k.names).unwrap_or_default());
+    }
+    run.journal
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .set_server_listing(
+            key,
+            ServerListing {
+                cursor: listing.cursor,
+                names: names.clone(),
+            },
+        );
+    Ok(names)
+}
 
-    let response = client.get(&url).basic_auth(email, Some(password)).send()?;
+fn sync_with_server(engine: &SyncEngine, full: bool) -> Result<(), KayaError> {
+    let Some((server, email, password)) = server_credentials(&load_config()?)? else {
+        return Ok(());
+    };
+    sync_with(
+        engine,
+        &engine.kaya_server(&server, &email, &password),
+        &get_kaya_dir(),
+        full,
+    )?;
+    Ok(())
+}
 
-    if !response.status().is_success() {
-        return Err(KayaError::Http(response.error_for_status().unwrap_err()));
+/// Syncs `~/.kaya` with another `~/.kaya`-shaped tree at `target`, e.g. on a USB
+/// stick, in both directions.
+///
+/// The target keeps its own journal and failure log, in
+/// `~/.kaya/.sync-targets/{id}`, so it never disturbs the server's. Other
+/// machines may have written to it since, so it is always rescanned in full.
+fn sync_with_directory(
+    engine: &SyncEngine,
+    target: &Path,
+) -> Result<Vec<(&'static str, Option<SyncCounts>)>, KayaError> {
+    if !target.is_dir() {
+        return Err(KayaError::Config(format!(
+            "{} is not a directory",
+            target.display()
+        )));
+    }
+    let target = fs::canonicalize(target)?;
+    let kaya_dir = get_kaya_dir();
+    if fs::canonicalize(&kaya_dir).is_ok_and(|k| k == target) {
+        return Err(KayaError::Config(
+            "Cannot sync ~/.kaya with itself".to_string(),
+        ));
     }
 
-    let server_files: HashSet<String> = parse_server_file_listing(&response.text()?);
+    let state_dir = target_state_dir(&target.to_string_lossy());
+    sync_with(engine, &Directory::new(&target), &state_dir, true)
+}
 
-    let local_dir = if collection == "anga" {
-        get_anga_dir()
-    } else {
-        get_meta_dir()
+/// Where the journal and failure log for a sync target other than the server
+/// live: `~/.kaya/.sync-targets/{id}`, with `id` derived from `key`.
+fn target_state_dir(key: &str) -> PathBuf {
+    let id = journal::fingerprint([&key.to_string()]);
+    get_kaya_dir().join(".sync-targets").join(&id[..16])
+}
+
+/// The peers to sync with: those configured by address, plus any found by
+/// discovery.
+fn peer_urls(config: &Config, discovered: &Discovered) -> BTreeSet<String> {
+    let mut urls: BTreeSet<String> = config
+        .peers
+        .iter()
+        .map(|u| u.trim_end_matches('/').to_string())
+        .collect();
+    urls.extend(discovered.urls());
+    urls
+}
+
+/// Syncs anga, meta and words with every paired daemon. Each peer keeps its own
+/// journal and failure log, like a directory target; a peer that cannot be
+/// reached is logged and the others still sync.
+fn sync_with_peers(
+    engine: &SyncEngine,
+    discovered: &Discovered,
+    full: bool,
+) -> Result<(), KayaError> {
+    let config = load_config()?;
+    let Some(secret) = config.peer_secret.as_deref() else {
+        return Ok(());
     };
+    for url in peer_urls(&config, discovered) {
+        let peer = Peer::new(
+            engine.client.clone(),
+            &url,
+            secret,
+            engine.bandwidth.clone(),
+            &get_kaya_dir(),
+        );
+        if let Err(e) = sync_with(engine, &peer, &target_state_dir(&url), full) {
+            log::error!("Sync with peer {} failed: {}", url, e);
+        }
+    }
+    Ok(())
+}
 
-    let local_files: HashSet<String> = if local_dir.exists() {
-        fs::read_dir(&local_dir)?
-            .filter_map(|e| e.ok())
-            .filter(|e| e.path().is_file())
-            .filter_map(|e| e.file_name().into_string().ok())
-            .filter(|n| !n.starts_with('.') && (collection == "anga" || n.ends_with(".toml")))
-            .collect()
-    } else {
-        HashSet::new()
+/// Syncs every collection in `~/.kaya` with `backend`, keeping the journal and
+/// failure log for it in `state_dir`. Returns each collection's counts, or `None`
+/// if it could not be listed.
+fn sync_with(
+    engine: &SyncEngine,
+    backend: &dyn SyncBackend,
+    state_dir: &Path,
+    full: bool,
+) -> Result<Vec<(&'static str, Option<SyncCounts>)>, KayaError> {
+    let kaya_dir = get_kaya_dir();
+    let run = SyncRun {
+        failures: Mutex::new(FailureLog::load(state_dir)?),
+        hashes: Mutex::new(HashIndex::load(&kaya_dir)),
+        journal: Mutex::new(SyncJournal::load(state_dir)),
+        full,
     };
 
-    let to_download: Vec<_> = server_files.difference(&local_files).collect();
-    let to_upload: Vec<_> = local_files.difference(&server_files).collect();
+    // Collections are isolated from each other: a failure listing one is logged
+    // and the others still sync
+    let results: Vec<_> = backend
+        .collections()
+        .iter()
+        .map(|&collection| {
+            let result = if NESTED_COLLECTIONS.contains(&collection) {
+                sync_nested(engine, backend, collection, &run)
+            } else {
+                sync_collection(engine, backend, collection, &run)
+            };
+            (collection, result)
+        })
+        .collect();
+
+    run.failures
+        .into_inner()
+        .unwrap_or_else(PoisonError::into_inner)
+        .save()?;
+    run.hashes
+        .into_inner()
+        .unwrap_or_else(PoisonError::into_inner)
+        .save()?;
+    run.journal
+        .into_inner()
+        .unwrap_or_else(PoisonError::into_inner)
+        .save()?;
+
+    let mut total_quarantined = 0;
+    let mut summary = Vec::new();
+    for (collection, result) in results {
+        match result {
+            Ok(counts) => {
+                total_quarantined += counts.quarantined;
+                if counts.succeeded() > 0 || counts.failed > 0 || counts.deferred > 0 {
+                    log::info!(
+                        "Sync {}: {} succeeded ({} downloaded, {} uploaded), {} failed, {} deferred",
+                        collection,
+                        counts.succeeded(),
+                        counts.downloaded,
+                        counts.uploaded,
+                        counts.failed,
+                        counts.deferred
+                    );
+                }
+                summary.push((collection, Some(counts)));
+            }
+            Err(e) => {
+                log::error!("Sync {}: listing failed: {}", collection, e);
+                summary.push((collection, None));
+            }
+        }
+    }
+    if total_quarantined > 0 {
+        log::warn!(
+            "Sync quarantined {} unsafe name(s) from the server; see `savebutton-daemon quarantine`",
+            total_quarantined
+        );
+    }
+
+    Ok(summary)
+}
 
-    let downloaded = to_download.len();
-    let uploaded = to_upload.len();
+/// Transfer counts for one collection in one sync run.
+#[derive(Debug, Default)]
+struct SyncCounts {
+    downloaded: usize,
+    uploaded: usize,
+    /// Transfers that still failed after every retry this run.
+    failed: usize,
+    /// Transfers skipped because an earlier failure is still cooling down.
+    deferred: usize,
+    /// Server-supplied names newly refused this run.
+    quarantined: usize,
+}
 
-    for filename in to_download {
-        log::info!("  downloading {}: {}", collection, filename);
-        download_file(client, server, email, password, collection, filename)?;
+impl SyncCounts {
+    fn succeeded(&self) -> usize {
+        self.downloaded + self.uploaded
     }
 
-    for filename in to_upload {
-        log::info!("  uploading {}: {}", collection, filename);
-        upload_file(client, server, email, password, collection, filename)?;
+    /// Adds failed and deferred transfers to the counts and returns how many
+    /// succeeded, for the caller to count as downloads or uploads.
+    fn tally(&mut self, outcomes: Vec<TransferOutcome>) -> usize {
+        let mut done = 0;
+        for outcome in outcomes {
+            match outcome {
+                TransferOutcome::Done => done += 1,
+                TransferOutcome::Failed => self.failed += 1,
+                TransferOutcome::Deferred => self.deferred += 1,
+            }
+        }
+        done
     }
+}
 
-    Ok((downloaded, uploaded))
+enum TransferOutcome {
+    Done,
+    Failed,
+    Deferred,
 }
 
-fn download_file(
-    client: &reqwest::blocking::Client,
-    server: &str,
-    email: &str,
-    password: &str,
+/// Whether a failed transfer is worth retrying within the same sync run.
+fn is_transient(e: &KayaError) -> bool {
+    match e {
+        KayaError::Http(e) => e.is_timeout() || e.is_connect() || e.is_request() || e.is_body(),
+        KayaError::Download(e) => e.is_transient(),
+        KayaError::Backend(e) => e.is_transient(),
+        _ => false,
+    }
+}
+
+/// Runs one file transfer with retries, recording the outcome in `failures`.
+/// Files still cooling down from an earlier failure are skipped.
+fn transfer(
+    failures: &Mutex<FailureLog>,
     collection: &str,
     filename: &str,
-) -> Result<(), KayaError> {
-    let url = format!(
-        "{}/api/v1/{}/{}/{}",
-        server.trim_end_matches('/'),
-        urlencoding::encode(email),
-        collection,
-        filename
-    );
+    op: impl FnMut() -> Result<(), KayaError>,
+) -> TransferOutcome {
+    if failures
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .is_deferred(collection, filename, Utc::now())
+    {
+        return TransferOutcome::Deferred;
+    }
 
-    let response = client.get(&url).basic_auth(email, Some(password)).send()?;
+    match SYNC_RETRY.run(op, is_transient) {
+        Ok(()) => {
+            failures
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .record_success(collection, filename);
+            TransferOutcome::Done
+        }
+        Err(e) => {
+            let mut failures = failures.lock().unwrap_or_else(PoisonError::into_inner);
+            let entry = failures.record_failure(collection, filename, &e.to_string(), Utc::now());
+            log::warn!(
+                "  {} {} failed ({} run(s) in a row), retrying after {}: {}",
+                collection,
+                filename,
+                entry.failures,
+                entry.retry_after.format("%Y-%m-%dT%H:%M:%SZ"),
+                e
+            );
+            TransferOutcome::Failed
+        }
+    }
+}
 
-    if response.status().is_success() {
-        let content = response.bytes()?;
-        let dir = if collection == "anga" {
-            get_anga_dir()
-        } else {
-            get_meta_dir()
-        };
-        fs::write(dir.join(filename), content)?;
+/// Records a refused server-supplied name. Returns true if it was not already
+/// quarantined.
+fn quarantine_name(collection: &str, name: &str, reason: &str) -> bool {
+    match Quarantine::new(&get_kaya_dir()).record(collection, name, reason) {
+        Ok(true) => {
+            log::warn!(
+                "Quarantined {} name {:?} from server: {}",
+                collection,
+                name,
+                reason
+            );
+            true
+        }
+        Ok(false) => false,
+        Err(e) => {
+            log::error!("Failed to record quarantined name {:?}: {}", name, e);
+            false
+        }
     }
+}
 
-    Ok(())
+/// Drops server listing entries whose canonical names are unsafe to write,
+/// quarantining them. `is_anga_name` applies the timestamp naming scheme on top of
+/// the path checks.
+fn accept_server_names(
+    collection: &str,
+    listing: HashMap<String, String>,
+    is_anga_name: bool,
+    counts: &mut SyncCounts,
+) -> HashMap<String, String> {
+    listing
+        .into_iter()
+        .filter(|(name, _)| {
+            let verdict = sanitize::check_component(name)
+                .map_err(|e| e.to_string())
+                .and_then(|()| {
+                    if is_anga_name {
+                        validate_filename(collection, name).map_err(|e| e.to_string())
+                    } else {
+                        Ok(())
+                    }
+                });
+            match verdict {
+                Ok(()) => true,
+                Err(reason) => {
+                    if quarantine_name(collection, name, &reason) {
+                        counts.quarantined += 1;
+                    }
+                    false
+                }
+            }
+        })
+        .collect()
 }
 
-fn upload_file(
-    client: &reqwest::blocking::Client,
-    server: &str,
-    email: &str,
-    password: &str,
+/// Syncs a flat collection. anga and meta sync both ways; smart lists are
+/// generated by the server and only downloaded from it.
+fn sync_collection(
+    engine: &SyncEngine,
+    backend: &dyn SyncBackend,
     collection: &str,
-    filename: &str,
-) -> Result<(), KayaError> {
-    let dir = if collection == "anga" {
-        get_anga_dir()
-    } else {
-        get_meta_dir()
-    };
-    let path = dir.join(filename);
-    let content = fs::read(&path)?;
+    run: &SyncRun,
+) -> Result<SyncCounts, KayaError> {
+    let names = list_server(backend, collection, run)?;
+    let download_only = !backend.accepts_uploads(collection);
 
-    let url = format!(
-        "{}/api/v1/{}/{}/{}",
-        server.trim_end_matches('/'),
-        urlencoding::encode(email),
+    let mut counts = SyncCounts::default();
+    let listing = accept_server_names(
         collection,
-        urlencoding::encode(filename)
+        codec::canonical_listing(names),
+        collection != "smart",
+        &mut counts,
     );
+    let server_files: HashSet<String> = listing.keys().cloned().collect();
+
+    let local_dir = collection_dir(collection);
+
+    // Same names on the server as after the last clean run, and nothing added,
+    // renamed or removed locally since: there is nothing to transfer
+    let local_modified = || fs::metadata(&local_dir).and_then(|m| m.modified()).ok();
+    if !run.full
+        && run
+            .journal
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_unchanged(
+                collection,
+                &journal::fingerprint(&server_files),
+                local_modified(),
+            )
+    {
+        return Ok(counts);
+    }
 
-    let content_type = mime_type_for(filename);
-
-    let part = reqwest::blocking::multipart::Part::bytes(content)
-        .file_name(filename.to_string())
-        .mime_str(&content_type)
-        .unwrap();
-
-    let form = reqwest::blocking::multipart::Form::new().part("file", part);
-
-    let response = client
-        .post(&url)
-        .basic_auth(email, Some(password))
-        .multipart(form)
-        .send()?;
+    let local_files: HashSet<String> = if local_dir.exists() {
+        fs::read_dir(&local_dir)?
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_file())
+            .filter_map(|e| e.file_name().into_string().ok())
+            .filter(|n| !n.starts_with('.') && (collection != "meta" || n.ends_with(".toml")))
+            // Non-canonical leftovers (migration conflicts) would re-create
+            // double-encoded duplicates on the server
+            .filter(|n| codec::canonical(n) == *n)
+            .collect()
+    } else {
+        HashSet::new()
+    };
 
-    if response.status() == reqwest::StatusCode::CONFLICT {
-        // File already exists, that's fine
-    } else if !response.status().is_success() {
-        log::error!(
-            "Failed to upload {} {}: {}",
+    let to_download: Vec<_> = server_files.difference(&local_files).collect();
+    let to_upload: Vec<_> = if download_only {
+        Vec::new()
+    } else {
+        local_files.difference(&server_files).collect()
+    };
+    run.failures
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .retain_pending(
             collection,
-            filename,
-            response.status()
+            &to_download
+                .iter()
+                .chain(&to_upload)
+                .map(|n| n.to_string())
+                .collect(),
         );
+
+    let outcomes = map_parallel(&to_download, engine.transfers, |filename| {
+        transfer(&run.failures, collection, filename, || {
+            log::info!("  downloading {}: {}", collection, filename);
+            // Refuse unsafe names before anything touches the disk
+            let dest = sanitize::safe_join(&local_dir, filename)?;
+            let downloaded = backend.get(&[collection, &listing[*filename]], &dest)?;
+            run.record_download(&format!("{}/{}", collection, filename), &downloaded)?;
+            Ok(())
+        })
+    });
+    counts.downloaded = counts.tally(outcomes);
+
+    let outcomes = map_parallel(&to_upload, engine.transfers, |filename| {
+        transfer(&run.failures, collection, filename, || {
+            log::info!("  uploading {}: {}", collection, filename);
+            let rel = format!("{}/{}", collection, filename);
+            let sha256 = run.local_hash(&rel)?;
+            let path = local_dir.join(filename);
+            match backend.put(&[collection, filename], &path, &sha256)? {
+                // The server already having an identical file is all an upload needs
+                PutOutcome::Exists(Some(server_sha256)) if server_sha256 != sha256 => {
+                    return Err(KayaError::DigestMismatch(Mismatch {
+                        path: rel,
+                        local: sha256,
+                        server: server_sha256,
+                    }));
+                }
+                PutOutcome::Stored | PutOutcome::Exists(_) => {}
+            }
+            run.record_upload(collection, filename, &sha256);
+            Ok(())
+        })
+    });
+    counts.uploaded = counts.tally(outcomes);
+
+    // Only a run that left both sides with the same names may be skipped next time
+    if counts.failed == 0 && counts.deferred == 0 {
+        let names = if download_only {
+            journal::fingerprint(&server_files)
+        } else {
+            journal::fingerprint(server_files.union(&local_files))
+        };
+        run.journal
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .mark_synced(collection, names, local_modified(), Utc::now());
     }
 
-    Ok(())
+    Ok(counts)
 }
 
 // ---------------------------------------------------------------------------
-// Words sync (download-only from server, nested: words/{anga}/{filename})
+// Nested sync: words/{anga}/{filename} (both ways, server wins on conflict) and
+// cache/{bookmark}/{filename} (download-only)
 // ---------------------------------------------------------------------------
 
-fn sync_words(
-    client: &reqwest::blocking::Client,
-    server: &str,
-    email: &str,
-    password: &str,
-) -> Result<usize, KayaError> {
-    let url = format!(
-        "{}/api/v1/{}/words",
-        server.trim_end_matches('/'),
-        urlencoding::encode(email),
-    );
+/// One file in a nested collection to transfer.
+struct NestedTransfer {
+    /// `{collection}/{anga}`, as used in logs and the failure log.
+    collection: String,
+    dir: PathBuf,
+    filename: String,
+    /// `[nested, anga, filename]` as the backend spells them.
+    remote: [String; 3],
+    upload: bool,
+}
 
-    let response = client.get(&url).basic_auth(email, Some(password)).send()?;
+/// Mirrors a collection of per-anga directories (`words` or `cache`) with the
+/// backend. Words are also uploaded, as is the cache to backends that accept it;
+/// the backend's copy wins on conflict.
+fn sync_nested(
+    engine: &SyncEngine,
+    backend: &dyn SyncBackend,
+    nested: &str,
+    run: &SyncRun,
+) -> Result<SyncCounts, KayaError> {
+    let bidirectional = backend.accepts_uploads(nested);
+    let local_root = collection_dir(nested);
+    let dir_modified = |anga: &str| {
+        fs::metadata(local_root.join(anga))
+            .and_then(|m| m.modified())
+            .ok()
+    };
 
-    if !response.status().is_success() {
-        return Err(KayaError::Http(response.error_for_status().unwrap_err()));
+    let names = list_server(backend, nested, run)?;
+
+    let mut counts = SyncCounts::default();
+    // Each anga directory with its server name, or `None` if only local
+    let mut anga_dirs: HashMap<String, Option<String>> =
+        accept_server_names(nested, codec::canonical_listing(names), true, &mut counts)
+            .into_iter()
+            .map(|(anga, server_anga)| (anga, Some(server_anga)))
+            .collect();
+    if bidirectional && local_root.exists() {
+        for entry in fs::read_dir(&local_root)?.filter_map(|e| e.ok()) {
+            let Ok(anga) = entry.file_name().into_string() else {
+                continue;
+            };
+            if entry.path().is_dir()
+                && !anga.starts_with('.')
+                && codec::canonical(&anga) == anga
+                && parse_anga_filename(&anga).is_ok()
+            {
+                anga_dirs.entry(anga).or_insert(None);
+            }
+        }
     }
 
-    let anga_dirs: HashSet<String> = parse_server_file_listing(&response.text()?);
-    let mut downloaded = 0;
+    let mut anga_dirs: Vec<(String, Option<String>)> = anga_dirs.into_iter().collect();
+    anga_dirs.sort();
 
-    for anga in &anga_dirs {
-        let anga_url = format!(
-            "{}/api/v1/{}/words/{}",
-            server.trim_end_matches('/'),
-            urlencoding::encode(email),
-            urlencoding::encode(anga),
-        );
+    // Per-anga listings are small requests, so they share the transfer limit. A
+    // server with incremental listings only sends what is new in each.
+    let listings = map_parallel(&anga_dirs, engine.transfers, |(anga, server_anga)| {
+        let Some(server_anga) = server_anga else {
+            return Ok(None);
+        };
+        let key = format!("{}/{}", nested, anga);
+        SYNC_RETRY
+            .run(
+                || list_server_dir(backend, &[nested, server_anga], &key, run),
+                is_transient,
+            )
+            .map(Some)
+    });
 
-        let response = client
-            .get(&anga_url)
-            .basic_auth(email, Some(password))
-            .send()?;
+    let mut transfers = Vec::new();
+    let mut listed = Vec::new();
+    for ((anga, server_anga), listing) in anga_dirs.iter().zip(listings) {
+        let collection = format!("{}/{}", nested, anga);
+        let server_names = match listing {
+            Ok(listing) => listing.unwrap_or_default(),
+            Err(e) => {
+                log::warn!("  listing {} failed: {}", collection, e);
+                counts.failed += 1;
+                continue;
+            }
+        };
 
-        if !response.status().is_success() {
+        let server_listing = accept_server_names(
+            &collection,
+            codec::canonical_listing(server_names),
+            false,
+            &mut counts,
+        );
+        let server_files: HashSet<String> = server_listing.keys().cloned().collect();
+
+        // A directory is only re-read when the server's listing of it or the
+        // local directory changed since it was last fully synced
+        let server_names = journal::fingerprint(&server_files);
+        if !run.full
+            && run
+                .journal
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .is_clean(&collection, &server_names, dir_modified(anga))
+        {
             continue;
         }
 
-        let server_files: HashSet<String> = parse_server_file_listing(&response.text()?);
-
-        let local_anga_dir = get_words_dir().join(anga);
+        let local_anga_dir = sanitize::safe_join(&local_root, anga)?;
         let local_files: HashSet<String> = if local_anga_dir.exists() {
             fs::read_dir(&local_anga_dir)?
                 .filter_map(|e| e.ok())
                 .filter(|e| e.path().is_file())
                 .filter_map(|e| e.file_name().into_string().ok())
+                .filter(|n| !n.starts_with('.'))
+                .filter(|n| codec::canonical(n) == *n)
                 .collect()
         } else {
             HashSet::new()
         };
 
-        for filename in server_files.difference(&local_files) {
-            let file_url = format!(
-                "{}/api/v1/{}/words/{}/{}",
-                server.trim_end_matches('/'),
-                urlencoding::encode(email),
-                urlencoding::encode(anga),
-                urlencoding::encode(filename),
+        let to_download: HashSet<String> = server_files.difference(&local_files).cloned().collect();
+        let to_upload: HashSet<String> = if bidirectional {
+            local_files.difference(&server_files).cloned().collect()
+        } else {
+            HashSet::new()
+        };
+        run.failures
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain_pending(
+                &collection,
+                &to_download.union(&to_upload).cloned().collect(),
             );
+        // Once synced, both sides hold every name either held
+        let synced_names = if bidirectional {
+            journal::fingerprint(server_files.union(&local_files))
+        } else {
+            server_names
+        };
+        listed.push((anga.clone(), collection.clone(), synced_names));
+
+        let server_anga = server_anga.as_deref().unwrap_or(anga);
+        for (filename, upload) in to_download
+            .into_iter()
+            .map(|f| (f, false))
+            .chain(to_upload.into_iter().map(|f| (f, true)))
+        {
+            let server_name = server_listing.get(&filename).unwrap_or(&filename);
+            transfers.push(NestedTransfer {
+                remote: [
+                    nested.to_string(),
+                    server_anga.to_string(),
+                    server_name.clone(),
+                ],
+                collection: collection.clone(),
+                dir: local_anga_dir.clone(),
+                filename,
+                upload,
+            });
+        }
+    }
 
-            let response = client
-                .get(&file_url)
-                .basic_auth(email, Some(password))
-                .send()?;
-
-            if response.status().is_success() {
-                let content = response.bytes()?;
-                fs::create_dir_all(&local_anga_dir)?;
-                fs::write(local_anga_dir.join(filename), content)?;
-                log::info!("  downloading words/{}/{}", anga, filename);
-                downloaded += 1;
+    let outcomes = map_parallel(&transfers, engine.transfers, |t| {
+        transfer(&run.failures, &t.collection, &t.filename, || {
+            let rel = format!("{}/{}", t.collection, t.filename);
+            let remote = t.remote.each_ref().map(String::as_str);
+            if t.upload {
+                log::info!("  uploading {}", rel);
+                let sha256 = run.local_hash(&rel)?;
+                let path = t.dir.join(&t.filename);
+                match backend.put(&remote, &path, &sha256)? {
+                    PutOutcome::Stored => {
+                        run.record_upload(&t.collection, &t.filename, &sha256);
+                        return Ok(());
+                    }
+                    PutOutcome::Exists(Some(server_sha256)) if server_sha256 == sha256 => {
+                        return Ok(());
+                    }
+                    // The server generated its own copy meanwhile; it wins
+                    PutOutcome::Exists(_) => {
+                        log::info!("  {} conflicts with the server copy, downloading it", rel);
+                    }
+                }
+            } else {
+                log::info!("  downloading {}", rel);
             }
+            let dest = sanitize::safe_join(&t.dir, &t.filename)?;
+            let downloaded = backend.get(&remote, &dest)?;
+            run.record_download(&rel, &downloaded)?;
+            Ok(())
+        })
+    });
+
+    let incomplete: HashSet<&str> = transfers
+        .iter()
+        .zip(&outcomes)
+        .filter(|(_, outcome)| !matches!(outcome, TransferOutcome::Done))
+        .map(|(t, _)| t.collection.as_str())
+        .collect();
+    let mut journal = run.journal.lock().unwrap_or_else(PoisonError::into_inner);
+    for (anga, collection, names) in listed {
+        if !incomplete.contains(collection.as_str()) {
+            journal.mark_clean(&collection, names, dir_modified(&anga));
+        }
+    }
+    drop(journal);
+    for (t, outcome) in transfers.iter().zip(outcomes) {
+        let done = counts.tally(vec![outcome]);
+        if t.upload {
+            counts.uploaded += done;
+        } else {
+            counts.downloaded += done;
         }
     }
 
-    Ok(downloaded)
+    Ok(counts)
 }
 
-fn mime_type_for(filename: &str) -> String {
-    let ext = filename.rsplit('.').next().unwrap_or("").to_lowercase();
-    match ext.as_str() {
-        "md" => "text/markdown",
-        "url" | "txt" => "text/plain",
-        "json" => "application/json",
-        "toml" => "application/toml",
-        "pdf" => "application/pdf",
-        "png" => "image/png",
-        "jpg" | "jpeg" => "image/jpeg",
-        "gif" => "image/gif",
-        "webp" => "image/webp",
-        "svg" => "image/svg+xml",
-        "html" | "htm" => "text/html",
-        _ => "application/octet-stream",
+/// Checks that `filename` follows the timestamp naming scheme for `collection`.
+fn validate_filename(collection: &str, filename: &str) -> Result<(), FilenameError> {
+    if collection == "meta" {
+        parse_meta_filename(filename).map(|_| ())
+    } else {
+        parse_anga_filename(filename).map(|_| ())
     }
-    .to_string()
 }
 
 // ---------------------------------------------------------------------------
 // HTTP server handlers
 // ---------------------------------------------------------------------------
 
-fn cors_headers() -> Vec<Header> {
+/// Limits shared by every HTTP worker thread.
+struct HttpLimits {
+    request_timeout: Duration,
+    uploads: InFlightLimit,
+    max_body_bytes: u64,
+}
+
+fn header_value<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str())
+}
+
+/// The request's `Origin`, if it is one the daemon answers.
+///
+/// Any extension origin may reach `/pair`, since that is how it joins the allowlist.
+/// Web page origins are never allowed.
+fn allowed_origin<'a>(request: &'a Request, allowlist: &[String]) -> Option<&'a str> {
+    let origin = header_value(request, "Origin")?;
+    if request.url() == "/pair" && cors::is_extension_origin(origin) {
+        return Some(origin);
+    }
+    cors::origin_allowed(origin, allowlist).then_some(origin)
+}
+
+fn cors_headers(request: &Request) -> Vec<Header> {
+    if header_value(request, "Origin").is_none() {
+        return Vec::new();
+    }
+    let origin = match allowed_origin(request, &allowed_origins()) {
+        Some(o) => o,
+        None => return Vec::new(),
+    };
     vec![
-        Header::from_bytes("Access-Control-Allow-Origin", "*").unwrap(),
+        Header::from_bytes("Access-Control-Allow-Origin", origin).unwrap(),
         Header::from_bytes("Access-Control-Allow-Methods", "GET, POST, OPTIONS").unwrap(),
-        Header::from_bytes("Access-Control-Allow-Headers", "Content-Type").unwrap(),
+        Header::from_bytes(
+            "Access-Control-Allow-Headers",
+            "Content-Type, Authorization",
+        )
+        .unwrap(),
+        Header::from_bytes("Vary", "Origin").unwrap(),
     ]
 }
 
 fn respond_ok(request: Request, body: &str) {
     let mut response = Response::from_string(body).with_status_code(StatusCode(200));
-    for h in cors_headers() {
+    for h in cors_headers(&request) {
         response.add_header(h);
     }
     let _ = request.respond(response);
@@ -496,7 +1452,7 @@ fn respond_ok(request: Request, body: &str) {
 
 fn respond_error(request: Request, status: u16, msg: &str) {
     let mut response = Response::from_string(msg).with_status_code(StatusCode(status));
-    for h in cors_headers() {
+    for h in cors_headers(&request) {
         response.add_header(h);
     }
     let _ = request.respond(response);
@@ -504,26 +1460,185 @@ fn respond_error(request: Request, status: u16, msg: &str) {
 
 fn respond_no_content(request: Request) {
     let mut response = Response::from_string("").with_status_code(StatusCode(204));
-    for h in cors_headers() {
+    for h in cors_headers(&request) {
         response.add_header(h);
     }
     let _ = request.respond(response);
 }
 
-fn handle_request(request: Request) {
-    let method = request.method().clone();
-    let url = request.url().to_string();
+fn is_authorized(request: &Request) -> bool {
+    let token = match header_value(request, "Authorization").and_then(auth::bearer_token) {
+        Some(t) => t,
+        None => return false,
+    };
+    match auth::verify_token(&get_kaya_dir(), token) {
+        Ok(valid) => valid,
+        Err(e) => {
+            log::error!("Failed to read tokens: {}", e);
+            false
+        }
+    }
+}
 
-    // Handle CORS preflight
-    if method == Method::Options {
-        respond_no_content(request);
-        return;
+/// Largest body accepted by the JSON endpoints (`/config`, `/pair`), which are
+/// read into memory and `/pair` needs no token.
+const MAX_JSON_BODY_BYTES: u64 = 64 * 1024;
+
+/// Reads a whole (small) request body within the request deadline, refusing
+/// bodies over [`MAX_JSON_BODY_BYTES`].
+fn read_body(request: &mut Request, limits: &HttpLimits) -> Result<Vec<u8>, StoreError> {
+    let too_large = StoreError::TooLarge {
+        limit: MAX_JSON_BODY_BYTES,
+    };
+    if request
+        .body_length()
+        .is_some_and(|n| n as u64 > MAX_JSON_BODY_BYTES)
+    {
+        return Err(too_large);
+    }
+    let mut body = Vec::new();
+    DeadlineReader::new(request.as_reader(), limits.request_timeout)
+        .take(MAX_JSON_BODY_BYTES + 1)
+        .read_to_end(&mut body)?;
+    if body.len() as u64 > MAX_JSON_BODY_BYTES {
+        return Err(too_large);
     }
+    Ok(body)
+}
 
-    // Route: GET /health
-    if method == Method::Get && url == "/health" {
-        respond_ok(request, "ok");
-        return;
+/// Answers a request whose body could not be read or stored.
+fn respond_read_error(request: Request, e: &io::Error) {
+    match e.kind() {
+        io::ErrorKind::TimedOut => respond_error(request, 408, "Request timeout"),
+        io::ErrorKind::UnexpectedEof | io::ErrorKind::InvalidData => {
+            respond_error(request, 400, "Could not read request body")
+        }
+        _ => respond_error(request, 500, &e.to_string()),
+    }
+}
+
+/// The request body, limited by the request deadline. Refuses oversized bodies up
+/// front when the client declares a length.
+fn upload_body<'a>(
+    request: &'a mut Request,
+    limits: &HttpLimits,
+) -> Result<DeadlineReader<&'a mut dyn Read>, StoreError> {
+    if request
+        .body_length()
+        .is_some_and(|n| n as u64 > limits.max_body_bytes)
+    {
+        return Err(StoreError::TooLarge {
+            limit: limits.max_body_bytes,
+        });
+    }
+    Ok(DeadlineReader::new(
+        request.as_reader(),
+        limits.request_timeout,
+    ))
+}
+
+/// Streams an upload body to `dir/filename`, replacing any existing file.
+fn receive_upload(
+    request: &mut Request,
+    limits: &HttpLimits,
+    dir: &Path,
+    filename: &str,
+) -> Result<u64, StoreError> {
+    let body = upload_body(request, limits)?;
+    store::write_streamed(dir, filename, body, limits.max_body_bytes)
+}
+
+/// Streams an upload body to `dir/filename`, refusing to change an existing file.
+fn receive_immutable_upload(
+    request: &mut Request,
+    limits: &HttpLimits,
+    dir: &Path,
+    filename: &str,
+) -> Result<WriteOutcome, StoreError> {
+    let body = upload_body(request, limits)?;
+    store::write_immutable(dir, filename, body, limits.max_body_bytes)
+}
+
+/// Answers a request whose upload could not be stored.
+fn respond_store_error(request: Request, e: &StoreError) {
+    match e {
+        StoreError::TooLarge { .. } => respond_error(request, 413, "Payload too large"),
+        StoreError::Conflict(_) => respond_error(request, 409, &e.to_string()),
+        StoreError::DigestMismatch { .. } => {
+            respond_error(request, 400, "Body does not match Repr-Digest")
+        }
+        StoreError::Io(io) => respond_read_error(request, io),
+    }
+}
+
+/// `url` without its query string.
+fn without_query(url: &str) -> &str {
+    url.split_once('?').map_or(url, |(path, _)| path)
+}
+
+fn handle_request(request: Request, limits: &HttpLimits) {
+    let method = request.method().clone();
+    let url = request.url().to_string();
+
+    // Refuse browser requests from origins outside the allowlist, preflight included.
+    // Requests without an Origin come from non-browser local clients.
+    if let Some(origin) = header_value(&request, "Origin") {
+        if allowed_origin(&request, &allowed_origins()).is_none() {
+            log::warn!("Rejected {} {} from origin {}", method, url, origin);
+            respond_error(request, 403, "Origin not allowed");
+            return;
+        }
+    }
+
+    // Handle CORS preflight
+    if method == Method::Options {
+        respond_no_content(request);
+        return;
+    }
+
+    // Route: POST /pair -- trade a pairing code for a bearer token
+    if method == Method::Post && url == "/pair" {
+        match handle_pair_post(request, limits) {
+            Ok(()) => {}
+            Err(e) => log::error!("Failed to pair: {}", e),
+        }
+        return;
+    }
+
+    // Every other mutating route requires a paired bearer token
+    if method == Method::Post && !is_authorized(&request) {
+        log::warn!("Rejected unauthorized POST {}", url);
+        respond_error(request, 401, "Unauthorized");
+        return;
+    }
+
+    // File uploads hold a slot for their whole duration; shed load rather than queue
+    let is_upload = method == Method::Post
+        && ["/anga/", "/meta/", "/words/", "/cache/", "/smart/"]
+            .iter()
+            .any(|prefix| url.starts_with(prefix));
+    let _upload_slot = if is_upload {
+        match limits.uploads.try_acquire() {
+            Some(slot) => Some(slot),
+            None => {
+                respond_busy(request);
+                return;
+            }
+        }
+    } else {
+        None
+    };
+
+    // Route: GET /health
+    if method == Method::Get && url == "/health" {
+        respond_ok(request, "ok");
+        return;
+    }
+
+    // Route: GET /search?q= -- ranked full-text search over words, notes and meta
+    if method == Method::Get && (url == "/search" || url.starts_with("/search?")) {
+        handle_search(request);
+        return;
     }
 
     // Route: GET /anga -- list anga files
@@ -535,6 +1650,20 @@ fn handle_request(request: Request) {
         return;
     }
 
+    // Route: GET /anga/{filename}/related -- the anga most like one, as JSON
+    if method == Method::Get && url.starts_with("/anga/") {
+        let path = without_query(&url);
+        if let Some(filename) = path[6..].strip_suffix("/related") {
+            let filename = codec::canonical(filename);
+            if let Err(e) = parse_anga_filename(&filename) {
+                respond_error(request, 400, &format!("Invalid anga name: {}", e));
+                return;
+            }
+            handle_related(request, &filename);
+            return;
+        }
+    }
+
     // Route: GET /meta -- list meta files
     if method == Method::Get && url == "/meta" {
         match list_files("meta") {
@@ -544,16 +1673,40 @@ fn handle_request(request: Request) {
         return;
     }
 
+    // Route: GET /smart -- list smart list files
+    if method == Method::Get && url == "/smart" {
+        match list_files("smart") {
+            Ok(listing) => respond_ok(request, &listing),
+            Err(e) => respond_error(request, 500, &e.to_string()),
+        }
+        return;
+    }
+
+    // Route: POST /smart/{filename} -- write a smart list file
+    if method == Method::Post
+        && url.starts_with("/smart/")
+        && without_query(&url).matches('/').count() == 2
+    {
+        let filename = codec::canonical(without_query(&url[7..]));
+        if let Err(e) = sanitize::check_component(&filename) {
+            respond_error(request, 400, &format!("Invalid filename: {}", e));
+            return;
+        }
+        match write_smart_file(request, limits, &filename) {
+            Ok(()) => {}
+            Err(e) => log::error!("Failed to write smart {}: {}", filename, e),
+        }
+        return;
+    }
+
     // Route: POST /anga/{filename} -- write anga file
     if method == Method::Post && url.starts_with("/anga/") {
-        let filename = urlencoding::decode(&url[6..])
-            .unwrap_or_default()
-            .into_owned();
-        if filename.is_empty() || filename.contains('/') || filename.contains("..") {
-            respond_error(request, 400, "Invalid filename");
+        let filename = codec::canonical(without_query(&url[6..]));
+        if let Err(e) = validate_filename("anga", &filename) {
+            respond_error(request, 400, &format!("Invalid filename: {}", e));
             return;
         }
-        match write_uploaded_file(request, "anga", &filename) {
+        match write_uploaded_file(request, limits, "anga", &filename) {
             Ok(()) => {}
             Err(e) => log::error!("Failed to write anga {}: {}", filename, e),
         }
@@ -562,75 +1715,83 @@ fn handle_request(request: Request) {
 
     // Route: POST /meta/{filename} -- write meta file
     if method == Method::Post && url.starts_with("/meta/") {
-        let filename = urlencoding::decode(&url[6..])
-            .unwrap_or_default()
-            .into_owned();
-        if filename.is_empty() || filename.contains('/') || filename.contains("..") {
-            respond_error(request, 400, "Invalid filename");
+        let filename = codec::canonical(without_query(&url[6..]));
+        if let Err(e) = validate_filename("meta", &filename) {
+            respond_error(request, 400, &format!("Invalid filename: {}", e));
             return;
         }
-        match write_uploaded_file(request, "meta", &filename) {
+        match write_uploaded_file(request, limits, "meta", &filename) {
             Ok(()) => {}
             Err(e) => log::error!("Failed to write meta {}: {}", filename, e),
         }
         return;
     }
 
-    // Route: GET /words -- list anga subdirectories under ~/.kaya/words/
-    if method == Method::Get && url == "/words" {
-        match list_words_dirs() {
-            Ok(listing) => respond_ok(request, &listing),
-            Err(e) => respond_error(request, 500, &e.to_string()),
-        }
-        return;
-    }
+    // Routes for per-anga collections, e.g. for words:
+    //   GET /words -- list anga subdirectories under ~/.kaya/words/
+    //   GET /words/{anga} -- list files in a words anga subdir
+    //   POST /words/{anga}/{filename} -- write a words file
+    let nested = NESTED_COLLECTIONS
+        .into_iter()
+        .find(|c| url.strip_prefix('/').unwrap_or("").split('/').next() == Some(*c));
+    if let Some(nested) = nested {
+        let path = without_query(&url[nested.len() + 1..]).trim_start_matches('/');
+        let segments = if path.is_empty() {
+            0
+        } else {
+            path.matches('/').count() + 1
+        };
 
-    // Route: GET /words/{anga} -- list files in a words anga subdir
-    if method == Method::Get && url.starts_with("/words/") && url.matches('/').count() == 2 {
-        let anga = urlencoding::decode(&url[7..])
-            .unwrap_or_default()
-            .into_owned();
-        if anga.is_empty() || anga.contains('/') || anga.contains("..") {
-            respond_error(request, 400, "Invalid anga name");
+        if method == Method::Get && segments == 0 {
+            match list_nested_dirs(nested) {
+                Ok(listing) => respond_ok(request, &listing),
+                Err(e) => respond_error(request, 500, &e.to_string()),
+            }
             return;
         }
-        match list_words_files(&anga) {
-            Ok(listing) => respond_ok(request, &listing),
-            Err(e) => respond_error(request, 500, &e.to_string()),
-        }
-        return;
-    }
 
-    // Route: POST /words/{anga}/{filename} -- write a words file
-    if method == Method::Post && url.starts_with("/words/") && url.matches('/').count() == 3 {
-        let path = &url[7..]; // strip "/words/"
-        if let Some((anga, filename)) = path.split_once('/') {
-            let anga = urlencoding::decode(anga).unwrap_or_default().into_owned();
-            let filename = urlencoding::decode(filename)
-                .unwrap_or_default()
-                .into_owned();
-            if anga.is_empty()
-                || anga.contains("..")
-                || filename.is_empty()
-                || filename.contains('/')
-                || filename.contains("..")
-            {
-                respond_error(request, 400, "Invalid path");
+        if method == Method::Get && segments == 1 {
+            let anga = codec::canonical(path);
+            if let Err(e) = parse_anga_filename(&anga) {
+                respond_error(request, 400, &format!("Invalid anga name: {}", e));
                 return;
             }
-            match write_words_file(request, &anga, &filename) {
-                Ok(()) => {}
-                Err(e) => log::error!("Failed to write words/{}/{}: {}", anga, filename, e),
+            match list_nested_files(nested, &anga) {
+                Ok(listing) => respond_ok(request, &listing),
+                Err(e) => respond_error(request, 500, &e.to_string()),
             }
             return;
         }
-        respond_error(request, 400, "Invalid path");
+
+        if method == Method::Post && segments == 2 {
+            if let Some((anga, filename)) = path.split_once('/') {
+                let anga = codec::canonical(anga);
+                let filename = codec::canonical(filename);
+                if let Err(e) = parse_anga_filename(&anga) {
+                    respond_error(request, 400, &format!("Invalid anga name: {}", e));
+                    return;
+                }
+                if let Err(e) = sanitize::check_component(&filename) {
+                    respond_error(request, 400, &format!("Invalid filename: {}", e));
+                    return;
+                }
+                match write_nested_file(request, limits, nested, &anga, &filename) {
+                    Ok(()) => {}
+                    Err(e) => {
+                        log::error!("Failed to write {}/{}/{}: {}", nested, anga, filename, e)
+                    }
+                }
+                return;
+            }
+        }
+
+        respond_error(request, 404, "Not found");
         return;
     }
 
     // Route: POST /config -- receive config from extension
     if method == Method::Post && url == "/config" {
-        match handle_config_post(request) {
+        match handle_config_post(request, limits) {
             Ok(()) => {}
             Err(e) => log::error!("Failed to save config: {}", e),
         }
@@ -647,24 +1808,25 @@ struct IncomingConfig {
     password: String,
 }
 
-fn handle_config_post(mut request: Request) -> Result<(), KayaError> {
-    let mut body = String::new();
-    request
-        .as_reader()
-        .read_to_string(&mut body)
-        .map_err(KayaError::Io)?;
+fn handle_config_post(mut request: Request, limits: &HttpLimits) -> Result<(), KayaError> {
+    let body = match read_body(&mut request, limits) {
+        Ok(b) => b,
+        Err(e) => {
+            respond_store_error(request, &e);
+            return Err(e.into());
+        }
+    };
 
-    let incoming: IncomingConfig = serde_json::from_str(&body)?;
+    let incoming: IncomingConfig = serde_json::from_slice(&body)?;
 
     let key = generate_encryption_key();
     let encrypted = encrypt_password(&incoming.password, &key)?;
 
-    let config = Config {
-        server: Some(incoming.server),
-        email: Some(incoming.email),
-        encrypted_password: Some(encrypted),
-        encryption_key: Some(BASE64.encode(key)),
-    };
+    let mut config = load_config()?;
+    config.server = Some(incoming.server);
+    config.email = Some(incoming.email);
+    config.encrypted_password = Some(encrypted);
+    config.encryption_key = Some(BASE64.encode(key));
 
     save_config(&config)?;
     log::info!("Config updated via POST /config");
@@ -673,8 +1835,62 @@ fn handle_config_post(mut request: Request) -> Result<(), KayaError> {
     Ok(())
 }
 
-fn list_words_dirs() -> Result<String, KayaError> {
-    let dir = get_words_dir();
+/// Adds `origin` to the allowlist if it is not already covered by it.
+fn allow_origin(origin: &str) -> Result<(), KayaError> {
+    let mut config = load_config()?;
+    if !cors::origin_allowed(origin, &config.allowed_origins) {
+        config.allowed_origins.push(origin.to_string());
+        save_config(&config)?;
+        log::info!("Allowed origin {}", origin);
+    }
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct PairRequest {
+    code: String,
+    #[serde(default)]
+    label: Option<String>,
+}
+
+fn handle_pair_post(mut request: Request, limits: &HttpLimits) -> Result<(), KayaError> {
+    let body = match read_body(&mut request, limits) {
+        Ok(b) => b,
+        Err(e) => {
+            respond_store_error(request, &e);
+            return Err(e.into());
+        }
+    };
+
+    let pair: PairRequest = match serde_json::from_slice(&body) {
+        Ok(p) => p,
+        Err(e) => {
+            respond_error(request, 400, "Invalid pairing request");
+            return Err(e.into());
+        }
+    };
+    let label = pair.label.unwrap_or_else(|| "extension".to_string());
+    let origin = header_value(&request, "Origin").map(str::to_string);
+
+    match auth::redeem_pairing_code(&get_kaya_dir(), &pair.code, &label)? {
+        Some(token) => {
+            log::info!("Paired new client: {}", label);
+            if let Some(origin) = origin {
+                allow_origin(&origin)?;
+            }
+            let body = serde_json::json!({ "token": token }).to_string();
+            respond_ok(request, &body);
+        }
+        None => {
+            log::warn!("Rejected pairing attempt with invalid or expired code");
+            respond_error(request, 403, "Invalid or expired pairing code");
+        }
+    }
+    Ok(())
+}
+
+fn list_nested_dirs(nested: &str) -> Result<String, KayaError> {
+    let dir = collection_dir(nested);
     if !dir.exists() {
         return Ok(String::new());
     }
@@ -690,8 +1906,8 @@ fn list_words_dirs() -> Result<String, KayaError> {
     Ok(names.join("\n"))
 }
 
-fn list_words_files(anga: &str) -> Result<String, KayaError> {
-    let dir = get_words_dir().join(anga);
+fn list_nested_files(nested: &str, anga: &str) -> Result<String, KayaError> {
+    let dir = collection_dir(nested).join(anga);
     if !dir.exists() {
         return Ok(String::new());
     }
@@ -700,34 +1916,51 @@ fn list_words_files(anga: &str) -> Result<String, KayaError> {
         .filter_map(|e| e.ok())
         .filter(|e| e.path().is_file())
         .filter_map(|e| e.file_name().into_string().ok())
+        .filter(|n| !n.starts_with('.'))
         .collect();
 
     names.sort();
     Ok(names.join("\n"))
 }
 
-fn write_words_file(mut request: Request, anga: &str, filename: &str) -> Result<(), KayaError> {
-    let mut body = Vec::new();
-    request
-        .as_reader()
-        .read_to_end(&mut body)
-        .map_err(KayaError::Io)?;
+fn write_nested_file(
+    mut request: Request,
+    limits: &HttpLimits,
+    nested: &str,
+    anga: &str,
+    filename: &str,
+) -> Result<(), KayaError> {
+    let dir = collection_dir(nested).join(anga);
+    if let Err(e) = receive_upload(&mut request, limits, &dir, filename) {
+        respond_store_error(request, &e);
+        return Err(e.into());
+    }
+    log::info!("Wrote {}/{}/{}", nested, anga, filename);
+    index_file(&format!("{}/{}/{}", nested, anga, filename));
 
-    let dir = get_words_dir().join(anga);
-    fs::create_dir_all(&dir)?;
-    fs::write(dir.join(filename), &body)?;
-    log::info!("Wrote words/{}/{}", anga, filename);
+    respond_ok(request, "ok");
+    Ok(())
+}
+
+/// Smart lists are regenerated rather than appended, so unlike anga and meta they
+/// may be overwritten.
+fn write_smart_file(
+    mut request: Request,
+    limits: &HttpLimits,
+    filename: &str,
+) -> Result<(), KayaError> {
+    if let Err(e) = receive_upload(&mut request, limits, &get_smart_dir(), filename) {
+        respond_store_error(request, &e);
+        return Err(e.into());
+    }
+    log::info!("Wrote smart/{}", filename);
 
     respond_ok(request, "ok");
     Ok(())
 }
 
 fn list_files(collection: &str) -> Result<String, KayaError> {
-    let dir = if collection == "anga" {
-        get_anga_dir()
-    } else {
-        get_meta_dir()
-    };
+    let dir = collection_dir(collection);
 
     if !dir.exists() {
         return Ok(String::new());
@@ -746,35 +1979,1030 @@ fn list_files(collection: &str) -> Result<String, KayaError> {
 
 fn write_uploaded_file(
     mut request: Request,
+    limits: &HttpLimits,
     collection: &str,
     filename: &str,
 ) -> Result<(), KayaError> {
-    let mut body = Vec::new();
-    request
-        .as_reader()
-        .read_to_end(&mut body)
-        .map_err(KayaError::Io)?;
+    let dir = collection_dir(collection);
 
-    let dir = if collection == "anga" {
-        get_anga_dir()
-    } else {
-        get_meta_dir()
+    ensure_directories()?;
+    match receive_immutable_upload(&mut request, limits, &dir, filename) {
+        Ok(WriteOutcome::Created) => {
+            log::info!("Wrote {} {}", collection, filename);
+            index_file(&format!("{}/{}", collection, filename));
+            respond_ok(request, "ok");
+        }
+        Ok(WriteOutcome::Unchanged) => respond_ok(request, "unchanged"),
+        Err(e) => {
+            if let StoreError::Conflict(_) = e {
+                log::warn!(
+                    "Refused to overwrite {} {} with different content",
+                    collection,
+                    filename
+                );
+            }
+            respond_store_error(request, &e);
+            return Err(e.into());
+        }
+    }
+    Ok(())
+}
+
+// ---------------------------------------------------------------------------
+// Search
+// ---------------------------------------------------------------------------
+
+const DEFAULT_SEARCH_LIMIT: usize = 20;
+const DEFAULT_RELATED_LIMIT: usize = 10;
+const MAX_SEARCH_LIMIT: usize = 100;
+
+/// The search index, once the daemon has loaded it. Commands run without the
+/// daemon leave what they write for its next refresh.
+static SEARCH: OnceLock<Mutex<SearchIndex>> = OnceLock::new();
+
+/// Locks the search index. A handler that panicked while holding it may have
+/// left it half-updated, so it is reloaded from the last save; the next refresh
+/// catches up with anything written since.
+fn lock_search(index: &Mutex<SearchIndex>) -> MutexGuard<'_, SearchIndex> {
+    index.lock().unwrap_or_else(|e| {
+        log::error!("Search index lock was poisoned by a panic, reloading the index");
+        let mut guard = e.into_inner();
+        *guard = SearchIndex::load(&get_kaya_dir());
+        index.clear_poison();
+        guard
+    })
+}
+
+/// Indexes a file just written under `~/.kaya`, if the daemon has loaded the index.
+fn index_file(rel: &str) {
+    if let Some(index) = SEARCH.get() {
+        if let Err(e) = lock_search(index).update(rel) {
+            log::warn!("Failed to index {}: {}", rel, e);
+        }
+    }
+}
+
+/// Brings the search index up to date with `~/.kaya`, catching files written
+/// behind the daemon, and saves it.
+fn refresh_search_index() {
+    let Some(index) = SEARCH.get() else {
+        return;
     };
+    let mut index = lock_search(index);
+    match index.refresh() {
+        Ok(0) => {}
+        Ok(changed) => log::info!(
+            "Search index: {} source(s) updated, {} anga indexed",
+            changed,
+            index.documents()
+        ),
+        Err(e) => log::error!("Failed to refresh search index: {}", e),
+    }
+    if let Err(e) = index.save() {
+        log::error!("Failed to save search index: {}", e);
+    }
+}
 
-    ensure_directories()?;
-    fs::write(dir.join(filename), &body)?;
-    log::info!("Wrote {} {}", collection, filename);
+/// The decoded value of `name` in `url`'s query string.
+fn query_param(url: &str, name: &str) -> Option<String> {
+    let (_, query) = url.split_once('?')?;
+    query.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        (key == name).then(|| {
+            let value = value.replace('+', " ");
+            urlencoding::decode(&value)
+                .map(|v| v.into_owned())
+                .unwrap_or(value)
+        })
+    })
+}
 
-    respond_ok(request, "ok");
-    Ok(())
+/// `GET /search?q=...[&limit=N]`: the best matching anga as JSON, best first.
+/// `q` is taken as typed, so its last word also matches longer words.
+fn handle_search(request: Request) {
+    let url = request.url().to_string();
+    let Some(q) = query_param(&url, "q") else {
+        respond_error(request, 400, "Missing q");
+        return;
+    };
+    let query = match Query::parse_as_typed(&q) {
+        Ok(query) => query,
+        Err(e) => {
+            respond_error(request, 400, &format!("Invalid query: {}", e));
+            return;
+        }
+    };
+    let limit = query_param(&url, "limit")
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(DEFAULT_SEARCH_LIMIT)
+        .clamp(1, MAX_SEARCH_LIMIT);
+    let Some(index) = SEARCH.get() else {
+        respond_error(request, 503, "Search index not loaded");
+        return;
+    };
+    // Phrases and snippets are read from disk, so only after the index is released
+    let candidates = lock_search(index).candidates(&query, limit);
+    let hits = candidates.hits();
+    let body = serde_json::json!({ "query": q, "results": hits }).to_string();
+    respond_ok(request, &body);
+}
+
+/// `GET /anga/{filename}/related[?limit=N]`: the anga most like `filename` as
+/// JSON, best first. `404` if nothing about it is indexed.
+fn handle_related(request: Request, filename: &str) {
+    let limit = query_param(request.url(), "limit")
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(DEFAULT_RELATED_LIMIT)
+        .clamp(1, MAX_SEARCH_LIMIT);
+    let Some(index) = SEARCH.get() else {
+        respond_error(request, 503, "Search index not loaded");
+        return;
+    };
+    let Some(related) = lock_search(index).related(filename, limit) else {
+        respond_error(request, 404, "Not found");
+        return;
+    };
+    let body = serde_json::json!({ "anga": filename, "related": related }).to_string();
+    respond_ok(request, &body);
+}
+
+// ---------------------------------------------------------------------------
+// Remote APIs: /peer/... for paired daemons (see `peer`) and the
+// Kaya-Server-compatible /api/v1/{email}/... (--api-listen)
+// ---------------------------------------------------------------------------
+
+/// What a collection path names, with its segments decoded and checked.
+enum ApiTarget {
+    /// `{anga,meta,smart}`
+    Collection(&'static str),
+    /// `{words,cache}`
+    NestedDirs(&'static str),
+    /// `{words,cache}/{anga}`
+    NestedFiles(&'static str, String),
+    /// `{anga,meta,smart}/{filename}` or `{words,cache}/{anga}/{filename}`
+    File {
+        collection: &'static str,
+        dir: PathBuf,
+        filename: String,
+        rel: String,
+    },
+}
+
+fn not_found() -> (u16, String) {
+    (404, "Not found".to_string())
+}
+
+/// Splits a URL path (without its query) into decoded, path-safe segments.
+fn decode_segments(path: &str) -> Result<Vec<String>, (u16, String)> {
+    let segments = path
+        .split('/')
+        .map(|s| urlencoding::decode(s).map(|d| d.into_owned()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| (400, "Invalid path".to_string()))?;
+    for segment in &segments {
+        sanitize::check_component(segment).map_err(|e| (400, format!("Invalid name: {}", e)))?;
+    }
+    Ok(segments)
+}
+
+fn api_target(segments: &[String]) -> Result<ApiTarget, (u16, String)> {
+    let (first, rest) = segments.split_first().ok_or_else(not_found)?;
+    let collection = ["anga", "meta", "smart", "words", "cache"]
+        .into_iter()
+        .find(|c| c == first)
+        .ok_or_else(not_found)?;
+    let invalid = |e: FilenameError| (400, format!("Invalid filename: {}", e));
+    let nested = NESTED_COLLECTIONS.contains(&collection);
+
+    match (nested, rest) {
+        (false, []) => Ok(ApiTarget::Collection(collection)),
+        (true, []) => Ok(ApiTarget::NestedDirs(collection)),
+        (false, [filename]) => {
+            // Smart lists are named freely; anga and meta follow the naming scheme
+            if collection != "smart" {
+                validate_filename(collection, filename).map_err(invalid)?;
+            }
+            Ok(ApiTarget::File {
+                collection,
+                dir: collection_dir(collection),
+                filename: filename.clone(),
+                rel: format!("{}/{}", collection, filename),
+            })
+        }
+        (true, [anga]) => {
+            parse_anga_filename(anga).map_err(invalid)?;
+            Ok(ApiTarget::NestedFiles(collection, anga.clone()))
+        }
+        (true, [anga, filename]) => {
+            parse_anga_filename(anga).map_err(invalid)?;
+            Ok(ApiTarget::File {
+                collection,
+                dir: collection_dir(collection).join(anga),
+                filename: filename.clone(),
+                rel: format!("{}/{}/{}", collection, anga, filename),
+            })
+        }
+        _ => Err(not_found()),
+    }
+}
+
+/// How an upload's body is framed.
+enum UploadBody {
+    /// The raw file, with the SHA-256 the sender declared for it.
+    Raw(String),
+    /// The `file` part of a `multipart/form-data` body with this boundary.
+    Multipart(String),
+}
+
+/// Answers a request for `target` on one of the remote APIs. Listings, files
+/// (`GET`/`HEAD`, with `Repr-Digest`) and uploads to `uploadable` collections,
+/// which never replace a file: an existing one is a `409` carrying its digest.
+fn serve_api_target(
+    request: Request,
+    limits: &HttpLimits,
+    target: ApiTarget,
+    uploadable: &[&str],
+    body: Option<UploadBody>,
+    reply: Option<&PeerReply>,
+) {
+    let method = request.method().clone();
+    match (&method, target) {
+        (Method::Get, ApiTarget::Collection(collection)) => {
+            respond_listing(request, list_files(collection), reply)
+        }
+        (Method::Get, ApiTarget::NestedDirs(nested)) => {
+            respond_listing(request, list_nested_dirs(nested), reply)
+        }
+        (Method::Get, ApiTarget::NestedFiles(nested, anga)) => {
+            respond_listing(request, list_nested_files(nested, &anga), reply)
+        }
+        (Method::Get | Method::Head, ApiTarget::File { dir, filename, .. }) => {
+            serve_file(request, &dir.join(filename), reply)
+        }
+        (
+            Method::Post,
+            ApiTarget::File {
+                collection,
+                dir,
+                filename,
+                rel,
+            },
+        ) if uploadable.contains(&collection) => {
+            let Some(body) = body else {
+                respond_error(request, 400, "Missing Repr-Digest or multipart body");
+                return;
+            };
+            let Some(_slot) = limits.uploads.try_acquire() else {
+                respond_busy(request);
+                return;
+            };
+            match receive_file(request, limits, &dir, &filename, body, reply) {
+                Ok(true) => {
+                    log::info!("Received {}", rel);
+                    index_file(&rel);
+                }
+                Ok(false) => {}
+                Err(e) => log::error!("Failed to write {}: {}", rel, e),
+            }
+        }
+        _ => respond_error(request, 405, "Method not allowed"),
+    }
+}
+
+/// Answers with a listing in the Kaya Server's form: one URL-encoded name per line.
+fn respond_listing(
+    request: Request,
+    listing: Result<String, KayaError>,
+    reply: Option<&PeerReply>,
+) {
+    match listing {
+        Ok(listing) => {
+            let names: Vec<String> = listing.lines().map(codec::url_segment).collect();
+            let body = names.join("\n");
+            let mut response =
+                Response::from_string(body.as_str()).with_status_code(StatusCode(200));
+            for h in cors_headers(&request) {
+                response.add_header(h);
+            }
+            if let Some(reply) = reply {
+                for h in reply.headers(200, Some(&integrity::sha256_bytes(body.as_bytes()))) {
+                    response.add_header(h);
+                }
+            }
+            let _ = request.respond(response);
+        }
+        Err(e) => respond_error(request, 500, &e.to_string()),
+    }
+}
+
+fn respond_busy(request: Request) {
+    log::warn!("Too many uploads in flight, rejecting {}", request.url());
+    let mut response =
+        Response::from_string("Too many uploads in flight").with_status_code(StatusCode(503));
+    for h in cors_headers(&request) {
+        response.add_header(h);
+    }
+    response.add_header(Header::from_bytes("Retry-After", "5").unwrap());
+    let _ = request.respond(response);
+}
+
+fn digest_header(sha256: &str) -> Header {
+    Header::from_bytes("Repr-Digest", integrity::repr_digest(sha256)).unwrap()
+}
+
+fn serve_file(request: Request, path: &Path, reply: Option<&PeerReply>) {
+    let sha256 = match integrity::sha256_file(path) {
+        Ok(sha256) => sha256,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            respond_error(request, 404, "Not found");
+            return;
+        }
+        Err(e) => {
+            respond_error(request, 500, &e.to_string());
+            return;
+        }
+    };
+    match fs::File::open(path) {
+        Ok(file) => {
+            let mut response = Response::from_file(file).with_header(digest_header(&sha256));
+            for h in reply
+                .into_iter()
+                .flat_map(|r| r.headers(200, Some(&sha256)))
+            {
+                response.add_header(h);
+            }
+            let _ = request.respond(response);
+        }
+        Err(e) => respond_error(request, 500, &e.to_string()),
+    }
+}
+
+/// Stores an uploaded file unless one already exists. Returns whether it was
+/// written. A raw body must match the digest its sender declared.
+fn receive_file(
+    mut request: Request,
+    limits: &HttpLimits,
+    dir: &Path,
+    filename: &str,
+    body: UploadBody,
+    reply: Option<&PeerReply>,
+) -> Result<bool, KayaError> {
+    fs::create_dir_all(dir)?;
+    let dest = dir.join(filename);
+
+    // A raw body is checked against its digest before it is given its name
+    let written = upload_body(&mut request, limits).and_then(|reader| match &body {
+        UploadBody::Raw(declared) => {
+            store::write_immutable_digest(dir, filename, reader, limits.max_body_bytes, declared)
+        }
+        UploadBody::Multipart(boundary) => {
+            let part = multipart::file_part(reader, boundary, "file")?;
+            store::write_immutable(dir, filename, part, limits.max_body_bytes)
+        }
+    });
+    let created = match written {
+        Ok(WriteOutcome::Created) => true,
+        Ok(WriteOutcome::Unchanged) | Err(StoreError::Conflict(_)) => false,
+        Err(e) => {
+            respond_store_error(request, &e);
+            return Err(e.into());
+        }
+    };
+
+    let stored = integrity::sha256_file(&dest)?;
+    let status = if created { 201 } else { 409 };
+    let mut response = Response::from_string("")
+        .with_status_code(StatusCode(status))
+        .with_header(digest_header(&stored));
+    for h in reply
+        .into_iter()
+        .flat_map(|r| r.headers(status, Some(&stored)))
+    {
+        response.add_header(h);
+    }
+    let _ = request.respond(response);
+    Ok(created)
+}
+
+/// The request a peer response answers, which its signature is bound to.
+struct PeerReply<'a> {
+    secret: &'a str,
+    path: &'a str,
+    nonce: &'a str,
+}
+
+impl PeerReply<'_> {
+    /// Headers signing a response with `status` over `sha256`.
+    fn headers(&self, status: u16, sha256: Option<&str>) -> [Header; 2] {
+        let time = Utc::now().timestamp();
+        let signature =
+            peer::sign_response(self.secret, status, self.path, time, self.nonce, sha256);
+        [
+            Header::from_bytes(peer::TIME_HEADER, time.to_string()).unwrap(),
+            Header::from_bytes(peer::SIGNATURE_HEADER, signature).unwrap(),
+        ]
+    }
+}
+
+/// Nonces of peer requests accepted recently, shared by every peer worker.
+static PEER_NONCES: OnceLock<NonceCache> = OnceLock::new();
+
+/// Handles a request on the peer listener. Every request must be signed with the
+/// shared secret; there are no unauthenticated routes.
+fn handle_peer_request(request: Request, limits: &HttpLimits) {
+    let method = request.method().clone();
+    let url = request.url().to_string();
+
+    let secret = match load_config() {
+        Ok(Config {
+            peer_secret: Some(secret),
+            ..
+        }) => secret,
+        Ok(_) => {
+            respond_error(request, 503, "Peer sync is not configured");
+            return;
+        }
+        Err(e) => {
+            respond_error(request, 500, &e.to_string());
+            return;
+        }
+    };
+    let declared = header_value(&request, "Repr-Digest").and_then(integrity::parse_sha256_field);
+    let headers = PeerHeaders {
+        time: header_value(&request, peer::TIME_HEADER),
+        nonce: header_value(&request, peer::NONCE_HEADER),
+        signature: header_value(&request, peer::SIGNATURE_HEADER),
+    };
+    let now = Utc::now().timestamp();
+    // Only a correctly signed request's nonce is remembered
+    let verified = peer::verify(
+        &secret,
+        method.as_str(),
+        &url,
+        headers,
+        declared.as_deref(),
+        now,
+    )
+    .and_then(|()| {
+        PEER_NONCES
+            .get_or_init(NonceCache::default)
+            .check(headers.nonce.unwrap_or_default(), now)
+    });
+    if let Err(e) = verified {
+        log::warn!("Rejected peer {} {}: {}", method, url, e);
+        respond_error(request, 401, &e.to_string());
+        return;
+    }
+    let nonce = headers.nonce.unwrap_or_default().to_string();
+    let reply = PeerReply {
+        secret: &secret,
+        path: &url,
+        nonce: &nonce,
+    };
+
+    let target = url
+        .strip_prefix("/peer/")
+        .ok_or_else(not_found)
+        .and_then(decode_segments)
+        .and_then(|segments| {
+            if !peer::PEER_COLLECTIONS.contains(&segments[0].as_str()) {
+                return Err(not_found());
+            }
+            api_target(&segments)
+        });
+    match target {
+        Ok(target) => serve_api_target(
+            request,
+            limits,
+            target,
+            &peer::PEER_COLLECTIONS,
+            declared.map(UploadBody::Raw),
+            Some(&reply),
+        ),
+        Err((status, msg)) => respond_error(request, status, &msg),
+    }
+}
+
+/// Basic-auth logins to the API, shared by every API worker.
+static API_LOGINS: OnceLock<PasswordGuard> = OnceLock::new();
+
+/// Whether `request` carries a paired bearer token, or Basic credentials for the
+/// API account.
+fn api_login(request: &Request, config: &Config, email: &str) -> Login {
+    let refused_unless = |ok| if ok { Login::Accepted } else { Login::Refused };
+    let Some(header) = header_value(request, "Authorization") else {
+        return Login::Refused;
+    };
+    if auth::bearer_token(header).is_some() {
+        return refused_unless(is_authorized(request));
+    }
+    match (auth::basic_credentials(header), &config.api_password_hash) {
+        (Some((user, password)), Some(hash)) if user.eq_ignore_ascii_case(email) => {
+            let client = request
+                .remote_addr()
+                .map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |a| a.ip());
+            API_LOGINS.get_or_init(PasswordGuard::default).check(
+                client,
+                hash,
+                &password,
+                Instant::now(),
+            )
+        }
+        _ => Login::Refused,
+    }
+}
+
+/// Handles a request on the Kaya-Server-compatible listener: the same routes and
+/// semantics the sync client expects from a server, for the configured account.
+fn handle_api_request(request: Request, limits: &HttpLimits) {
+    let method = request.method().clone();
+    let url = request.url().to_string();
+
+    let config = match load_config() {
+        Ok(config) => config,
+        Err(e) => {
+            respond_error(request, 500, &e.to_string());
+            return;
+        }
+    };
+    let Some(email) = config.api_email.clone() else {
+        respond_error(
+            request,
+            503,
+            "No API account; run `savebutton-daemon api-user`",
+        );
+        return;
+    };
+    match api_login(&request, &config, &email) {
+        Login::Accepted => {}
+        Login::Throttled => {
+            log::warn!("Throttled API {} {} after failed logins", method, url);
+            let response = Response::from_string("Too many failed logins")
+                .with_status_code(StatusCode(429))
+                .with_header(
+                    Header::from_bytes("Retry-After", auth::LOGIN_WINDOW_SECS.to_string()).unwrap(),
+                );
+            let _ = request.respond(response);
+            return;
+        }
+        Login::Refused => {
+            log::warn!("Rejected unauthorized API {} {}", method, url);
+            let response = Response::from_string("Unauthorized")
+                .with_status_code(StatusCode(401))
+                .with_header(
+                    Header::from_bytes("WWW-Authenticate", "Basic realm=\"kaya\"").unwrap(),
+                );
+            let _ = request.respond(response);
+            return;
+        }
+    }
+
+    // Listing queries (`limit`, `since`) are ignored: every listing is complete,
+    // which clients treat like a server without incremental listings
+    let path = without_query(&url);
+    let target = path
+        .strip_prefix("/api/v1/")
+        .ok_or_else(not_found)
+        .and_then(decode_segments)
+        .and_then(|segments| match segments.split_first() {
+            Some((user, rest)) if user.eq_ignore_ascii_case(&email) => api_target(rest),
+            _ => Err(not_found()),
+        });
+    let body = header_value(&request, "Content-Type")
+        .and_then(multipart::boundary)
+        .map(UploadBody::Multipart);
+    match target {
+        Ok(target) => serve_api_target(
+            request,
+            limits,
+            target,
+            &["anga", "meta", "words"],
+            body,
+            None,
+        ),
+        Err((status, msg)) => respond_error(request, status, &msg),
+    }
+}
+
+/// Serves `handler` on a listener of its own at `addr`, exiting if the address
+/// cannot be bound. Returns the port it listens on.
+fn spawn_listener(
+    name: &str,
+    addr: &str,
+    limits: &Arc<HttpLimits>,
+    handler: fn(Request, &HttpLimits),
+) -> u16 {
+    let server = match Server::http(addr) {
+        Ok(s) => Arc::new(s),
+        Err(e) => {
+            log::error!("Failed to start {} listener on {}: {}", name, addr, e);
+            eprintln!("Failed to start {} listener on {}: {}", name, addr, e);
+            std::process::exit(1);
+        }
+    };
+    log::info!("Serving the {} API on {}", name, addr);
+    for i in 0..LISTENER_WORKERS {
+        let server = server.clone();
+        let limits = limits.clone();
+        thread::Builder::new()
+            .name(format!("{}-worker-{}", name, i))
+            .spawn(move || loop {
+                let request = match server.recv() {
+                    Ok(r) => r,
+                    Err(e) => {
+                        log::error!("Failed to receive request: {}", e);
+                        continue;
+                    }
+                };
+                let result = panic::catch_unwind(AssertUnwindSafe(|| handler(request, &limits)));
+                if result.is_err() {
+                    log::error!("Request handler panicked");
+                }
+            })
+            .expect("Failed to spawn HTTP worker");
+    }
+    server.server_addr().to_ip().map_or(0, |a| a.port())
+}
+
+/// Peers announced by discovery beacons, with when each was last heard.
+#[derive(Default)]
+struct Discovered {
+    seen: Mutex<HashMap<String, Instant>>,
+}
+
+impl Discovered {
+    fn note(&self, url: String) {
+        let mut seen = self.seen.lock().unwrap_or_else(PoisonError::into_inner);
+        if seen.insert(url.clone(), Instant::now()).is_none() {
+            log::info!("Discovered peer {}", url);
+        }
+    }
+
+    /// Peers heard from recently enough to still be around.
+    fn urls(&self) -> Vec<String> {
+        let mut seen = self.seen.lock().unwrap_or_else(PoisonError::into_inner);
+        seen.retain(|_, heard| heard.elapsed() < DISCOVERY_EXPIRY);
+        seen.keys().cloned().collect()
+    }
+}
+
+/// Broadcasts a beacon for `peer_port` (if this daemon serves peers) every
+/// [`BEACON_INTERVAL`], and notes beacons from daemons sharing the secret.
+fn run_discovery(peer_port: Option<u16>, discovered: Arc<Discovered>) {
+    let instance = format!("{:016x}", rand::random::<u64>());
+
+    let listener_instance = instance.clone();
+    thread::spawn(move || {
+        let socket = match UdpSocket::bind(("0.0.0.0", peer::DISCOVERY_PORT)) {
+            Ok(s) => s,
+            Err(e) => {
+                log::warn!(
+                    "Peer discovery cannot listen on UDP port {}: {}",
+                    peer::DISCOVERY_PORT,
+                    e
+                );
+                return;
+            }
+        };
+        let nonces = NonceCache::default();
+        let mut buf = [0u8; 512];
+        loop {
+            let Ok((n, from)) = socket.recv_from(&mut buf) else {
+                continue;
+            };
+            let Some(beacon) = peer::Beacon::parse(&buf[..n]) else {
+                continue;
+            };
+            let Some(secret) = load_config().ok().and_then(|c| c.peer_secret) else {
+                continue;
+            };
+            if beacon.instance == listener_instance {
+                continue;
+            }
+            // A beacon repeated by another host is refused like a replayed request
+            let now = Utc::now().timestamp();
+            match beacon
+                .verify(&secret, now)
+                .and_then(|()| nonces.check(&beacon.nonce, now))
+            {
+                Ok(()) => discovered.note(format!("http://{}:{}", from.ip(), beacon.port)),
+                Err(e) => log::debug!("Ignored discovery beacon from {}: {}", from, e),
+            }
+        }
+    });
+
+    let Some(port) = peer_port else {
+        return;
+    };
+    thread::spawn(move || {
+        let socket = match UdpSocket::bind(("0.0.0.0", 0)).and_then(|s| {
+            s.set_broadcast(true)?;
+            Ok(s)
+        }) {
+            Ok(s) => s,
+            Err(e) => {
+                log::warn!("Peer discovery cannot broadcast: {}", e);
+                return;
+            }
+        };
+        loop {
+            if let Some(secret) = load_config().ok().and_then(|c| c.peer_secret) {
+                let beacon = peer::Beacon::new(&secret, &instance, port, Utc::now().timestamp());
+                if let Err(e) = socket.send_to(
+                    beacon.encode().as_bytes(),
+                    ("255.255.255.255", peer::DISCOVERY_PORT),
+                ) {
+                    log::debug!("Failed to send discovery beacon: {}", e);
+                }
+            }
+            thread::sleep(BEACON_INTERVAL);
+        }
+    });
 }
 
 // ---------------------------------------------------------------------------
 // Main
 // ---------------------------------------------------------------------------
 
+fn run_command(command: Command, cli: &Cli) -> Result<(), KayaError> {
+    ensure_directories()?;
+    let kaya_dir = get_kaya_dir();
+
+    match command {
+        Command::Pair => {
+            let code = auth::issue_pairing_code(&kaya_dir)?;
+            println!("Pairing code: {}", code);
+            println!(
+                "Enter this code in the Save Button preferences within {} minutes.",
+                auth::PAIRING_CODE_TTL_MINUTES
+            );
+        }
+        Command::Tokens {
+            action: TokenAction::List,
+        } => {
+            let tokens = auth::list_tokens(&kaya_dir)?;
+            if tokens.is_empty() {
+                println!("No paired tokens.");
+            }
+            for t in tokens {
+                println!(
+                    "{}  {}  {}",
+                    t.id,
+                    t.created.format("%Y-%m-%dT%H:%M:%SZ"),
+                    t.label
+                );
+            }
+        }
+        Command::Tokens {
+            action: TokenAction::Revoke { id },
+        } => {
+            if auth::revoke_token(&kaya_dir, &id)? {
+                println!("Revoked token {}", id);
+            } else {
+                return Err(KayaError::Config(format!("No token with id {}", id)));
+            }
+        }
+        Command::Origins {
+            action: OriginAction::List,
+        } => {
+            for origin in load_config()?.allowed_origins {
+                println!("{}", origin);
+            }
+        }
+        Command::Origins {
+            action: OriginAction::Add { origin },
+        } => {
+            allow_origin(origin.trim_end_matches('/'))?;
+        }
+        Command::Origins {
+            action: OriginAction::Remove { origin },
+        } => {
+            let mut config = load_config()?;
+            let before = config.allowed_origins.len();
+            config.allowed_origins.retain(|o| o != &origin);
+            if config.allowed_origins.len() == before {
+                return Err(KayaError::Config(format!("{} is not allowed", origin)));
+            }
+            save_config(&config)?;
+        }
+        Command::ApiUser { email } => {
+            let mut password = String::new();
+            io::stdin().read_line(&mut password)?;
+            let password = password.trim_end_matches(['\r', '\n']);
+            if password.is_empty() {
+                return Err(KayaError::Config("Empty password".to_string()));
+            }
+            let mut config = load_config()?;
+            config.api_email = Some(email);
+            config.api_password_hash = Some(auth::hash_password(password));
+            save_config(&config)?;
+        }
+        Command::Peers {
+            action: PeerAction::List,
+        } => {
+            for url in load_config()?.peers {
+                println!("{}", url);
+            }
+        }
+        Command::Peers {
+            action: PeerAction::Add { url },
+        } => {
+            let url = url.trim_end_matches('/').to_string();
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(KayaError::Config(format!(
+                    "{} is not an http:// or https:// URL",
+                    url
+                )));
+            }
+            let mut config = load_config()?;
+            if !config.peers.contains(&url) {
+                config.peers.push(url);
+                save_config(&config)?;
+            }
+        }
+        Command::Peers {
+            action: PeerAction::Remove { url },
+        } => {
+            let mut config = load_config()?;
+            let before = config.peers.len();
+            config.peers.retain(|p| p != url.trim_end_matches('/'));
+            if config.peers.len() == before {
+                return Err(KayaError::Config(format!("{} is not a peer", url)));
+            }
+            save_config(&config)?;
+        }
+        Command::Peers {
+            action: PeerAction::Secret { secret },
+        } => {
+            let mut config = load_config()?;
+            match (secret, &config.peer_secret) {
+                (Some(secret), _) => {
+                    config.peer_secret = Some(secret);
+                    save_config(&config)?;
+                }
+                (None, Some(secret)) => println!("{}", secret),
+                (None, None) => {
+                    let secret = peer::generate_secret();
+                    println!("{}", secret);
+                    config.peer_secret = Some(secret);
+                    save_config(&config)?;
+                }
+            }
+        }
+        Command::Verify => {
+            let report = verify()?;
+            for m in &report.mismatches {
+                println!(
+                    "MISMATCH  {}  local {}  server {}",
+                    m.path, m.local, m.server
+                );
+            }
+            println!(
+                "Checked {} file(s): {} verified, {} without a server digest, {} mismatched",
+                report.checked,
+                report.verified,
+                report.unverified,
+                report.mismatches.len()
+            );
+            if !report.mismatches.is_empty() {
+                return Err(KayaError::Integrity(report.mismatches.len()));
+            }
+        }
+        Command::Sync {
+            to: Some(target), ..
+        } => {
+            setup_logging();
+            ensure_directories()?;
+            let engine = SyncEngine::new(
+                cli.sync_transfers,
+                cli.sync_bandwidth_kb.saturating_mul(1024),
+            )?;
+            let summary = sync_with_directory(&engine, &target)?;
+
+            let mut total = SyncCounts::default();
+            println!("Synced with {}:", target.display());
+            for (collection, counts) in summary {
+                let Some(counts) = counts else {
+                    println!("  {:<6} listing failed", collection);
+                    total.failed += 1;
+                    continue;
+                };
+                println!(
+                    "  {:<6} {} downloaded, {} uploaded, {} failed, {} deferred",
+                    collection, counts.downloaded, counts.uploaded, counts.failed, counts.deferred
+                );
+                total.downloaded += counts.downloaded;
+                total.uploaded += counts.uploaded;
+                total.failed += counts.failed;
+                total.deferred += counts.deferred;
+            }
+            println!(
+                "  total  {} downloaded, {} uploaded, {} failed, {} deferred",
+                total.downloaded, total.uploaded, total.failed, total.deferred
+            );
+            if total.failed > 0 {
+                return Err(KayaError::Incomplete(total.failed));
+            }
+        }
+        Command::Sync { full, to: None } => {
+            setup_logging();
+            let config = load_config()?;
+            let peers = config.peer_secret.is_some() && !config.peers.is_empty();
+            if server_credentials(&config)?.is_none() && !peers {
+                return Err(KayaError::Config("Sync is not configured".to_string()));
+            }
+            let engine = SyncEngine::new(
+                cli.sync_transfers,
+                cli.sync_bandwidth_kb.saturating_mul(1024),
+            )?;
+            sync_with_server(&engine, full)?;
+            sync_with_peers(&engine, &Discovered::default(), full)?;
+        }
+        Command::Search { query, limit, json } => {
+            let q = query.join(" ");
+            let query = Query::parse(&q)?;
+            // The daemon may be running and hold a newer index; catch up with
+            // the files either way
+            let mut index = SearchIndex::load(&kaya_dir);
+            index.refresh()?;
+            index.save()?;
+            let hits = index.search(&query, limit.max(1));
+            if json {
+                println!("{}", serde_json::json!({ "query": q, "results": hits }));
+            } else {
+                if hits.is_empty() {
+                    println!("No matches.");
+                }
+                for hit in hits {
+                    println!("{}  ({:.2})", hit.anga, hit.score);
+                    if !hit.snippet.is_empty() {
+                        println!("    {}", hit.snippet);
+                    }
+                }
+            }
+        }
+        Command::MigrateFilenames => {
+            setup_logging();
+            migrate_filenames()?;
+        }
+        Command::Failures => {
+            let failures = FailureLog::load(&kaya_dir)?;
+            let mut any = false;
+            for e in failures.entries() {
+                any = true;
+                println!(
+                    "{}  {}  {:?}  failed {} time(s), retry after {}  ({})",
+                    e.last_attempt.format("%Y-%m-%dT%H:%M:%SZ"),
+                    e.collection,
+                    e.name,
+                    e.failures,
+                    e.retry_after.format("%Y-%m-%dT%H:%M:%SZ"),
+                    e.last_error
+                );
+            }
+            if !any {
+                println!("No failed transfers.");
+            }
+        }
+        Command::Quarantine => {
+            let entries = Quarantine::new(&kaya_dir).entries()?;
+            if entries.is_empty() {
+                println!("No quarantined names.");
+            }
+            for e in entries {
+                println!(
+                    "{}  {}  {:?}  ({})",
+                    e.time.format("%Y-%m-%dT%H:%M:%SZ"),
+                    e.collection,
+                    e.name,
+                    e.reason
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
 fn main() {
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
+    if let Some(root) = &cli.root {
+        let root = if root.is_absolute() {
+            root.clone()
+        } else {
+            std::env::current_dir()
+                .map(|d| d.join(root))
+                .unwrap_or_else(|_| root.clone())
+        };
+        let _ = KAYA_ROOT.set(root);
+    }
+
+    if let Some(command) = cli.command.take() {
+        if let Err(e) = run_command(command, &cli) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
 
     setup_logging();
 
@@ -783,6 +3011,14 @@ fn main() {
         std::process::exit(1);
     }
 
+    if !get_migration_marker_path().exists() {
+        if let Err(e) = migrate_filenames() {
+            log::error!("Failed to migrate filenames: {}", e);
+        }
+    }
+
+    let _ = SEARCH.set(Mutex::new(SearchIndex::load(&get_kaya_dir())));
+
     let addr = format!("127.0.0.1:{}", cli.port);
     let server = match Server::http(&addr) {
         Ok(s) => s,
@@ -796,20 +3032,91 @@ fn main() {
     log::info!("Save Button daemon listening on {}", addr);
     println!("Save Button daemon listening on {}", addr);
 
+    if matches!(auth::list_tokens(&get_kaya_dir()), Ok(t) if t.is_empty()) {
+        log::info!("No paired extensions yet; run `savebutton-daemon pair` to pair one");
+    }
+
     let running = Arc::new(AtomicBool::new(true));
     let running_clone = running.clone();
 
-    // Background sync thread: sync every 60 seconds
+    let engine = match SyncEngine::new(
+        cli.sync_transfers,
+        cli.sync_bandwidth_kb.saturating_mul(1024),
+    ) {
+        Ok(e) => e,
+        Err(e) => {
+            log::error!("Failed to create sync client: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let limits = Arc::new(HttpLimits {
+        request_timeout: Duration::from_secs(cli.request_timeout),
+        uploads: InFlightLimit::new(cli.max_uploads.max(1)),
+        max_body_bytes: cli.max_body_mb.saturating_mul(1024 * 1024),
+    });
+
+    // Remote listeners serve only their own signed or authenticated routes, on
+    // their own addresses, so the local API never leaves localhost
+    let peer_port = cli
+        .peer_listen
+        .as_deref()
+        .map(|addr| spawn_listener("peer", addr, &limits, handle_peer_request));
+    if let Some(addr) = cli.api_listen.as_deref() {
+        spawn_listener("api", addr, &limits, handle_api_request);
+    }
+
+    let discovered = Arc::new(Discovered::default());
+    if cli.discover {
+        run_discovery(peer_port, discovered.clone());
+    }
+
+    // Background sync thread: sync with the server and peers every 60 seconds,
+    // then index whatever arrived
     thread::spawn(move || {
+        refresh_search_index();
         while running_clone.load(Ordering::Relaxed) {
-            if let Err(e) = sync_with_server() {
+            if let Err(e) = sync_with_server(&engine, false) {
                 log::error!("Sync error: {}", e);
             }
+            if let Err(e) = sync_with_peers(&engine, &discovered, false) {
+                log::error!("Peer sync error: {}", e);
+            }
+            refresh_search_index();
             thread::sleep(Duration::from_secs(60));
         }
     });
 
-    for request in server.incoming_requests() {
-        handle_request(request);
+    // HTTP worker pool: each worker pulls requests off the shared server, so a slow
+    // upload only ties up its own worker and `/health` stays responsive.
+    let server = Arc::new(server);
+
+    let workers: Vec<_> = (0..cli.workers.max(1))
+        .map(|i| {
+            let server = server.clone();
+            let limits = limits.clone();
+            thread::Builder::new()
+                .name(format!("http-worker-{}", i))
+                .spawn(move || loop {
+                    let request = match server.recv() {
+                        Ok(r) => r,
+                        Err(e) => {
+                            log::error!("Failed to receive request: {}", e);
+                            continue;
+                        }
+                    };
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                        handle_request(request, &limits);
+                    }));
+                    if result.is_err() {
+                        log::error!("Request handler panicked");
+                    }
+                })
+                .expect("Failed to spawn HTTP worker")
+        })
+        .collect();
+
+    for worker in workers {
+        let _ = worker.join();
     }
 }
@@ -0,0 +1,186 @@
+//! Streaming `multipart/form-data` bodies.
+//!
+//! The Kaya Server API uploads each file as the `file` part of a multipart form.
+//! [`file_part`] skips to that part and returns a reader over its content, so an
+//! upload streams straight to disk without being held in memory.
+
+use std::io::{self, Read};
+
+/// Longest run of part headers accepted, in bytes.
+const MAX_HEADER_BYTES: usize = 8 * 1024;
+const READ_CHUNK: usize = 16 * 1024;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// The boundary from a `multipart/form-data` `Content-Type` value.
+pub fn boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    if !params
+        .next()?
+        .trim()
+        .eq_ignore_ascii_case("multipart/form-data")
+    {
+        return None;
+    }
+    params.find_map(|p| {
+        let (name, value) = p.trim().split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("boundary")
+            .then(|| value.trim().trim_matches('"').to_string())
+            .filter(|b| !b.is_empty())
+    })
+}
+
+/// The `name` parameter of a part's `Content-Disposition` header.
+fn part_name(headers: &str) -> Option<&str> {
+    headers.split("\r\n").find_map(|line| {
+        let (field, value) = line.split_once(':')?;
+        if !field.trim().eq_ignore_ascii_case("content-disposition") {
+            return None;
+        }
+        value.split(';').find_map(|p| {
+            let (name, value) = p.trim().split_once('=')?;
+            (name.trim() == "name").then(|| value.trim().trim_matches('"'))
+        })
+    })
+}
+
+/// Reads the content of one part of a multipart body.
+pub struct FilePart<R> {
+    body: R,
+    /// Bytes read from `body` but not yet consumed.
+    buf: Vec<u8>,
+    /// `\r\n--{boundary}`, which ends every part.
+    delimiter: Vec<u8>,
+    done: bool,
+}
+
+impl<R: Read> FilePart<R> {
+    /// Reads more of the body into `buf`. Returns false at the end of the body.
+    fn fill(&mut self) -> io::Result<bool> {
+        let start = self.buf.len();
+        self.buf.resize(start + READ_CHUNK, 0);
+        let n = loop {
+            match self.body.read(&mut self.buf[start..]) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => break result,
+            }
+        };
+        self.buf.truncate(start + n.as_ref().copied().unwrap_or(0));
+        Ok(n? > 0)
+    }
+
+    /// Consumes everything up to and including the next `pattern`, returning what
+    /// came before it. Fails if more than `limit` bytes come first.
+    fn take_until(&mut self, pattern: &[u8], limit: usize) -> io::Result<Vec<u8>> {
+        let mut searched = 0;
+        loop {
+            if let Some(pos) = find(&self.buf[searched..], pattern) {
+                let pos = searched + pos;
+                let before = self.buf[..pos].to_vec();
+                self.buf.drain(..pos + pattern.len());
+                return Ok(before);
+            }
+            searched = self.buf.len().saturating_sub(pattern.len() - 1);
+            if self.buf.len() > limit {
+                return Err(invalid("multipart part headers too long"));
+            }
+            if !self.fill()? {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+
+    /// Skips content up to and including the next delimiter, without buffering it.
+    fn skip_part(&mut self) -> io::Result<()> {
+        loop {
+            if let Some(pos) = find(&self.buf, &self.delimiter) {
+                self.buf.drain(..pos + self.delimiter.len());
+                return Ok(());
+            }
+            let keep = self.delimiter.len() - 1;
+            let drop = self.buf.len().saturating_sub(keep);
+            self.buf.drain(..drop);
+            if !self.fill()? {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+
+    /// After a delimiter: true if another part follows, false at the closing `--`.
+    fn next_part(&mut self) -> io::Result<bool> {
+        while self.buf.len() < 2 {
+            if !self.fill()? {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+        match &self.buf[..2] {
+            b"--" => Ok(false),
+            b"\r\n" => {
+                self.buf.drain(..2);
+                Ok(true)
+            }
+            _ => Err(invalid("malformed multipart delimiter")),
+        }
+    }
+}
+
+impl<R: Read> Read for FilePart<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.done || out.is_empty() {
+                return Ok(0);
+            }
+            // Everything before the delimiter, or before a tail that might be the
+            // start of one, is content
+            let available = match find(&self.buf, &self.delimiter) {
+                Some(pos) => {
+                    if pos == 0 {
+                        self.done = true;
+                        continue;
+                    }
+                    pos
+                }
+                None => self.buf.len().saturating_sub(self.delimiter.len() - 1),
+            };
+            if available > 0 {
+                let n = available.min(out.len());
+                out[..n].copy_from_slice(&self.buf[..n]);
+                self.buf.drain(..n);
+                return Ok(n);
+            }
+            if !self.fill()? {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Skips to the part named `field` in a multipart `body` and returns a reader over
+/// its content. Fails with `InvalidData` if the body is malformed or has no such
+/// part.
+pub fn file_part<R: Read>(body: R, boundary: &str, field: &str) -> io::Result<FilePart<R>> {
+    let mut part = FilePart {
+        body,
+        // The first delimiter may start the body, without the leading CRLF
+        buf: b"\r\n".to_vec(),
+        delimiter: format!("\r\n--{}", boundary).into_bytes(),
+        done: false,
+    };
+    part.skip_part()?;
+    while part.next_part()? {
+        let headers = part.take_until(b"\r\n\r\n", MAX_HEADER_BYTES)?;
+        let headers = String::from_utf8_lossy(&headers);
+        if part_name(&headers) == Some(field) {
+            return Ok(part);
+        }
+        part.skip_part()?;
+    }
+    Err(invalid(&format!("no {} part in multipart body", field)))
+}
@@ -0,0 +1,491 @@
+//! Peer-to-peer sync between daemons on a LAN (ADR 0001).
+//!
+//! A daemon started with `--peer-listen` serves its anga, meta and words under
+//! `/peer/...`, shaped like the Kaya Server API and with the same listings. Every
+//! request is signed with a secret shared by the paired daemons:
+//!
+//! ```text
+//! Kaya-Peer-Time: <unix seconds>
+//! Kaya-Peer-Nonce: <random, unique per request>
+//! Kaya-Peer-Signature: base64(HMAC-SHA256(secret, "{method}\n{path}\n{time}\n{nonce}\n{sha256}"))
+//! ```
+//!
+//! where `sha256` is the hex digest an upload declares in `Repr-Digest`, or empty.
+//! Requests more than [`MAX_CLOCK_SKEW_SECS`] away from the receiver's clock are
+//! refused, and so is a nonce already seen while its request could still be valid
+//! ([`NonceCache`]), so a captured request cannot be replayed. Uploads never
+//! replace a file; a `409` carries the stored file's digest.
+//!
+//! Responses are signed the same way, with the status code in place of the method
+//! and the request's nonce, over the SHA-256 of a listing's body or the file
+//! digest in `Repr-Digest` ([`sign_response`]). A response signed for another
+//! request does not verify, so only a daemon holding the secret can answer. Every
+//! collection is listed before anything is uploaded to it, so nothing is sent to
+//! a host that cannot.
+//!
+//! With `--discover`, daemons also broadcast a signed [`Beacon`] over UDP so peers
+//! sharing the secret find each other without being configured by address.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use rand::RngCore;
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::header::HeaderMap;
+use reqwest::{Method, StatusCode};
+use ring::hmac;
+use std::collections::{HashSet, VecDeque};
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+use crate::backend::{self, upload_timeout, BackendError, PutOutcome, SyncBackend};
+use crate::codec;
+use crate::download::{Downloaded, PartialStore};
+use crate::integrity::{self, Mismatch};
+use crate::listing::Listing;
+use crate::parse_server_file_listing;
+use crate::transfer::{ThrottledReader, TokenBucket};
+
+pub const TIME_HEADER: &str = "kaya-peer-time";
+pub const NONCE_HEADER: &str = "kaya-peer-nonce";
+pub const SIGNATURE_HEADER: &str = "kaya-peer-signature";
+/// How far a request's time may be from the receiver's clock, in seconds.
+pub const MAX_CLOCK_SKEW_SECS: i64 = 300;
+/// UDP port discovery beacons are broadcast to.
+pub const DISCOVERY_PORT: u16 = 21422;
+/// Collections peers exchange.
+pub const PEER_COLLECTIONS: [&str; 3] = ["anga", "meta", "words"];
+
+const SECRET_BYTES: usize = 32;
+const NONCE_BYTES: usize = 16;
+/// Longest nonce accepted, so remembering them stays cheap.
+const MAX_NONCE_LEN: usize = 64;
+const BEACON_PREFIX: &str = "kaya-peer";
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PeerAuthError {
+    #[error("missing peer signature")]
+    Missing,
+    #[error("request time is too far from this daemon's clock")]
+    Stale,
+    #[error("bad peer signature")]
+    BadSignature,
+    #[error("request was already received")]
+    Replayed,
+}
+
+/// A request's peer authentication headers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PeerHeaders<'a> {
+    pub time: Option<&'a str>,
+    pub nonce: Option<&'a str>,
+    pub signature: Option<&'a str>,
+}
+
+/// A new random shared secret.
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; SECRET_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    BASE64.encode(bytes)
+}
+
+/// A new random request nonce.
+pub fn generate_nonce() -> String {
+    let mut bytes = [0u8; NONCE_BYTES];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn message(method: &str, path: &str, time: i64, nonce: &str, sha256: Option<&str>) -> String {
+    format!(
+        "{}\n{}\n{}\n{}\n{}",
+        method.to_ascii_uppercase(),
+        path,
+        time,
+        nonce,
+        sha256.unwrap_or("")
+    )
+}
+
+/// The signature for a request to `path` (including any query) at `time`.
+pub fn sign(
+    secret: &str,
+    method: &str,
+    path: &str,
+    time: i64,
+    nonce: &str,
+    sha256: Option<&str>,
+) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let tag = hmac::sign(&key, message(method, path, time, nonce, sha256).as_bytes());
+    BASE64.encode(tag.as_ref())
+}
+
+/// Checks a request's time, nonce and signature headers against `secret` at
+/// `now`. Whether the nonce is fresh is up to a [`NonceCache`].
+pub fn verify(
+    secret: &str,
+    method: &str,
+    path: &str,
+    headers: PeerHeaders,
+    sha256: Option<&str>,
+    now: i64,
+) -> Result<(), PeerAuthError> {
+    let (Some(time), Some(nonce), Some(signature)) =
+        (headers.time, headers.nonce, headers.signature)
+    else {
+        return Err(PeerAuthError::Missing);
+    };
+    if nonce.is_empty() || nonce.len() > MAX_NONCE_LEN {
+        return Err(PeerAuthError::Missing);
+    }
+    let time: i64 = time.trim().parse().map_err(|_| PeerAuthError::Missing)?;
+    if (now - time).abs() > MAX_CLOCK_SKEW_SECS {
+        return Err(PeerAuthError::Stale);
+    }
+    let tag = BASE64
+        .decode(signature.trim())
+        .map_err(|_| PeerAuthError::BadSignature)?;
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    hmac::verify(
+        &key,
+        message(method, path, time, nonce, sha256).as_bytes(),
+        &tag,
+    )
+    .map_err(|_| PeerAuthError::BadSignature)
+}
+
+/// The signature for a response with `status` to a request for `path` that
+/// carried `nonce`, over `sha256`: a listing body's digest, or the file digest the
+/// response declares.
+pub fn sign_response(
+    secret: &str,
+    status: u16,
+    path: &str,
+    time: i64,
+    nonce: &str,
+    sha256: Option<&str>,
+) -> String {
+    sign(secret, &status.to_string(), path, time, nonce, sha256)
+}
+
+/// Checks a response's time and signature headers for a request to `path` that
+/// carried `nonce`. The response's own nonce header, if any, is ignored.
+pub fn verify_response(
+    secret: &str,
+    status: u16,
+    path: &str,
+    nonce: &str,
+    headers: PeerHeaders,
+    sha256: Option<&str>,
+    now: i64,
+) -> Result<(), PeerAuthError> {
+    let headers = PeerHeaders {
+        nonce: Some(nonce),
+        ..headers
+    };
+    verify(secret, &status.to_string(), path, headers, sha256, now)
+}
+
+/// Nonces of recently accepted requests.
+///
+/// A request is accepted while its time is within [`MAX_CLOCK_SKEW_SECS`] of the
+/// receiver's clock, so a nonce is remembered for twice that after it is seen.
+#[derive(Debug, Default)]
+pub struct NonceCache {
+    seen: Mutex<NonceWindow>,
+}
+
+#[derive(Debug, Default)]
+struct NonceWindow {
+    /// Nonces in the order they were seen, with when.
+    order: VecDeque<(i64, String)>,
+    nonces: HashSet<String>,
+}
+
+impl NonceCache {
+    /// Records `nonce` as seen at `now`, failing if it was seen before.
+    pub fn check(&self, nonce: &str, now: i64) -> Result<(), PeerAuthError> {
+        let mut window = self.seen.lock().unwrap_or_else(|e| e.into_inner());
+        while let Some((seen, _)) = window.order.front() {
+            if now - seen <= 2 * MAX_CLOCK_SKEW_SECS {
+                break;
+            }
+            let (_, expired) = window.order.pop_front().expect("front exists");
+            window.nonces.remove(&expired);
+        }
+        if !window.nonces.insert(nonce.to_string()) {
+            return Err(PeerAuthError::Replayed);
+        }
+        window.order.push_back((now, nonce.to_string()));
+        Ok(())
+    }
+}
+
+/// A discovery announcement: "a daemon with this secret serves peers on `port`".
+///
+/// It is signed like a request, over the instance and port, with its own time and
+/// nonce, so a receiver checks it with [`Beacon::verify`] and a [`NonceCache`]
+/// rather than trusting whoever repeats it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Beacon {
+    /// Random per process, so a daemon can ignore its own beacons.
+    pub instance: String,
+    pub port: u16,
+    pub time: i64,
+    pub nonce: String,
+    pub signature: String,
+}
+
+impl Beacon {
+    /// A beacon from `instance` for `port`, signed with `secret` at `now`.
+    pub fn new(secret: &str, instance: &str, port: u16, now: i64) -> Beacon {
+        let nonce = generate_nonce();
+        let signature = sign(
+            secret,
+            BEACON_PREFIX,
+            &Self::subject(instance, port),
+            now,
+            &nonce,
+            None,
+        );
+        Beacon {
+            instance: instance.to_string(),
+            port,
+            time: now,
+            nonce,
+            signature,
+        }
+    }
+
+    fn subject(instance: &str, port: u16) -> String {
+        format!("{}:{}", instance, port)
+    }
+
+    /// Checks the beacon was signed with `secret`, recently enough at `now`.
+    /// Whether its nonce is fresh is up to a [`NonceCache`].
+    pub fn verify(&self, secret: &str, now: i64) -> Result<(), PeerAuthError> {
+        let time = self.time.to_string();
+        verify(
+            secret,
+            BEACON_PREFIX,
+            &Self::subject(&self.instance, self.port),
+            PeerHeaders {
+                time: Some(&time),
+                nonce: Some(&self.nonce),
+                signature: Some(&self.signature),
+            },
+            None,
+            now,
+        )
+    }
+
+    pub fn encode(&self) -> String {
+        format!(
+            "{} {} {} {} {} {}",
+            BEACON_PREFIX, self.instance, self.port, self.time, self.nonce, self.signature
+        )
+    }
+
+    pub fn parse(datagram: &[u8]) -> Option<Beacon> {
+        let text = std::str::from_utf8(datagram).ok()?;
+        let mut fields = text.split_whitespace();
+        if fields.next()? != BEACON_PREFIX {
+            return None;
+        }
+        let beacon = Beacon {
+            instance: fields.next()?.to_string(),
+            port: fields.next()?.parse().ok()?,
+            time: fields.next()?.parse().ok()?,
+            nonce: fields.next()?.to_string(),
+            signature: fields.next()?.to_string(),
+        };
+        fields.next().is_none().then_some(beacon)
+    }
+}
+
+/// Another daemon's `/peer` API.
+pub struct Peer {
+    client: Client,
+    base: String,
+    secret: String,
+    bandwidth: Arc<TokenBucket>,
+    partials: PartialStore,
+}
+
+impl Peer {
+    /// A peer at `url`, e.g. `http://192.168.1.20:21421`. Every byte transferred
+    /// is charged to `bandwidth`; interrupted downloads resume from
+    /// `kaya_dir/.partial`.
+    pub fn new(
+        client: Client,
+        url: &str,
+        secret: &str,
+        bandwidth: Arc<TokenBucket>,
+        kaya_dir: &Path,
+    ) -> Self {
+        Peer {
+            client,
+            base: url.trim_end_matches('/').to_string(),
+            secret: secret.to_string(),
+            bandwidth,
+            partials: PartialStore::new(kaya_dir),
+        }
+    }
+
+    fn path(segments: &[&str]) -> String {
+        let segments: Vec<String> = segments.iter().map(|s| codec::url_segment(s)).collect();
+        format!("/peer/{}", segments.join("/"))
+    }
+
+    /// A request to `segments`, signed for `sha256` if it uploads a body, with what
+    /// its response must be signed for.
+    fn request(
+        &self,
+        method: Method,
+        segments: &[&str],
+        sha256: Option<&str>,
+    ) -> (RequestBuilder, SentRequest) {
+        let path = Self::path(segments);
+        let time = chrono::Utc::now().timestamp();
+        let nonce = generate_nonce();
+        let signature = sign(&self.secret, method.as_str(), &path, time, &nonce, sha256);
+        let builder = self
+            .client
+            .request(method, format!("{}{}", self.base, path))
+            .header(TIME_HEADER, time.to_string())
+            .header(NONCE_HEADER, &nonce)
+            .header(SIGNATURE_HEADER, signature);
+        (builder, SentRequest { path, nonce })
+    }
+
+    /// Checks that the response to `sent` with `status` and `headers` was signed
+    /// with the secret over `sha256`.
+    fn verify_response(
+        &self,
+        sent: &SentRequest,
+        status: StatusCode,
+        headers: &HeaderMap,
+        sha256: Option<&str>,
+    ) -> Result<(), PeerAuthError> {
+        let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+        verify_response(
+            &self.secret,
+            status.as_u16(),
+            &sent.path,
+            &sent.nonce,
+            PeerHeaders {
+                time: header(TIME_HEADER),
+                nonce: None,
+                signature: header(SIGNATURE_HEADER),
+            },
+            sha256,
+            chrono::Utc::now().timestamp(),
+        )
+    }
+}
+
+/// What a response to a signed request must be signed for.
+struct SentRequest {
+    path: String,
+    nonce: String,
+}
+
+impl SyncBackend for Peer {
+    fn list(&self, dir: &[&str], _since: Option<&str>) -> Result<Listing, BackendError> {
+        let (request, sent) = self.request(Method::GET, dir, None);
+        let response = request.send()?;
+        if !response.status().is_success() {
+            return Err(BackendError::Status(response.status()));
+        }
+        let (status, headers) = (response.status(), response.headers().clone());
+        let body = response.bytes()?;
+        self.verify_response(
+            &sent,
+            status,
+            &headers,
+            Some(&integrity::sha256_bytes(&body)),
+        )?;
+        Ok(Listing {
+            names: parse_server_file_listing(&String::from_utf8_lossy(&body)),
+            cursor: None,
+            delta: false,
+        })
+    }
+
+    /// Asks for the file's signed digest first, and only keeps a body that matches
+    /// it.
+    fn get(&self, path: &[&str], dest: &Path) -> Result<Downloaded, BackendError> {
+        let sha256 = self.digest(path)?.ok_or(PeerAuthError::Missing)?;
+        let (request, _) = self.request(Method::GET, path, None);
+        Ok(backend::download(
+            &self.partials,
+            &self.client,
+            request.build()?,
+            path,
+            dest,
+            &self.bandwidth,
+            Some(&sha256),
+        )?)
+    }
+
+    fn put(&self, path: &[&str], src: &Path, sha256: &str) -> Result<PutOutcome, BackendError> {
+        let file = File::open(src)?;
+        let len = file.metadata()?.len();
+        let body =
+            reqwest::blocking::Body::sized(ThrottledReader::new(file, self.bandwidth.clone()), len);
+
+        let (request, sent) = self.request(Method::POST, path, Some(sha256));
+        let response = request
+            .header("Repr-Digest", integrity::repr_digest(sha256))
+            .timeout(upload_timeout(&self.bandwidth, len))
+            .body(body)
+            .send()?;
+
+        let peer_sha256 = integrity::server_digest(response.headers());
+        if response.status() != StatusCode::CONFLICT && !response.status().is_success() {
+            return Err(BackendError::Status(response.status()));
+        }
+        self.verify_response(
+            &sent,
+            response.status(),
+            response.headers(),
+            peer_sha256.as_deref(),
+        )?;
+        if response.status() == StatusCode::CONFLICT {
+            return Ok(PutOutcome::Exists(peer_sha256));
+        }
+        match peer_sha256 {
+            Some(peer) if peer != sha256 => Err(BackendError::DigestMismatch(Mismatch {
+                path: path.join("/"),
+                local: sha256.to_string(),
+                server: peer,
+            })),
+            _ => Ok(PutOutcome::Stored),
+        }
+    }
+
+    fn digest(&self, path: &[&str]) -> Result<Option<String>, BackendError> {
+        let (request, sent) = self.request(Method::HEAD, path, None);
+        let response = request.send()?;
+        if !response.status().is_success() {
+            return Err(BackendError::Status(response.status()));
+        }
+        let sha256 = integrity::server_digest(response.headers());
+        self.verify_response(
+            &sent,
+            response.status(),
+            response.headers(),
+            sha256.as_deref(),
+        )?;
+        Ok(sha256)
+    }
+
+    fn accepts_uploads(&self, collection: &str) -> bool {
+        PEER_COLLECTIONS.contains(&collection)
+    }
+
+    fn collections(&self) -> &'static [&'static str] {
+        &PEER_COLLECTIONS
+    }
+}
@@ -0,0 +1,186 @@
+//! The search query language.
+//!
+//! A query is a list of clauses separated by spaces:
+//!
+//! * `word` ranks anga containing it or another form of it; at least one such
+//!   word must match. A Chinese or Japanese word is a phrase of its character
+//!   pairs (see [`crate::text`]). Words of four letters or more also match
+//!   words a typo or two away, ranked below exact matches.
+//! * `word*` also matches words starting with `word`.
+//! * `"exact phrase"` requires the words in this order, next to each other.
+//! * `tag:podcast` requires a meta tag, ignoring case.
+//! * `type:pdf` requires an anga type: its extension (`pdf`), or the MIME type
+//!   `filename::mime_type_for` gives it, whole (`image/png`) or either half
+//!   (`image`, `png`).
+//! * `after:2026-01-01` and `before:2026-02-01` bound the timestamp in the anga's
+//!   filename. `after` includes the day or instant given, `before` excludes it.
+//!   Both take a date or an RFC 3339 time.
+//! * `-clause` excludes anga matching any of the above, e.g. `-draft`,
+//!   `-"to do"`, `-tag:archived`.
+//!
+//! Unknown `key:value` pairs are searched as plain words.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use thiserror::Error;
+
+use crate::filename::mime_type_for;
+use crate::text::{fold, tokenize, Token};
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum QueryError {
+    #[error("{key}: expects a date like 2026-01-31, got {value:?}")]
+    Date { key: &'static str, value: String },
+    #[error("{0}: needs a value")]
+    MissingValue(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Clause {
+    Term(String),
+    /// A term that may also be the start of a longer word.
+    Prefix(String),
+    /// Terms that must appear in this order.
+    Phrase(Vec<String>),
+    Tag(String),
+    Type(String),
+    After(DateTime<Utc>),
+    Before(DateTime<Utc>),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query {
+    pub include: Vec<Clause>,
+    pub exclude: Vec<Clause>,
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Result<Query, QueryError> {
+        Query::parse_with(input, false)
+    }
+
+    /// Parses a query still being typed: unless it ends with a space, its last
+    /// plain word is a [`Clause::Prefix`].
+    pub fn parse_as_typed(input: &str) -> Result<Query, QueryError> {
+        Query::parse_with(input, !input.ends_with(char::is_whitespace))
+    }
+
+    fn parse_with(input: &str, prefix_last: bool) -> Result<Query, QueryError> {
+        let mut query = Query::default();
+        let mut rest = input.trim_start();
+        while !rest.is_empty() {
+            let (negated, after_sign) = match rest.strip_prefix('-') {
+                Some(r) if !r.is_empty() && !r.starts_with(char::is_whitespace) => (true, r),
+                _ => (false, rest),
+            };
+            let (clauses, remaining) = if let Some(quoted) = after_sign.strip_prefix('"') {
+                // An unterminated quote runs to the end of the query
+                let (phrase, remaining) = quoted.split_once('"').unwrap_or((quoted, ""));
+                let terms: Vec<String> = tokenize(phrase).into_iter().map(|t| t.term).collect();
+                (phrase_clause(terms).into_iter().collect(), remaining)
+            } else {
+                let end = after_sign
+                    .find(char::is_whitespace)
+                    .unwrap_or(after_sign.len());
+                let last = prefix_last && !negated && end == after_sign.len();
+                (parse_word(&after_sign[..end], last)?, &after_sign[end..])
+            };
+            if negated {
+                query.exclude.extend(clauses);
+            } else {
+                query.include.extend(clauses);
+            }
+            rest = remaining.trim_start();
+        }
+        Ok(query)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+}
+
+/// The clauses in one unquoted word: a filter, or the terms it tokenizes to.
+/// With `prefix`, or a trailing `*`, its last term is a [`Clause::Prefix`].
+fn parse_word(word: &str, prefix: bool) -> Result<Vec<Clause>, QueryError> {
+    if let Some((key, value)) = word.split_once(':') {
+        let filter = match key.to_ascii_lowercase().as_str() {
+            "tag" => Some("tag"),
+            "type" => Some("type"),
+            "after" => Some("after"),
+            "before" => Some("before"),
+            _ => None,
+        };
+        if let Some(key) = filter {
+            if value.is_empty() {
+                return Err(QueryError::MissingValue(key));
+            }
+            let clause = match key {
+                "tag" => Clause::Tag(fold(value)),
+                "type" => Clause::Type(value.to_lowercase()),
+                "after" => Clause::After(parse_time(key, value)?),
+                _ => Clause::Before(parse_time(key, value)?),
+            };
+            return Ok(vec![clause]);
+        }
+    }
+    let prefix = prefix || word.ends_with('*');
+    let mut clauses: Vec<Clause> = overlapping_runs(tokenize(word))
+        .into_iter()
+        .filter_map(phrase_clause)
+        .collect();
+    match clauses.pop() {
+        Some(Clause::Term(term)) if prefix => clauses.push(Clause::Prefix(term)),
+        Some(clause) => clauses.push(clause),
+        None => {}
+    }
+    Ok(clauses)
+}
+
+/// A single term, or a phrase of several.
+fn phrase_clause(terms: Vec<String>) -> Option<Clause> {
+    match terms.len() {
+        0 => None,
+        1 => terms.into_iter().next().map(Clause::Term),
+        _ => Some(Clause::Phrase(terms)),
+    }
+}
+
+/// `tokens` grouped into runs that overlap, which are the character pairs of one
+/// CJK word. Every other token is a run of its own.
+fn overlapping_runs(tokens: Vec<Token>) -> Vec<Vec<String>> {
+    let mut runs: Vec<Vec<String>> = Vec::new();
+    let mut end = 0;
+    for token in tokens {
+        match runs.last_mut() {
+            Some(run) if token.start < end => run.push(token.term),
+            _ => runs.push(vec![token.term]),
+        }
+        end = token.end;
+    }
+    runs
+}
+
+/// A date (the start of that day, UTC) or an RFC 3339 time.
+fn parse_time(key: &'static str, value: &str) -> Result<DateTime<Utc>, QueryError> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc());
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|_| QueryError::Date {
+            key,
+            value: value.to_string(),
+        })
+}
+
+/// Whether the anga `filename` has `kind` as its extension or (part of) its MIME
+/// type.
+pub fn type_matches(filename: &str, kind: &str) -> bool {
+    let extension = filename
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_lowercase())
+        .unwrap_or_default();
+    let mime = mime_type_for(filename);
+    let (top, sub) = mime.split_once('/').unwrap_or((&mime, ""));
+    [extension.as_str(), mime.as_str(), top, sub].contains(&kind)
+}
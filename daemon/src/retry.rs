@@ -0,0 +1,179 @@
+//! Retrying individual sync transfers.
+//!
+//! Within a sync run each file transfer is retried on its own with exponential
+//! backoff and jitter ([`RetryPolicy`]). A file that still fails is recorded in the
+//! failure log (`~/.kaya/.sync-failures`) and deferred across runs with a growing
+//! cooldown, so one broken file neither blocks the rest of its collection nor gets
+//! hammered every sync cycle.
+
+use chrono::{DateTime, TimeDelta, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use tempfile::NamedTempFile;
+
+/// Cooldown after the first failed run of a file; doubles with every further one.
+const FAILURE_COOLDOWN_BASE_MINUTES: i64 = 1;
+/// Upper bound on the cooldown, so a file is retried at least a few times a day.
+const FAILURE_COOLDOWN_MAX_MINUTES: i64 = 6 * 60;
+
+/// How often, and how patiently, one transfer is attempted within a sync run.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// The pause before retry number `attempt` (1-based): `base_delay * 2^(attempt-1)`,
+    /// capped at `max_delay`, then scaled by a random factor in `[0.5, 1.0]` so that
+    /// transfers failing together do not retry in lockstep.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(attempt.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        let capped = self.base_delay.saturating_mul(factor).min(self.max_delay);
+        capped.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+
+    /// Runs `op` until it succeeds, fails with an error `retryable` rejects, or
+    /// `max_attempts` is used up. Returns the last error.
+    pub fn run<T, E>(
+        &self,
+        mut op: impl FnMut() -> Result<T, E>,
+        retryable: impl Fn(&E) -> bool,
+    ) -> Result<T, E> {
+        let mut attempt = 1;
+        loop {
+            match op() {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt < self.max_attempts && retryable(&e) => {
+                    thread::sleep(self.delay(attempt));
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// A file whose most recent sync run ended in failure.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FailureEntry {
+    pub collection: String,
+    pub name: String,
+    /// Consecutive sync runs in which the file failed.
+    pub failures: u32,
+    pub last_error: String,
+    pub last_attempt: DateTime<Utc>,
+    /// The file is skipped (deferred) until this time.
+    pub retry_after: DateTime<Utc>,
+}
+
+/// Persistent per-file failure record, stored as a JSON array.
+pub struct FailureLog {
+    path: PathBuf,
+    entries: BTreeMap<(String, String), FailureEntry>,
+}
+
+impl FailureLog {
+    /// Loads the failure log from `kaya_dir`. A missing file is an empty log.
+    pub fn load(kaya_dir: &Path) -> io::Result<Self> {
+        let path = kaya_dir.join(".sync-failures");
+        let entries = match fs::read_to_string(&path) {
+            Ok(s) => serde_json::from_str::<Vec<FailureEntry>>(&s)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(FailureLog {
+            path,
+            entries: entries
+                .into_iter()
+                .map(|e| ((e.collection.clone(), e.name.clone()), e))
+                .collect(),
+        })
+    }
+
+    /// Writes the log back to disk, replacing the previous file atomically.
+    pub fn save(&self) -> io::Result<()> {
+        let dir = self.path.parent().unwrap_or(Path::new("."));
+        fs::create_dir_all(dir)?;
+        let entries: Vec<&FailureEntry> = self.entries.values().collect();
+        let json = serde_json::to_string_pretty(&entries)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let mut temp = NamedTempFile::new_in(dir)?;
+        temp.write_all(json.as_bytes())?;
+        temp.persist(&self.path).map_err(|e| e.error)?;
+        Ok(())
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &FailureEntry> {
+        self.entries.values()
+    }
+
+    /// True if `name` failed recently and its cooldown has not yet passed.
+    pub fn is_deferred(&self, collection: &str, name: &str, now: DateTime<Utc>) -> bool {
+        self.entries
+            .get(&(collection.to_string(), name.to_string()))
+            .is_some_and(|e| e.retry_after > now)
+    }
+
+    /// Records a failed run for `name`, deferring it for a cooldown that doubles
+    /// with each consecutive failure.
+    pub fn record_failure(
+        &mut self,
+        collection: &str,
+        name: &str,
+        error: &str,
+        now: DateTime<Utc>,
+    ) -> &FailureEntry {
+        let entry = self
+            .entries
+            .entry((collection.to_string(), name.to_string()))
+            .or_insert_with(|| FailureEntry {
+                collection: collection.to_string(),
+                name: name.to_string(),
+                failures: 0,
+                last_error: String::new(),
+                last_attempt: now,
+                retry_after: now,
+            });
+        entry.failures += 1;
+        entry.last_error = error.to_string();
+        entry.last_attempt = now;
+        entry.retry_after = now + cooldown(entry.failures);
+        entry
+    }
+
+    /// Clears any failure recorded for `name`.
+    pub fn record_success(&mut self, collection: &str, name: &str) {
+        self.entries
+            .remove(&(collection.to_string(), name.to_string()));
+    }
+
+    /// Drops entries in `collection` that are no longer waiting to be transferred,
+    /// e.g. because the file was synced by another device in the meantime.
+    pub fn retain_pending(&mut self, collection: &str, pending: &HashSet<String>) {
+        self.entries
+            .retain(|(c, name), _| c != collection || pending.contains(name));
+    }
+}
+
+fn cooldown(failures: u32) -> TimeDelta {
+    // Past 2^30 the cap has long since applied; stop before the shift overflows
+    let factor = 1i64 << failures.saturating_sub(1).min(30);
+    TimeDelta::minutes(
+        FAILURE_COOLDOWN_BASE_MINUTES
+            .saturating_mul(factor)
+            .min(FAILURE_COOLDOWN_MAX_MINUTES),
+    )
+}
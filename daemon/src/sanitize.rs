@@ -0,0 +1,165 @@
+//! Guards against writing outside `~/.kaya` with names supplied by a server.
+//!
+//! Every name taken from a server listing passes through [`safe_join`] before it
+//! touches the filesystem. Names that fail are recorded in the quarantine file
+//! (`~/.kaya/.quarantine`) instead of being written, so they can be reported.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Component, Path, PathBuf};
+use thiserror::Error;
+
+/// Most filesystems cap a single path component at 255 bytes.
+const MAX_COMPONENT_BYTES: usize = 255;
+
+// Device names Windows refuses as filenames, with or without an extension.
+const WINDOWS_RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum UnsafeName {
+    #[error("name is empty")]
+    Empty,
+    #[error("name is a relative directory reference")]
+    DotName,
+    #[error("name is hidden (starts with '.')")]
+    Hidden,
+    #[error("name contains a path separator")]
+    Separator,
+    #[error("name contains a control character")]
+    Control,
+    #[error("name is reserved on Windows")]
+    Reserved,
+    #[error("name ends with a dot or space")]
+    TrailingDotOrSpace,
+    #[error("name is longer than {MAX_COMPONENT_BYTES} bytes")]
+    TooLong,
+}
+
+/// Checks that `name` is a single, ordinary path component on every platform the
+/// daemon runs on.
+pub fn check_component(name: &str) -> Result<(), UnsafeName> {
+    if name.is_empty() {
+        return Err(UnsafeName::Empty);
+    }
+    if name == "." || name == ".." {
+        return Err(UnsafeName::DotName);
+    }
+    if name.starts_with('.') {
+        return Err(UnsafeName::Hidden);
+    }
+    if name.contains(['/', '\\']) {
+        return Err(UnsafeName::Separator);
+    }
+    // ':' is legal in Unix filenames but selects a drive or stream on Windows
+    if cfg!(windows) && name.contains(':') {
+        return Err(UnsafeName::Separator);
+    }
+    if name.chars().any(|c| c.is_control()) {
+        return Err(UnsafeName::Control);
+    }
+    if name.ends_with('.') || name.ends_with(' ') {
+        return Err(UnsafeName::TrailingDotOrSpace);
+    }
+    if name.len() > MAX_COMPONENT_BYTES {
+        return Err(UnsafeName::TooLong);
+    }
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    if WINDOWS_RESERVED
+        .iter()
+        .any(|r| r.eq_ignore_ascii_case(stem))
+    {
+        return Err(UnsafeName::Reserved);
+    }
+    Ok(())
+}
+
+/// Joins an untrusted `name` onto `dir`, guaranteeing the result is a direct child
+/// of `dir`.
+pub fn safe_join(dir: &Path, name: &str) -> Result<PathBuf, UnsafeName> {
+    check_component(name)?;
+    let joined = dir.join(name);
+
+    // Belt and braces: the checks above should make this impossible
+    let mut components = joined
+        .strip_prefix(dir)
+        .map_err(|_| UnsafeName::Separator)?
+        .components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(joined),
+        _ => Err(UnsafeName::Separator),
+    }
+}
+
+/// A server-supplied name that was refused rather than written.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct QuarantineEntry {
+    pub time: DateTime<Utc>,
+    pub collection: String,
+    pub name: String,
+    pub reason: String,
+}
+
+/// Append-only record of refused names, one JSON object per line.
+pub struct Quarantine {
+    path: PathBuf,
+}
+
+impl Quarantine {
+    pub fn new(kaya_dir: &Path) -> Self {
+        Quarantine {
+            path: kaya_dir.join(".quarantine"),
+        }
+    }
+
+    /// Records a refused name. Returns false if it was already quarantined, so
+    /// callers can avoid re-reporting the same entry every sync cycle.
+    pub fn record(&self, collection: &str, name: &str, reason: &str) -> io::Result<bool> {
+        if self
+            .entries()?
+            .iter()
+            .any(|e| e.collection == collection && e.name == name)
+        {
+            return Ok(false);
+        }
+
+        let entry = QuarantineEntry {
+            time: Utc::now(),
+            collection: collection.to_string(),
+            name: name.to_string(),
+            reason: reason.to_string(),
+        };
+        let line = serde_json::to_string(&entry)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", line)?;
+        Ok(true)
+    }
+
+    pub fn entries(&self) -> io::Result<Vec<QuarantineEntry>> {
+        let file = match fs::File::open(&self.path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut entries = Vec::new();
+        for line in BufReader::new(file).lines() {
+            // Skip lines that do not parse rather than hiding every other entry
+            if let Ok(entry) = serde_json::from_str(&line?) {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+}
@@ -0,0 +1,1312 @@
+//! The local full-text search index, `~/.kaya/.search-index` (ADR 0005).
+//!
+//! Every anga is one document, made of each source that describes it:
+//!
+//! * the anga itself: the slug of its filename, and its text if it is a `.md`
+//!   note;
+//! * `words/{anga}/*`, the plaintext copies synced from the server;
+//! * the `note` and `tags` of each meta file whose `[anga] filename` names it.
+//!
+//! The index maps every term to the sources containing it, and remembers each
+//! source's size and modification time so [`SearchIndex::refresh`] only re-reads
+//! what changed. anga and meta are immutable, so only new names are read; words
+//! directories are re-listed only when their modification time moves.
+//!
+//! Each source's language is detected and its text split into stemmed terms by
+//! [`crate::text`]. A query word matches its stem in every language the index
+//! holds, so "cooperative" finds "cooperatives" and "किताब" finds "किताबें".
+//!
+//! A query word can also match longer words it starts (a prefix, as typed) and
+//! words a typo or two away. The vocabulary is sorted, so both are found without
+//! reading most of it: prefixes by range, typos by [`within_edits`].
+//!
+//! Results are ranked by how many query words they match, then by how few typos,
+//! then with BM25 over whole anga. Meta counts double, since the user wrote it.
+//! Queries are parsed by [`crate::query`].
+//!
+//! Only two things read files at query time: the word order of phrases, and
+//! snippets. [`SearchIndex::candidates`] ranks from the index alone, and
+//! [`Candidates::hits`] then reads the best candidates until it has enough, so
+//! the daemon need not hold the index meanwhile.
+//!
+//! [`SearchIndex::related`] finds the anga most like another from the same index:
+//! the cosine of their TF-IDF vectors over words and notes, the meta tags they
+//! share, and how close together they were saved.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tempfile::NamedTempFile;
+
+use crate::filename::parse_anga_filename;
+use crate::query::{type_matches, Clause, Query};
+use crate::text::{analyze, detect_language, fold, is_cjk, stem, tokenize, within_edits, Language};
+
+/// Bumped whenever tokenization or the file layout changes; an index in another
+/// format is discarded and rebuilt.
+const FORMAT_VERSION: u32 = 4;
+const SNIPPET_CHARS: usize = 160;
+/// How much a completion of a prefix counts, relative to the word itself.
+const PREFIX_WEIGHT: f64 = 0.8;
+/// How much a word one typo away counts, relative to the word itself; each
+/// further typo multiplies it again.
+const TYPO_WEIGHT: f64 = 0.5;
+/// Shorter prefixes only match themselves.
+const MIN_PREFIX_CHARS: usize = 2;
+/// The most completions, and the most words a typo away, one query word matches.
+const MAX_EXPANSIONS: usize = 32;
+/// How much text, shared tags and closeness in time count towards relatedness.
+const RELATED_TEXT_WEIGHT: f64 = 0.6;
+const RELATED_TAG_WEIGHT: f64 = 0.25;
+const RELATED_TIME_WEIGHT: f64 = 0.15;
+/// Anga saved this many days apart are half as close in time as ones saved
+/// together.
+const RELATED_HALF_LIFE_DAYS: f64 = 30.0;
+const BM25_K1: f64 = 1.2;
+const BM25_B: f64 = 0.75;
+
+/// What a source is, which decides how it is read and weighted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SourceKind {
+    /// `anga/{filename}`
+    Anga,
+    /// `words/{anga}/{filename}`
+    Words,
+    /// `meta/{filename}.toml`
+    Meta,
+}
+
+impl SourceKind {
+    fn weight(self) -> f64 {
+        match self {
+            SourceKind::Meta => 2.0,
+            SourceKind::Anga | SourceKind::Words => 1.0,
+        }
+    }
+
+    /// Whether it is the anga's own text, as opposed to what meta says about it.
+    fn is_text(self) -> bool {
+        matches!(self, SourceKind::Anga | SourceKind::Words)
+    }
+}
+
+/// The searchable fields of a meta file, or `None` if it does not parse.
+fn parse_meta(text: &str) -> Option<(String, Vec<String>, String)> {
+    let value: toml::Value = toml::from_str(text).ok()?;
+    let anga = value.get("anga")?.get("filename")?.as_str()?.to_string();
+    let meta = value.get("meta");
+    let tags = meta
+        .and_then(|m| m.get("tags"))
+        .and_then(|t| t.as_array())
+        .map(|tags| {
+            tags.iter()
+                .filter_map(|t| t.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default();
+    let note = meta
+        .and_then(|m| m.get("note"))
+        .and_then(|n| n.as_str())
+        .unwrap_or("")
+        .to_string();
+    Some((anga, tags, note))
+}
+
+/// The kind of source `rel` (relative to `~/.kaya`) is, or `None` if it is not
+/// one.
+fn classify(rel: &str) -> Option<SourceKind> {
+    let segments: Vec<&str> = rel.split('/').collect();
+    if segments.iter().any(|s| s.is_empty() || s.starts_with('.')) {
+        return None;
+    }
+    match segments.as_slice() {
+        ["anga", name] => parse_anga_filename(name).ok().map(|_| SourceKind::Anga),
+        ["meta", name] if name.ends_with(".toml") => Some(SourceKind::Meta),
+        ["words", anga, _] => parse_anga_filename(anga).ok().map(|_| SourceKind::Words),
+        _ => None,
+    }
+}
+
+/// What a source says: the anga it describes, its tags and its text. `None` for
+/// meta that does not parse, which describes nothing.
+fn read_source(root: &Path, rel: &str, kind: SourceKind) -> io::Result<Option<SourceText>> {
+    let mut segments = rel.split('/').skip(1);
+    let anga = segments.next().unwrap_or_default().to_string();
+    let read = || -> io::Result<String> {
+        Ok(String::from_utf8_lossy(&fs::read(root.join(rel))?).into_owned())
+    };
+    Ok(match kind {
+        SourceKind::Anga => {
+            // Only notes are read; any other anga is known by its name alone
+            let slug = parse_anga_filename(&anga)
+                .map_or("", |f| f.slug)
+                .to_string();
+            let (text, display) = if anga.to_ascii_lowercase().ends_with(".md") {
+                let body = read()?;
+                (format!("{}\n{}", slug, body), body)
+            } else {
+                (slug.clone(), slug)
+            };
+            Some(SourceText {
+                anga,
+                tags: Vec::new(),
+                text,
+                display,
+            })
+        }
+        SourceKind::Words => {
+            let text = read()?;
+            Some(SourceText {
+                anga,
+                tags: Vec::new(),
+                display: text.clone(),
+                text,
+            })
+        }
+        SourceKind::Meta => parse_meta(&read()?).map(|(anga, tags, note)| SourceText {
+            anga,
+            text: format!("{}\n{}", tags.join(" "), note),
+            // The note reads better than the tags it was indexed with
+            display: if note.trim().is_empty() {
+                tags.join(", ")
+            } else {
+                note
+            },
+            tags,
+        }),
+    })
+}
+
+struct SourceText {
+    anga: String,
+    tags: Vec<String>,
+    /// Everything indexed.
+    text: String,
+    /// What a snippet shows.
+    display: String,
+}
+
+/// For each source matching a query: how many of its terms, and how often.
+type Matched = HashMap<u32, (usize, u32)>;
+
+/// How a query word is looked up: also as the start of longer words, and also
+/// with typos.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct Lookup<'a> {
+    word: &'a str,
+    prefix: bool,
+    fuzzy: bool,
+}
+
+/// The lookups for the words of `clause`. Only included words are fuzzy: an
+/// exclusion should not drop anga for a word that merely looks similar.
+fn lookups(clause: &Clause, include: bool) -> Vec<Lookup<'_>> {
+    match clause {
+        Clause::Term(word) | Clause::Prefix(word) => vec![Lookup {
+            word,
+            prefix: matches!(clause, Clause::Prefix(_)),
+            fuzzy: include,
+        }],
+        // Phrases match their words exactly
+        Clause::Phrase(words) => words
+            .iter()
+            .map(|word| Lookup {
+                word,
+                prefix: false,
+                fuzzy: false,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// An indexed term a query word matches.
+#[derive(Debug, Clone)]
+struct Variant<'a> {
+    term: &'a str,
+    /// How much an occurrence counts: less for completions and typos.
+    weight: f64,
+    typos: usize,
+}
+
+/// An anga like another, from [`SearchIndex::related`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Related {
+    pub anga: String,
+    /// 0 to 1: the weighted sum of the three below.
+    pub score: f64,
+    /// The cosine similarity of their texts' TF-IDF vectors, 0 to 1.
+    pub text: f64,
+    /// The meta tags both have, lowercased.
+    pub tags: Vec<String>,
+    /// Days between their timestamps.
+    pub days: f64,
+}
+
+/// How well an anga matches the words a query ranks by.
+#[derive(Debug, Default, Clone, Copy)]
+struct Rank {
+    words: usize,
+    typos: usize,
+    score: f64,
+}
+
+/// A term's weight for occurring `count` times: each repeat adds less.
+fn tf_weight(count: u32) -> f64 {
+    1.0 + (count as f64).ln()
+}
+
+/// Words of at least 4 characters may have a typo, and of at least 8 two. CJK
+/// pairs and short words, where one edit is a different word, must be exact.
+fn max_typos(word: &str) -> usize {
+    if word.chars().any(is_cjk) {
+        return 0;
+    }
+    match word.chars().count() {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+/// The second character of a CJK pair.
+fn pair_end(term: &str) -> Option<char> {
+    let mut chars = term.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some(a), Some(b), None) if is_cjk(a) && is_cjk(b) => Some(b),
+        _ => None,
+    }
+}
+
+/// One indexed file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Source {
+    rel: String,
+    /// The anga this source describes.
+    anga: String,
+    kind: SourceKind,
+    size: u64,
+    modified: Option<SystemTime>,
+    /// Number of terms read from it.
+    terms: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    /// The language its words were stemmed in, if it was detected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    language: Option<Language>,
+}
+
+impl Source {
+    fn is_fresh(&self, meta: &fs::Metadata) -> bool {
+        self.size == meta.len() && self.modified == meta.modified().ok()
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct IndexState {
+    version: u32,
+    next_id: u32,
+    sources: BTreeMap<u32, Source>,
+    /// Modification time of each `words/{anga}` directory when it was last read.
+    dirs: BTreeMap<String, SystemTime>,
+    /// Every term, with the sources containing it and how often, sorted by source.
+    postings: BTreeMap<String, Vec<(u32, u32)>>,
+    /// Words as written, where they were indexed as other terms (their stems), so
+    /// prefixes and typos can be matched against what people type.
+    forms: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// A ranked search result.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Hit {
+    pub anga: String,
+    pub score: f64,
+    /// Text around the first match in the source that matched best.
+    pub snippet: String,
+    /// That source, relative to `~/.kaya`.
+    pub source: String,
+}
+
+/// The terms each word of a phrase may be indexed as, in order.
+type Phrase = Vec<HashSet<String>>;
+
+/// A source to read back, once the index is released.
+#[derive(Debug, Clone)]
+struct SourceFile {
+    rel: String,
+    kind: SourceKind,
+    language: Option<Language>,
+}
+
+impl SourceFile {
+    fn of(source: &Source) -> Self {
+        SourceFile {
+            rel: source.rel.clone(),
+            kind: source.kind,
+            language: source.language,
+        }
+    }
+
+    /// Its terms in order, as they were indexed.
+    fn terms(&self, root: &Path) -> Vec<String> {
+        let Ok(Some(read)) = read_source(root, &self.rel, self.kind) else {
+            return Vec::new();
+        };
+        analyze(&read.text, self.language)
+            .into_iter()
+            .map(|t| t.term)
+            .collect()
+    }
+}
+
+/// An anga that may satisfy a query.
+#[derive(Debug)]
+struct Candidate {
+    anga: String,
+    score: f64,
+    /// Its sources, to check phrases in; empty if the query has none.
+    sources: Vec<SourceFile>,
+    /// The excluded phrases whose words it has.
+    excluded: Vec<usize>,
+    snippet: Option<SourceFile>,
+}
+
+/// The anga that may satisfy a query, best first, from
+/// [`SearchIndex::candidates`].
+#[derive(Debug)]
+pub struct Candidates {
+    root: PathBuf,
+    ranked: Vec<Candidate>,
+    limit: usize,
+    /// Phrases the query includes, which every hit has.
+    phrases: Vec<Phrase>,
+    /// Phrases the query excludes.
+    excluded: Vec<Phrase>,
+    /// The terms snippets look for.
+    terms: HashSet<String>,
+}
+
+/// What ranking and filters need to know about one anga.
+#[derive(Debug, Default, Clone)]
+struct Document {
+    /// Weighted number of terms across its sources.
+    length: f64,
+    sources: BTreeSet<u32>,
+    /// Its meta tags, lowercased, with the number of sources giving each.
+    tags: BTreeMap<String, u32>,
+}
+
+/// Adds source `id` to its anga's [`Document`].
+fn count(documents: &mut HashMap<String, Document>, id: u32, source: &Source) {
+    let document = documents.entry(source.anga.clone()).or_default();
+    document.length += source.kind.weight() * source.terms as f64;
+    document.sources.insert(id);
+    for tag in &source.tags {
+        *document.tags.entry(fold(tag)).or_default() += 1;
+    }
+}
+
+/// Removes source `id` from its anga's [`Document`], and the document once it
+/// has no sources left.
+fn uncount(documents: &mut HashMap<String, Document>, id: u32, source: &Source) {
+    let Some(document) = documents.get_mut(&source.anga) else {
+        return;
+    };
+    document.length -= source.kind.weight() * source.terms as f64;
+    document.sources.remove(&id);
+    for tag in &source.tags {
+        let tag = fold(tag);
+        if let Some(n) = document.tags.get_mut(&tag) {
+            *n -= 1;
+            if *n == 0 {
+                document.tags.remove(&tag);
+            }
+        }
+    }
+    if document.sources.is_empty() {
+        documents.remove(&source.anga);
+    }
+}
+
+pub struct SearchIndex {
+    root: PathBuf,
+    state: IndexState,
+    /// Source ids by path.
+    ids: HashMap<String, u32>,
+    /// Every anga with at least one source.
+    documents: HashMap<String, Document>,
+    /// The number of sources stemmed in each language.
+    languages: BTreeMap<Language, u32>,
+    /// The terms of each source, so removing one only touches its posting lists.
+    source_terms: HashMap<u32, Vec<String>>,
+    /// The words in `forms` standing for each term.
+    written_as: HashMap<String, BTreeSet<String>>,
+    /// CJK pairs by their second character. Pairs by their first are a range of
+    /// `postings`.
+    pairs_ending: HashMap<char, BTreeSet<String>>,
+    /// The length of each anga's TF-IDF vector, computed when first needed since
+    /// any change to the index changes them all.
+    norms: Option<HashMap<String, f64>>,
+    dirty: bool,
+}
+
+impl SearchIndex {
+    /// Loads the index for `kaya_dir`. A missing, unreadable or outdated index is
+    /// empty, which only costs one rebuild.
+    pub fn load(kaya_dir: &Path) -> Self {
+        let state = fs::read_to_string(kaya_dir.join(".search-index"))
+            .ok()
+            .and_then(|s| serde_json::from_str::<IndexState>(&s).ok())
+            .filter(|s| s.version == FORMAT_VERSION)
+            .unwrap_or(IndexState {
+                version: FORMAT_VERSION,
+                ..IndexState::default()
+            });
+        let mut ids = HashMap::new();
+        let mut documents = HashMap::new();
+        let mut languages = BTreeMap::new();
+        for (&id, source) in &state.sources {
+            ids.insert(source.rel.clone(), id);
+            count(&mut documents, id, source);
+            if let Some(language) = source.language {
+                *languages.entry(language).or_default() += 1;
+            }
+        }
+        let mut source_terms: HashMap<u32, Vec<String>> = HashMap::new();
+        let mut pairs_ending: HashMap<char, BTreeSet<String>> = HashMap::new();
+        for (term, postings) in &state.postings {
+            if let Some(c) = pair_end(term) {
+                pairs_ending.entry(c).or_default().insert(term.clone());
+            }
+            for &(id, _) in postings {
+                source_terms.entry(id).or_default().push(term.clone());
+            }
+        }
+        let mut written_as: HashMap<String, BTreeSet<String>> = HashMap::new();
+        for (word, terms) in &state.forms {
+            for term in terms {
+                written_as
+                    .entry(term.clone())
+                    .or_default()
+                    .insert(word.clone());
+            }
+        }
+        SearchIndex {
+            root: kaya_dir.to_path_buf(),
+            state,
+            ids,
+            documents,
+            languages,
+            source_terms,
+            written_as,
+            pairs_ending,
+            norms: None,
+            dirty: false,
+        }
+    }
+
+    /// Writes the index back if it changed since it was loaded or saved.
+    pub fn save(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.root)?;
+        let json = serde_json::to_string(&self.state)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut temp = NamedTempFile::new_in(&self.root)?;
+        temp.write_all(json.as_bytes())?;
+        temp.persist(self.root.join(".search-index"))
+            .map_err(|e| e.error)?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Number of anga with anything indexed.
+    pub fn documents(&self) -> usize {
+        self.documents.len()
+    }
+
+    /// Re-reads `rel` (relative to `~/.kaya`) if it changed since it was indexed,
+    /// and drops it if it is gone. Returns true if the index changed.
+    pub fn update(&mut self, rel: &str) -> io::Result<bool> {
+        let Some(kind) = classify(rel) else {
+            return Ok(false);
+        };
+        let meta = match fs::metadata(self.root.join(rel)) {
+            Ok(meta) if meta.is_file() => meta,
+            Ok(_) => return Ok(self.remove(rel)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(self.remove(rel)),
+            Err(e) => return Err(e),
+        };
+        if let Some(&id) = self.ids.get(rel) {
+            if self.state.sources[&id].is_fresh(&meta) {
+                return Ok(false);
+            }
+        }
+
+        // Unparseable meta is dropped, and retried next refresh
+        let Some(read) = read_source(&self.root, rel, kind)? else {
+            return Ok(self.remove(rel));
+        };
+        self.remove(rel);
+        self.insert(
+            Source {
+                rel: rel.to_string(),
+                anga: read.anga,
+                kind,
+                size: meta.len(),
+                modified: meta.modified().ok(),
+                terms: 0,
+                tags: read.tags,
+                language: detect_language(&read.text),
+            },
+            &read.text,
+        );
+        Ok(true)
+    }
+
+    fn insert(&mut self, mut source: Source, text: &str) {
+        let mut counts: HashMap<String, u32> = HashMap::new();
+        for token in tokenize(text) {
+            let term = stem(&token.term, source.language);
+            if term != token.term {
+                self.written_as
+                    .entry(term.clone())
+                    .or_default()
+                    .insert(token.term.clone());
+                self.state
+                    .forms
+                    .entry(token.term)
+                    .or_default()
+                    .insert(term.clone());
+            }
+            *counts.entry(term).or_default() += 1;
+        }
+        source.terms = counts.values().sum();
+
+        // Ids only grow, so pushing keeps every posting list sorted
+        let id = self.state.next_id;
+        self.state.next_id += 1;
+        let mut terms = Vec::with_capacity(counts.len());
+        for (term, n) in counts {
+            if let Some(c) = pair_end(&term) {
+                self.pairs_ending.entry(c).or_default().insert(term.clone());
+            }
+            self.state
+                .postings
+                .entry(term.clone())
+                .or_default()
+                .push((id, n));
+            terms.push(term);
+        }
+        self.source_terms.insert(id, terms);
+        count(&mut self.documents, id, &source);
+        if let Some(language) = source.language {
+            *self.languages.entry(language).or_default() += 1;
+        }
+        self.ids.insert(source.rel.clone(), id);
+        self.state.sources.insert(id, source);
+        self.norms = None;
+        self.dirty = true;
+    }
+
+    fn remove(&mut self, rel: &str) -> bool {
+        let Some(id) = self.ids.remove(rel) else {
+            return false;
+        };
+        if let Some(source) = self.state.sources.remove(&id) {
+            uncount(&mut self.documents, id, &source);
+            if let Some(language) = source.language {
+                if let Some(n) = self.languages.get_mut(&language) {
+                    *n -= 1;
+                    if *n == 0 {
+                        self.languages.remove(&language);
+                    }
+                }
+            }
+        }
+        for term in self.source_terms.remove(&id).unwrap_or_default() {
+            let Some(postings) = self.state.postings.get_mut(&term) else {
+                continue;
+            };
+            if let Ok(i) = postings.binary_search_by_key(&id, |&(source, _)| source) {
+                postings.remove(i);
+            }
+            if !postings.is_empty() {
+                continue;
+            }
+            // A term no source has left is dropped, and with it the words
+            // written for it
+            self.state.postings.remove(&term);
+            if let Some(c) = pair_end(&term) {
+                if let Some(pairs) = self.pairs_ending.get_mut(&c) {
+                    pairs.remove(&term);
+                    if pairs.is_empty() {
+                        self.pairs_ending.remove(&c);
+                    }
+                }
+            }
+            for word in self.written_as.remove(&term).unwrap_or_default() {
+                if let Some(terms) = self.state.forms.get_mut(&word) {
+                    terms.remove(&term);
+                    if terms.is_empty() {
+                        self.state.forms.remove(&word);
+                    }
+                }
+            }
+        }
+        self.norms = None;
+        self.dirty = true;
+        true
+    }
+
+    /// Brings the index up to date with everything under `~/.kaya`. Returns the
+    /// number of sources added, changed or dropped.
+    pub fn refresh(&mut self) -> io::Result<usize> {
+        let mut changed = 0;
+        let mut present: HashSet<String> = HashSet::new();
+
+        // anga and meta are immutable: only names not yet indexed are read
+        for collection in ["anga", "meta"] {
+            for name in visible_entries(&self.root.join(collection))? {
+                let rel = format!("{}/{}", collection, name);
+                if classify(&rel).is_none() {
+                    continue;
+                }
+                if !self.ids.contains_key(&rel) && self.update(&rel)? {
+                    changed += 1;
+                }
+                present.insert(rel);
+            }
+        }
+
+        let mut unchanged_dirs = HashSet::new();
+        let mut dirs = BTreeMap::new();
+        for anga in visible_entries(&self.root.join("words"))? {
+            let dir = format!("words/{}", anga);
+            let Ok(modified) = fs::metadata(self.root.join(&dir)).and_then(|m| m.modified()) else {
+                continue;
+            };
+            if self.state.dirs.get(&dir) == Some(&modified) {
+                unchanged_dirs.insert(dir.clone());
+            } else {
+                for name in visible_entries(&self.root.join(&dir))? {
+                    let rel = format!("{}/{}", dir, name);
+                    if self.update(&rel)? {
+                        changed += 1;
+                    }
+                    present.insert(rel);
+                }
+            }
+            dirs.insert(dir, modified);
+        }
+        if dirs != self.state.dirs {
+            self.state.dirs = dirs;
+            self.dirty = true;
+        }
+
+        let gone: Vec<String> = self
+            .ids
+            .keys()
+            .filter(|rel| {
+                !present.contains(*rel)
+                    && !rel
+                        .rsplit_once('/')
+                        .is_some_and(|(dir, _)| unchanged_dirs.contains(dir))
+            })
+            .cloned()
+            .collect();
+        for rel in gone {
+            self.remove(&rel);
+            changed += 1;
+        }
+        Ok(changed)
+    }
+
+    /// The anga that satisfy `query`, best first, at most `limit` of them: those
+    /// matching the most query words, then with the fewest typos, then by BM25. A
+    /// query of filters alone lists every anga passing them, newest first.
+    pub fn search(&self, query: &Query, limit: usize) -> Vec<Hit> {
+        self.candidates(query, limit).hits()
+    }
+
+    /// The part of [`SearchIndex::search`] that needs the index: every anga that
+    /// may satisfy `query`, ranked. The rest reads files, so the daemon does it
+    /// after releasing the index.
+    pub fn candidates(&self, query: &Query, limit: usize) -> Candidates {
+        let mut candidates = Candidates {
+            root: self.root.clone(),
+            ranked: Vec::new(),
+            limit,
+            phrases: Vec::new(),
+            excluded: Vec::new(),
+            terms: HashSet::new(),
+        };
+        if query.is_empty() || self.documents.is_empty() {
+            return candidates;
+        }
+        // The indexed terms each word the query mentions anywhere stands for, and
+        // the anga containing any of them
+        let mut variants: HashMap<Lookup, Vec<Variant>> = HashMap::new();
+        for (clauses, include) in [(&query.include, true), (&query.exclude, false)] {
+            for lookup in clauses.iter().flat_map(|c| lookups(c, include)) {
+                variants
+                    .entry(lookup)
+                    .or_insert_with(|| self.expand(lookup));
+            }
+        }
+        let containing: HashMap<Lookup, HashSet<&str>> = variants
+            .iter()
+            .map(|(&lookup, variants)| (lookup, self.containing(variants)))
+            .collect();
+        let mut ranked: Vec<Lookup> = query
+            .include
+            .iter()
+            .flat_map(|c| lookups(c, true))
+            .collect();
+        ranked.sort();
+        ranked.dedup();
+        let groups: Vec<&[Variant]> = ranked.iter().map(|l| variants[l].as_slice()).collect();
+
+        let is_term = |c: &&Clause| matches!(c, Clause::Term(_) | Clause::Prefix(_));
+        let is_phrase = |c: &&Clause| matches!(c, Clause::Phrase(_));
+        let phrase = |clause: &Clause| -> Phrase {
+            lookups(clause, false)
+                .iter()
+                .map(|l| variants[l].iter().map(|v| v.term.to_string()).collect())
+                .collect()
+        };
+        candidates.phrases = query.include.iter().filter(is_phrase).map(phrase).collect();
+        let excluded: Vec<&Clause> = query.exclude.iter().filter(is_phrase).collect();
+        candidates.excluded = excluded.iter().map(|c| phrase(c)).collect();
+
+        let required: Vec<Lookup> = query
+            .include
+            .iter()
+            .filter(is_term)
+            .flat_map(|c| lookups(c, true))
+            .collect();
+        let all: Vec<&str> = if required.is_empty() {
+            self.documents.keys().map(String::as_str).collect()
+        } else {
+            let mut any: HashSet<&str> = HashSet::new();
+            for lookup in &required {
+                any.extend(&containing[lookup]);
+            }
+            any.into_iter().collect()
+        };
+        // Each anga passing the filters, with the excluded phrases whose words it
+        // has, which only its text can rule out
+        let passing: Vec<(&str, Vec<usize>)> = all
+            .into_iter()
+            .filter_map(|anga| {
+                let document = &self.documents[anga];
+                let holds = |c: &Clause| self.matches(anga, document, c, &containing);
+                let passes = query.include.iter().filter(|c| !is_term(c)).all(holds)
+                    && !query.exclude.iter().filter(|c| !is_phrase(c)).any(holds);
+                passes.then(|| {
+                    let excluded = (0..excluded.len())
+                        .filter(|&i| holds(excluded[i]))
+                        .collect();
+                    (anga, excluded)
+                })
+            })
+            .collect();
+
+        let (ranks, matched) = self.score(&groups);
+        let mut ranked: Vec<(&str, Rank, Vec<usize>)> = passing
+            .into_iter()
+            .map(|(anga, excluded)| {
+                let rank = ranks.get(anga).copied().unwrap_or_default();
+                (anga, rank, excluded)
+            })
+            .collect();
+        // Ties go to the newer anga, whose names sort later
+        ranked.sort_by(|(a, x, _), (b, y, _)| {
+            y.words
+                .cmp(&x.words)
+                .then(x.typos.cmp(&y.typos))
+                .then(y.score.total_cmp(&x.score))
+                .then_with(|| b.cmp(a))
+        });
+        // Any anga may fail a phrase, so those queries keep the rest to fall back on
+        let phrases = !candidates.phrases.is_empty() || !candidates.excluded.is_empty();
+        if !phrases {
+            ranked.truncate(limit);
+        }
+
+        candidates.terms = groups
+            .iter()
+            .copied()
+            .flatten()
+            .map(|v| v.term.to_string())
+            .collect();
+        candidates.ranked = ranked
+            .into_iter()
+            .map(|(anga, rank, excluded)| Candidate {
+                anga: anga.to_string(),
+                score: rank.score,
+                sources: if !candidates.phrases.is_empty() || !excluded.is_empty() {
+                    self.documents[anga]
+                        .sources
+                        .iter()
+                        .map(|id| SourceFile::of(&self.state.sources[id]))
+                        .collect()
+                } else {
+                    Vec::new()
+                },
+                excluded,
+                snippet: self.snippet_source(anga, &matched).map(SourceFile::of),
+            })
+            .collect();
+        candidates
+    }
+
+    /// The indexed terms a query word matches:
+    ///
+    /// * the word and its stem in every language the index holds, and for a lone
+    ///   CJK character the pairs it is part of;
+    /// * for a prefix, the most frequent words starting with it;
+    /// * if fuzzy, the words closest to it within [`max_typos`].
+    fn expand<'a>(&'a self, lookup: Lookup) -> Vec<Variant<'a>> {
+        let word = lookup.word;
+        let mut best: BTreeMap<&str, (f64, usize)> = BTreeMap::new();
+        let mut add = |terms: Vec<&'a str>, weight: f64, typos: usize| {
+            for term in terms {
+                let entry = best.entry(term).or_insert((weight, typos));
+                if weight > entry.0 {
+                    *entry = (weight, typos);
+                }
+            }
+        };
+
+        let mut exact = vec![word.to_string()];
+        exact.extend(self.languages.keys().map(|&l| stem(word, Some(l))));
+        for form in &exact {
+            add(self.indexed_as(form), 1.0, 0);
+        }
+        let mut chars = word.chars();
+        let cjk = word.chars().any(is_cjk);
+        if let (Some(c), None, true) = (chars.next(), chars.next(), cjk) {
+            let starting = self
+                .state
+                .postings
+                .range::<str, _>((Bound::Excluded(word), Bound::Unbounded))
+                .map(|(t, _)| t)
+                .take_while(|t| t.starts_with(c))
+                .filter(|t| pair_end(t).is_some());
+            let ending = self.pairs_ending.get(&c).into_iter().flatten();
+            add(starting.chain(ending).map(String::as_str).collect(), 1.0, 0);
+        }
+
+        if lookup.prefix && !cjk && word.chars().count() >= MIN_PREFIX_CHARS {
+            let starting = (Bound::Included(word), Bound::Unbounded);
+            let mut completions: Vec<(usize, &str)> = self
+                .state
+                .postings
+                .range::<str, _>(starting)
+                .map(|(w, _)| w)
+                .take_while(|w| w.starts_with(word))
+                .chain(
+                    self.state
+                        .forms
+                        .range::<str, _>(starting)
+                        .map(|(w, _)| w)
+                        .take_while(|w| w.starts_with(word)),
+                )
+                .map(|w| (self.frequency(w), w.as_str()))
+                .collect();
+            completions.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(b.1)));
+            for (_, completion) in completions.into_iter().take(MAX_EXPANSIONS) {
+                add(self.indexed_as(completion), PREFIX_WEIGHT, 0);
+            }
+        }
+
+        let typos = if lookup.fuzzy { max_typos(word) } else { 0 };
+        if typos > 0 {
+            let mut near: Vec<(usize, usize, &str)> =
+                within_edits(&self.state.postings, word, typos)
+                    .into_iter()
+                    .chain(within_edits(&self.state.forms, word, typos))
+                    .filter(|&(_, distance)| distance > 0)
+                    .map(|(w, distance)| (distance, self.frequency(w), w))
+                    .collect();
+            near.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)).then(a.2.cmp(b.2)));
+            for (distance, _, w) in near.into_iter().take(MAX_EXPANSIONS) {
+                add(
+                    self.indexed_as(w),
+                    TYPO_WEIGHT.powi(distance as i32),
+                    distance,
+                );
+            }
+        }
+
+        best.into_iter()
+            .map(|(term, (weight, typos))| Variant {
+                term,
+                weight,
+                typos,
+            })
+            .collect()
+    }
+
+    /// The indexed terms a word as written stands for: itself if it is one, and
+    /// the stems it was indexed as.
+    fn indexed_as(&self, word: &str) -> Vec<&str> {
+        let itself = self.state.postings.get_key_value(word).map(|(t, _)| t);
+        itself
+            .into_iter()
+            .chain(self.state.forms.get(word).into_iter().flatten())
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// The number of sources containing the terms `word` stands for, at most.
+    fn frequency(&self, word: &str) -> usize {
+        self.indexed_as(word)
+            .into_iter()
+            .map(|t| self.state.postings[t].len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Every anga with a source containing any of `variants`.
+    fn containing(&self, variants: &[Variant]) -> HashSet<&str> {
+        variants
+            .iter()
+            .filter_map(|v| self.state.postings.get(v.term))
+            .flatten()
+            .map(|(id, _)| self.state.sources[id].anga.as_str())
+            .collect()
+    }
+
+    /// Whether `anga` satisfies `clause`. For a phrase, only whether it has every
+    /// word: [`Candidates::hits`] checks their order.
+    fn matches(
+        &self,
+        anga: &str,
+        document: &Document,
+        clause: &Clause,
+        containing: &HashMap<Lookup, HashSet<&str>>,
+    ) -> bool {
+        let timestamp = || parse_anga_filename(anga).ok().map(|f| f.timestamp);
+        let lookups = lookups(clause, false);
+        match clause {
+            // Only reached when excluding: included terms rank instead
+            Clause::Term(_) | Clause::Prefix(_) => {
+                lookups.iter().any(|l| containing[l].contains(anga))
+            }
+            Clause::Phrase(_) => lookups.iter().all(|l| containing[l].contains(anga)),
+            Clause::Tag(tag) => document.tags.contains_key(tag),
+            Clause::Type(kind) => type_matches(anga, kind),
+            Clause::After(time) => timestamp().is_some_and(|t| t >= *time),
+            Clause::Before(time) => timestamp().is_some_and(|t| t < *time),
+        }
+    }
+
+    /// How each anga matching any of `groups`, the variants of each ranked query
+    /// word, ranks; and for each source matching any of them how many words it
+    /// matched and how often.
+    fn score(&self, groups: &[&[Variant]]) -> (HashMap<&str, Rank>, Matched) {
+        let documents = self.documents.len() as f64;
+        let average = (self.documents.values().map(|d| d.length).sum::<f64>() / documents).max(1.0);
+        let mut ranks: HashMap<&str, Rank> = HashMap::new();
+        let mut matched: Matched = HashMap::new();
+        for variants in groups {
+            // Weighted occurrences in each anga, and the fewest typos among them
+            let mut frequencies: HashMap<&str, (f64, usize)> = HashMap::new();
+            let mut sources: HashMap<u32, u32> = HashMap::new();
+            for variant in variants.iter() {
+                let Some(postings) = self.state.postings.get(variant.term) else {
+                    continue;
+                };
+                for &(id, count) in postings {
+                    let source = &self.state.sources[&id];
+                    let entry = frequencies
+                        .entry(&source.anga)
+                        .or_insert((0.0, variant.typos));
+                    entry.0 += variant.weight * source.kind.weight() * count as f64;
+                    entry.1 = entry.1.min(variant.typos);
+                    *sources.entry(id).or_default() += count;
+                }
+            }
+            for (id, count) in sources {
+                let entry = matched.entry(id).or_default();
+                entry.0 += 1;
+                entry.1 += count;
+            }
+            let df = frequencies.len() as f64;
+            let idf = (1.0 + (documents - df + 0.5) / (df + 0.5)).ln();
+            for (anga, (tf, typos)) in frequencies {
+                let length = self.documents.get(anga).map_or(0.0, |d| d.length);
+                let norm = BM25_K1 * (1.0 - BM25_B + BM25_B * length / average);
+                let rank = ranks.entry(anga).or_default();
+                rank.words += 1;
+                rank.typos += typos;
+                rank.score += idf * tf * (BM25_K1 + 1.0) / (tf + norm);
+            }
+        }
+        (ranks, matched)
+    }
+
+    /// The anga most like `anga`, best first, at most `limit` of them, or `None`
+    /// if nothing about `anga` is indexed. Only anga sharing words or tags with
+    /// it are related; closeness in time ranks among those.
+    pub fn related(&mut self, anga: &str, limit: usize) -> Option<Vec<Related>> {
+        if !self.documents.contains_key(anga) {
+            return None;
+        }
+        if self.norms.is_none() {
+            self.norms = Some(self.compute_norms());
+        }
+        let norms = self.norms.as_ref()?;
+        let document = &self.documents[anga];
+        let own = self.text_terms(document);
+        let own_norm = norms.get(anga).copied().unwrap_or(0.0);
+
+        let mut dots: HashMap<&str, f64> = HashMap::new();
+        for (term, count) in own {
+            let Some(postings) = self.state.postings.get(term) else {
+                continue;
+            };
+            let frequencies = self.text_frequencies(postings);
+            let idf = self.idf(frequencies.len());
+            let weight = tf_weight(count) * idf;
+            for (other, n) in frequencies {
+                if other != anga {
+                    *dots.entry(other).or_default() += weight * tf_weight(n) * idf;
+                }
+            }
+        }
+
+        let timestamp = |anga: &str| parse_anga_filename(anga).ok().map(|f| f.timestamp);
+        let saved = timestamp(anga);
+        let mut related: Vec<Related> = self
+            .documents
+            .iter()
+            .filter(|(other, _)| other.as_str() != anga)
+            .filter_map(|(other, theirs)| {
+                let dot = dots.get(other.as_str()).copied().unwrap_or(0.0);
+                let their_norm = norms.get(other).copied().unwrap_or(0.0);
+                let text = if dot > 0.0 && own_norm > 0.0 && their_norm > 0.0 {
+                    (dot / (own_norm * their_norm)).min(1.0)
+                } else {
+                    0.0
+                };
+                let tags: Vec<String> = document
+                    .tags
+                    .keys()
+                    .filter(|t| theirs.tags.contains_key(*t))
+                    .cloned()
+                    .collect();
+                if text == 0.0 && tags.is_empty() {
+                    return None;
+                }
+                let union = document.tags.len() + theirs.tags.len() - tags.len();
+                let days = match (saved, timestamp(other)) {
+                    (Some(a), Some(b)) => (a - b).num_seconds().abs() as f64 / 86_400.0,
+                    _ => f64::INFINITY,
+                };
+                let score = RELATED_TEXT_WEIGHT * text
+                    + RELATED_TAG_WEIGHT * tags.len() as f64 / union.max(1) as f64
+                    + RELATED_TIME_WEIGHT * 0.5f64.powf(days / RELATED_HALF_LIFE_DAYS);
+                Some(Related {
+                    anga: other.clone(),
+                    score,
+                    text,
+                    tags,
+                    days,
+                })
+            })
+            .collect();
+        // Ties go to the newer anga, whose names sort later
+        related.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| b.anga.cmp(&a.anga))
+        });
+        related.truncate(limit);
+        Some(related)
+    }
+
+    /// Each term in `document`'s text sources, and how often, from its postings.
+    fn text_terms(&self, document: &Document) -> HashMap<&str, u32> {
+        let mut counts = HashMap::new();
+        for id in &document.sources {
+            if !self.state.sources[id].kind.is_text() {
+                continue;
+            }
+            for term in self.source_terms.get(id).into_iter().flatten() {
+                let postings = &self.state.postings[term];
+                if let Ok(i) = postings.binary_search_by_key(id, |&(id, _)| id) {
+                    *counts.entry(term.as_str()).or_default() += postings[i].1;
+                }
+            }
+        }
+        counts
+    }
+
+    /// The occurrences of one term in each anga's text sources, from its postings.
+    fn text_frequencies(&self, postings: &[(u32, u32)]) -> HashMap<&str, u32> {
+        let mut frequencies = HashMap::new();
+        for &(id, count) in postings {
+            let source = &self.state.sources[&id];
+            if source.kind.is_text() {
+                *frequencies.entry(source.anga.as_str()).or_default() += count;
+            }
+        }
+        frequencies
+    }
+
+    /// The inverse document frequency of a term in `df` anga. A term in every
+    /// anga tells them apart no better than none.
+    fn idf(&self, df: usize) -> f64 {
+        if df == 0 {
+            return 0.0;
+        }
+        (self.documents.len() as f64 / df as f64).ln()
+    }
+
+    /// The length of every anga's TF-IDF vector.
+    fn compute_norms(&self) -> HashMap<String, f64> {
+        let mut squares: HashMap<&str, f64> = HashMap::new();
+        for postings in self.state.postings.values() {
+            let frequencies = self.text_frequencies(postings);
+            let idf = self.idf(frequencies.len());
+            for (anga, count) in frequencies {
+                *squares.entry(anga).or_default() += (tf_weight(count) * idf).powi(2);
+            }
+        }
+        squares
+            .into_iter()
+            .map(|(anga, square)| (anga.to_string(), square.sqrt()))
+            .collect()
+    }
+
+    /// The source to take `anga`'s snippet from: the one matching the most query
+    /// terms, then prose over the anga's name and tag lists, then the most
+    /// occurrences.
+    fn snippet_source(&self, anga: &str, matched: &Matched) -> Option<&Source> {
+        let document = self.documents.get(anga)?;
+        document
+            .sources
+            .iter()
+            .max_by_key(|&id| {
+                let source = &self.state.sources[id];
+                let (terms, count) = matched.get(id).copied().unwrap_or_default();
+                let prose = match source.kind {
+                    SourceKind::Words => 2,
+                    SourceKind::Anga if anga.to_ascii_lowercase().ends_with(".md") => 2,
+                    SourceKind::Anga => 1,
+                    SourceKind::Meta => 0,
+                };
+                (terms, prose, count)
+            })
+            .map(|id| &self.state.sources[id])
+    }
+}
+
+impl Candidates {
+    /// The first `limit` candidates having every included phrase and no
+    /// excluded one, with their snippets. Reads only the files of the anga it
+    /// looks at, and not the index.
+    pub fn hits(self) -> Vec<Hit> {
+        let mut hits = Vec::new();
+        for candidate in &self.ranked {
+            if hits.len() == self.limit {
+                break;
+            }
+            if !self.phrases.is_empty() || !candidate.excluded.is_empty() {
+                let texts: Vec<Vec<String>> = candidate
+                    .sources
+                    .iter()
+                    .map(|s| s.terms(&self.root))
+                    .collect();
+                let has = |phrase: &Phrase| texts.iter().any(|t| has_phrase(t, phrase));
+                if !self.phrases.iter().all(has)
+                    || candidate.excluded.iter().any(|&i| has(&self.excluded[i]))
+                {
+                    continue;
+                }
+            }
+            hits.push(Hit {
+                anga: candidate.anga.clone(),
+                score: candidate.score,
+                snippet: candidate
+                    .snippet
+                    .as_ref()
+                    .map_or_else(String::new, |s| self.snippet(s)),
+                source: candidate
+                    .snippet
+                    .as_ref()
+                    .map_or_else(String::new, |s| s.rel.clone()),
+            });
+        }
+        hits
+    }
+
+    /// About [`SNIPPET_CHARS`] of `source` around its first match for `terms`,
+    /// with whitespace collapsed.
+    fn snippet(&self, source: &SourceFile) -> String {
+        let Ok(Some(read)) = read_source(&self.root, &source.rel, source.kind) else {
+            return String::new();
+        };
+        let text = read.display;
+        let first = analyze(&text, source.language)
+            .into_iter()
+            .find(|t| self.terms.contains(&t.term))
+            .map_or(0, |t| t.start);
+
+        // Start a third of the way back, on a character boundary
+        let before: Vec<usize> = text[..first].char_indices().map(|(i, _)| i).collect();
+        let start = before
+            .len()
+            .checked_sub(SNIPPET_CHARS / 3)
+            .map_or(0, |i| before[i]);
+        let end = text[start..]
+            .char_indices()
+            .nth(SNIPPET_CHARS)
+            .map_or(text.len(), |(i, _)| start + i);
+
+        let mut snippet = text[start..end]
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        if start > 0 {
+            snippet.insert_str(0, "… ");
+        }
+        if end < text.len() {
+            snippet.push_str(" …");
+        }
+        snippet
+    }
+}
+
+/// Whether `terms` has a term of each word of `phrase` next to each other, in
+/// order.
+fn has_phrase(terms: &[String], phrase: &Phrase) -> bool {
+    terms.windows(phrase.len()).any(|window| {
+        window
+            .iter()
+            .zip(phrase)
+            .all(|(term, variants)| variants.contains(term))
+    })
+}
+
+/// Names in `dir` not starting with '.', or nothing if it does not exist.
+fn visible_entries(dir: &Path) -> io::Result<Vec<String>> {
+    match fs::read_dir(dir) {
+        Ok(entries) => Ok(entries
+            .filter_map(|e| e.ok())
+            .filter_map(|e| e.file_name().into_string().ok())
+            .filter(|n| !n.starts_with('.'))
+            .collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,150 @@
+//! Writing incoming files into `~/.kaya`.
+//!
+//! Bodies are streamed into a hidden temp file in the destination directory and
+//! renamed into place once complete, so readers (and sync) never see a partial file
+//! and memory use stays flat regardless of file size.
+//!
+//! anga and meta are append-only and immutable (ADR 0001, ADR 0003), so
+//! [`write_immutable`] never replaces an existing file.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use tempfile::NamedTempFile;
+use thiserror::Error;
+
+use crate::integrity::sha256_file;
+
+#[derive(Error, Debug)]
+pub enum StoreError {
+    #[error("body exceeds the {limit} byte limit")]
+    TooLarge { limit: u64 },
+    #[error("{0} already exists with different content")]
+    Conflict(String),
+    #[error("body's SHA-256 {actual} does not match the declared {expected}")]
+    DigestMismatch { expected: String, actual: String },
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// Whether files in `collection` are immutable once written: anga and meta.
+pub fn is_immutable(collection: &str) -> bool {
+    matches!(collection, "anga" | "meta")
+}
+
+/// Result of a [`write_immutable`] call that did not fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteOutcome {
+    /// The file did not exist and has been written.
+    Created,
+    /// The file already existed with identical content; nothing was changed.
+    Unchanged,
+}
+
+/// Streams `body` into a temp file in `dir`, failing if it is longer than `max_bytes`.
+fn spool(dir: &Path, body: impl Read, max_bytes: u64) -> Result<(NamedTempFile, u64), StoreError> {
+    fs::create_dir_all(dir)?;
+
+    // Temp files are created in `dir` itself so the final rename never crosses
+    // filesystems. Their names start with '.', which keeps them out of listings.
+    let mut temp = NamedTempFile::new_in(dir)?;
+    let written = io::copy(&mut body.take(max_bytes.saturating_add(1)), &mut temp)?;
+    if written > max_bytes {
+        return Err(StoreError::TooLarge { limit: max_bytes });
+    }
+    temp.flush()?;
+    temp.as_file().sync_all()?;
+    Ok((temp, written))
+}
+
+/// Streams `body` into `dir/filename`, failing without touching the destination if
+/// it is longer than `max_bytes`. Replaces any existing file. Returns the number of
+/// bytes written.
+pub fn write_streamed(
+    dir: &Path,
+    filename: &str,
+    body: impl Read,
+    max_bytes: u64,
+) -> Result<u64, StoreError> {
+    let (temp, written) = spool(dir, body, max_bytes)?;
+    temp.persist(dir.join(filename)).map_err(|e| e.error)?;
+    Ok(written)
+}
+
+/// Like [`write_streamed`], but never overwrites an existing file.
+///
+/// Re-sending a file that is already stored with the same bytes is a no-op
+/// ([`WriteOutcome::Unchanged`]); different bytes are a [`StoreError::Conflict`].
+pub fn write_immutable(
+    dir: &Path,
+    filename: &str,
+    body: impl Read,
+    max_bytes: u64,
+) -> Result<WriteOutcome, StoreError> {
+    let (temp, _) = spool(dir, body, max_bytes)?;
+    persist_immutable(temp, dir, filename)
+}
+
+/// Like [`write_immutable`], for a body declared to have the SHA-256 `sha256`. A
+/// body that does not match is a [`StoreError::DigestMismatch`] and never appears
+/// under `filename`, even briefly.
+pub fn write_immutable_digest(
+    dir: &Path,
+    filename: &str,
+    body: impl Read,
+    max_bytes: u64,
+    sha256: &str,
+) -> Result<WriteOutcome, StoreError> {
+    let (temp, _) = spool(dir, body, max_bytes)?;
+    let actual = sha256_file(temp.path())?;
+    if actual != sha256 {
+        return Err(StoreError::DigestMismatch {
+            expected: sha256.to_string(),
+            actual,
+        });
+    }
+    persist_immutable(temp, dir, filename)
+}
+
+fn persist_immutable(
+    temp: NamedTempFile,
+    dir: &Path,
+    filename: &str,
+) -> Result<WriteOutcome, StoreError> {
+    let dest = dir.join(filename);
+
+    // persist_noclobber is atomic, so two concurrent uploads of the same name
+    // cannot both win
+    match temp.persist_noclobber(&dest) {
+        Ok(_) => Ok(WriteOutcome::Created),
+        Err(e) if e.error.kind() == io::ErrorKind::AlreadyExists => {
+            if files_equal(e.file.path(), &dest)? {
+                Ok(WriteOutcome::Unchanged)
+            } else {
+                Err(StoreError::Conflict(filename.to_string()))
+            }
+        }
+        Err(e) => Err(e.error.into()),
+    }
+}
+
+/// Compares two files byte for byte without loading either into memory.
+pub fn files_equal(a: &Path, b: &Path) -> io::Result<bool> {
+    let (fa, fb) = (File::open(a)?, File::open(b)?);
+    if fa.metadata()?.len() != fb.metadata()?.len() {
+        return Ok(false);
+    }
+
+    let (mut ra, mut rb) = (BufReader::new(fa), BufReader::new(fb));
+    let (mut buf_a, mut buf_b) = ([0u8; 8192], [0u8; 8192]);
+    loop {
+        let n = ra.read(&mut buf_a)?;
+        if n == 0 {
+            return Ok(true);
+        }
+        rb.read_exact(&mut buf_b[..n])?;
+        if buf_a[..n] != buf_b[..n] {
+            return Ok(false);
+        }
+    }
+}
@@ -0,0 +1,405 @@
+//! Text analysis for search: splitting text into terms, per language.
+//!
+//! Every term is NFKC-normalized and lowercased, so full-width letters and
+//! ligatures match their plain forms, and decomposed accents their precomposed
+//! ones. Words are runs of letters and
+//! digits, with the combining marks that follow them, so Devanagari vowel signs
+//! and viramas stay inside their word.
+//!
+//! Chinese and Japanese are not written with spaces, so runs of Han, Hiragana and
+//! Katakana become overlapping pairs of characters ("東京都" is "東京" and "京都").
+//! A query for a word is then the phrase of its pairs, which needs no dictionary.
+//!
+//! Each document's language is detected, and its words reduced to their stems:
+//! Snowball for the European languages (and Arabic and Tamil), and a light
+//! suffix stripper for Hindi. Other languages are indexed as written.
+
+use rust_stemmers::{Algorithm, Stemmer};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+use whatlang::Lang;
+
+/// Longer runs of letters and digits are hashes, URLs or base64, not words.
+const MAX_TERM_CHARS: usize = 64;
+/// Below this many words, language detection is a guess.
+const MIN_DETECT_WORDS: usize = 4;
+
+/// A language whose words are stemmed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    Arabic,
+    Danish,
+    Dutch,
+    English,
+    Finnish,
+    French,
+    German,
+    Greek,
+    Hindi,
+    Hungarian,
+    Italian,
+    Norwegian,
+    Portuguese,
+    Romanian,
+    Russian,
+    Spanish,
+    Swedish,
+    Tamil,
+    Turkish,
+}
+
+impl Language {
+    fn from_lang(lang: Lang) -> Option<Language> {
+        Some(match lang {
+            Lang::Ara => Language::Arabic,
+            Lang::Dan => Language::Danish,
+            Lang::Nld => Language::Dutch,
+            Lang::Eng => Language::English,
+            Lang::Fin => Language::Finnish,
+            Lang::Fra => Language::French,
+            Lang::Deu => Language::German,
+            Lang::Ell => Language::Greek,
+            Lang::Hin => Language::Hindi,
+            Lang::Hun => Language::Hungarian,
+            Lang::Ita => Language::Italian,
+            Lang::Nob => Language::Norwegian,
+            Lang::Por => Language::Portuguese,
+            Lang::Ron => Language::Romanian,
+            Lang::Rus => Language::Russian,
+            Lang::Spa => Language::Spanish,
+            Lang::Swe => Language::Swedish,
+            Lang::Tam => Language::Tamil,
+            Lang::Tur => Language::Turkish,
+            _ => return None,
+        })
+    }
+
+    fn algorithm(self) -> Option<Algorithm> {
+        Some(match self {
+            Language::Arabic => Algorithm::Arabic,
+            Language::Danish => Algorithm::Danish,
+            Language::Dutch => Algorithm::Dutch,
+            Language::English => Algorithm::English,
+            Language::Finnish => Algorithm::Finnish,
+            Language::French => Algorithm::French,
+            Language::German => Algorithm::German,
+            Language::Greek => Algorithm::Greek,
+            Language::Hungarian => Algorithm::Hungarian,
+            Language::Italian => Algorithm::Italian,
+            Language::Norwegian => Algorithm::Norwegian,
+            Language::Portuguese => Algorithm::Portuguese,
+            Language::Romanian => Algorithm::Romanian,
+            Language::Russian => Algorithm::Russian,
+            Language::Spanish => Algorithm::Spanish,
+            Language::Swedish => Algorithm::Swedish,
+            Language::Tamil => Algorithm::Tamil,
+            Language::Turkish => Algorithm::Turkish,
+            Language::Hindi => return None,
+        })
+    }
+}
+
+/// A term's position in the text it was read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub term: String,
+    /// Byte offsets into the text.
+    pub start: usize,
+    pub end: usize,
+}
+
+/// `text` NFKC-normalized and lowercased, as terms and tags are compared.
+pub fn fold(text: &str) -> String {
+    text.nfkc().collect::<String>().to_lowercase()
+}
+
+/// Whether `c` belongs to a script written without spaces between words.
+pub fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3005}' | '\u{3007}'             // 々 〇
+        | '\u{3040}'..='\u{30FF}'           // Hiragana, Katakana
+        | '\u{31F0}'..='\u{31FF}'           // Katakana phonetic extensions
+        | '\u{3400}'..='\u{4DBF}'           // Han extension A
+        | '\u{4E00}'..='\u{9FFF}'           // Han
+        | '\u{F900}'..='\u{FAFF}'           // Han compatibility
+        | '\u{FF66}'..='\u{FF9F}'           // Half-width Katakana
+        | '\u{20000}'..='\u{3134F}'         // Han extensions B to G
+    )
+}
+
+/// Whether `c` continues a word: a letter or digit, or a mark combining with
+/// the one before (Devanagari vowel signs and virama, zero-width joiners).
+fn is_word_char(c: char, in_word: bool) -> bool {
+    c.is_alphanumeric()
+        || (in_word && (is_combining_mark(c) || matches!(c, '\u{200C}' | '\u{200D}')))
+}
+
+/// Splits `text` into folded runs of letters and digits, and CJK runs into pairs
+/// of characters. Terms are not stemmed; see [`analyze`].
+pub fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut word: Option<usize> = None;
+    // The characters of the CJK run being read, with their offsets
+    let mut run: Vec<(char, usize, usize)> = Vec::new();
+    for (i, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        let cjk = is_cjk(c);
+        let continues = !cjk && is_word_char(c, word.is_some());
+        match (continues, word) {
+            (true, None) => word = Some(i),
+            (false, Some(start)) => {
+                let term = fold(&text[start..i]);
+                if term.chars().count() <= MAX_TERM_CHARS {
+                    tokens.push(Token {
+                        term,
+                        start,
+                        end: i,
+                    });
+                }
+                word = None;
+            }
+            _ => {}
+        }
+        if cjk {
+            // Half-width kana fold to full width
+            let end = i + c.len_utf8();
+            run.extend(fold(&text[i..end]).chars().map(|c| (c, i, end)));
+        } else if !is_combining_mark(c) {
+            flush_run(&mut run, &mut tokens);
+        }
+    }
+    tokens
+}
+
+/// Emits a CJK run as overlapping pairs, or a lone character as itself.
+fn flush_run(run: &mut Vec<(char, usize, usize)>, tokens: &mut Vec<Token>) {
+    if let [(c, start, end)] = run.as_slice() {
+        tokens.push(Token {
+            term: c.to_string(),
+            start: *start,
+            end: *end,
+        });
+    }
+    for pair in run.windows(2) {
+        tokens.push(Token {
+            term: [pair[0].0, pair[1].0].iter().collect(),
+            start: pair[0].1,
+            end: pair[1].2,
+        });
+    }
+    run.clear();
+}
+
+/// The language `text` is written in, if it is one whose words are stemmed and
+/// there is enough text to tell.
+pub fn detect_language(text: &str) -> Option<Language> {
+    if text.split_whitespace().take(MIN_DETECT_WORDS).count() < MIN_DETECT_WORDS {
+        return None;
+    }
+    // whatlang's confidence is low for short text even when its guess is right,
+    // and a wrong guess only costs some inflections: queries are stemmed in
+    // every language the index holds
+    Language::from_lang(whatlang::detect_lang(text)?)
+}
+
+/// The stem of a folded `term` in `language`. Terms in no known language, and
+/// CJK pairs, are their own stems.
+pub fn stem(term: &str, language: Option<Language>) -> String {
+    let Some(language) = language else {
+        return term.to_string();
+    };
+    if term.chars().any(is_cjk) {
+        return term.to_string();
+    }
+    match language.algorithm() {
+        Some(algorithm) => Stemmer::create(algorithm).stem(term).into_owned(),
+        None => stem_hindi(term).to_string(),
+    }
+}
+
+/// [`tokenize`]d and [`stem`]med, as `text` is indexed.
+pub fn analyze(text: &str, language: Option<Language>) -> Vec<Token> {
+    let mut tokens = tokenize(text);
+    for token in &mut tokens {
+        token.term = stem(&token.term, language);
+    }
+    tokens
+}
+
+/// The keys of `words` within `max` edits of `word`, with their distance. An
+/// insertion, deletion, substitution or swap of neighbouring characters is one
+/// edit.
+///
+/// The keys are walked in order like a trie: each shares the distance rows of
+/// its common prefix with the one before, and a prefix already more than `max`
+/// edits away skips every key starting with it. Most of a large vocabulary is
+/// never looked at.
+pub fn within_edits<'a, V>(
+    words: &'a BTreeMap<String, V>,
+    word: &str,
+    max: usize,
+) -> Vec<(&'a str, usize)> {
+    let target: Vec<char> = word.chars().collect();
+    // rows[k][j]: the distance from the first k characters of the key being
+    // read, `path`, to the first j of `word`
+    let mut rows: Vec<Vec<usize>> = vec![(0..=target.len()).collect()];
+    let mut path: Vec<char> = Vec::new();
+    let mut found = Vec::new();
+    let mut from: Bound<String> = Bound::Unbounded;
+    loop {
+        let bounds = (from.as_ref().map(String::as_str), Bound::Unbounded);
+        let Some((key, _)) = words.range::<str, _>(bounds).next() else {
+            break;
+        };
+        let chars: Vec<char> = key.chars().collect();
+        let common = path.iter().zip(&chars).take_while(|(a, b)| a == b).count();
+        path.truncate(common);
+        rows.truncate(common + 1);
+
+        let mut dead_end = None;
+        for (k, &c) in chars.iter().enumerate().skip(common) {
+            let row = next_row(&rows, &path, c, &target);
+            let lowest = row.iter().copied().min().unwrap_or(0);
+            path.push(c);
+            rows.push(row);
+            if lowest > max {
+                dead_end = Some(k + 1);
+                break;
+            }
+        }
+        from = match dead_end {
+            Some(depth) => match successor(&chars[..depth]) {
+                Some(next) => Bound::Included(next),
+                None => break,
+            },
+            None => {
+                let distance = rows[chars.len()][target.len()];
+                if distance <= max {
+                    found.push((key.as_str(), distance));
+                }
+                Bound::Excluded(key.clone())
+            }
+        };
+    }
+    found
+}
+
+/// The distance row for `path` followed by `c`, from the rows for `path`.
+fn next_row(rows: &[Vec<usize>], path: &[char], c: char, target: &[char]) -> Vec<usize> {
+    let k = rows.len();
+    let previous = &rows[k - 1];
+    let mut row = Vec::with_capacity(target.len() + 1);
+    row.push(k);
+    for j in 1..=target.len() {
+        let cost = usize::from(c != target[j - 1]);
+        let mut distance = (previous[j] + 1)
+            .min(row[j - 1] + 1)
+            .min(previous[j - 1] + cost);
+        if k > 1 && j > 1 && c == target[j - 2] && path[k - 2] == target[j - 1] {
+            distance = distance.min(rows[k - 2][j - 2] + 1);
+        }
+        row.push(distance);
+    }
+    row
+}
+
+/// The first string after every string starting with `prefix`.
+fn successor(prefix: &[char]) -> Option<String> {
+    let mut chars = prefix.to_vec();
+    while let Some(last) = chars.pop() {
+        let next = match last {
+            '\u{D7FF}' => Some('\u{E000}'),
+            c => char::from_u32(c as u32 + 1),
+        };
+        if let Some(next) = next {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
+}
+
+/// Hindi inflectional suffixes (Ramanathan and Rao, "A Lightweight
+/// Stemmer for Hindi", 2003).
+const HINDI_SUFFIXES: &[&str] = &[
+    "ाएंगी",
+    "ाएंगे",
+    "ाऊंगी",
+    "ाऊंगा",
+    "ाइयाँ",
+    "ाइयों",
+    "ाइयां",
+    "ाएगी",
+    "ाएगा",
+    "ाओगी",
+    "ाओगे",
+    "एंगी",
+    "ेंगी",
+    "एंगे",
+    "ेंगे",
+    "ूंगी",
+    "ूंगा",
+    "ातीं",
+    "नाओं",
+    "नाएं",
+    "ताओं",
+    "ताएं",
+    "ियाँ",
+    "ियों",
+    "ियां",
+    "ाकर",
+    "ाइए",
+    "ाईं",
+    "ाया",
+    "ेगी",
+    "ेगा",
+    "ोगी",
+    "ोगे",
+    "ाने",
+    "ाना",
+    "ाते",
+    "ाती",
+    "ाता",
+    "तीं",
+    "ाओं",
+    "ाएं",
+    "ुओं",
+    "ुएं",
+    "ुआं",
+    "कर",
+    "ाओ",
+    "िए",
+    "ाई",
+    "ाए",
+    "ने",
+    "नी",
+    "ना",
+    "ते",
+    "ीं",
+    "ती",
+    "ता",
+    "ाँ",
+    "ां",
+    "ों",
+    "ें",
+    "ो",
+    "े",
+    "ू",
+    "ु",
+    "ी",
+    "ि",
+    "ा",
+];
+
+/// `term` without its longest Hindi suffix, keeping at least two characters.
+fn stem_hindi(term: &str) -> &str {
+    let length = term.chars().count();
+    HINDI_SUFFIXES
+        .iter()
+        .filter(|suffix| term.ends_with(*suffix) && length >= suffix.chars().count() + 2)
+        .max_by_key(|suffix| suffix.len())
+        .map_or(term, |suffix| &term[..term.len() - suffix.len()])
+}
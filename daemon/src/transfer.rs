@@ -0,0 +1,124 @@
+//! Concurrency and bandwidth controls for sync transfers.
+//!
+//! Sync runs up to a fixed number of transfers at once ([`map_parallel`]) over one
+//! shared, connection-pooling HTTP client. Every byte sent or received passes
+//! through a [`ThrottledReader`] drawing on one global [`TokenBucket`], so the
+//! bandwidth cap holds no matter how many transfers are running.
+
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Largest single read charged to the bucket, so one transfer cannot take a
+/// burst's worth of bandwidth in one go.
+const MAX_CHUNK: usize = 16 * 1024;
+
+/// A token bucket limiting throughput to a fixed number of bytes per second.
+///
+/// Buckets refill continuously and hold at most one second of tokens. Callers may
+/// overdraw the bucket; the next caller then waits until the debt is repaid, which
+/// keeps the long-run rate exact without rejecting large reads.
+#[derive(Debug)]
+pub struct TokenBucket {
+    bytes_per_sec: u64,
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// A bucket allowing `bytes_per_sec`. Zero means unlimited.
+    pub fn new(bytes_per_sec: u64) -> Self {
+        TokenBucket {
+            bytes_per_sec,
+            state: Mutex::new(BucketState {
+                tokens: bytes_per_sec as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// The configured rate, or `None` if unlimited.
+    pub fn rate(&self) -> Option<u64> {
+        (self.bytes_per_sec > 0).then_some(self.bytes_per_sec)
+    }
+
+    /// Takes `bytes` tokens, sleeping until the bucket can afford them.
+    pub fn take(&self, bytes: usize) {
+        let Some(rate) = self.rate() else { return };
+        let rate = rate as f64;
+
+        let wait = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            let refill = now.duration_since(state.updated).as_secs_f64() * rate;
+            state.tokens = (state.tokens + refill).min(rate) - bytes as f64;
+            state.updated = now;
+            if state.tokens < 0.0 {
+                Duration::from_secs_f64(-state.tokens / rate)
+            } else {
+                Duration::ZERO
+            }
+        };
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// Charges every byte read from `inner` to a shared [`TokenBucket`].
+pub struct ThrottledReader<R> {
+    inner: R,
+    bucket: Arc<TokenBucket>,
+}
+
+impl<R: Read> ThrottledReader<R> {
+    pub fn new(inner: R, bucket: Arc<TokenBucket>) -> Self {
+        ThrottledReader { inner, bucket }
+    }
+}
+
+impl<R: Read> Read for ThrottledReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(MAX_CHUNK);
+        let n = self.inner.read(&mut buf[..len])?;
+        self.bucket.take(n);
+        Ok(n)
+    }
+}
+
+/// Applies `f` to every item on up to `concurrency` threads, returning the results
+/// in item order.
+pub fn map_parallel<T, R, F>(items: &[T], concurrency: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+
+    thread::scope(|scope| {
+        for _ in 0..concurrency.clamp(1, items.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(i) else { break };
+                let result = f(item);
+                results.lock().unwrap_or_else(|e| e.into_inner())[i] = Some(result);
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap_or_else(|e| e.into_inner())
+        .into_iter()
+        .map(|r| r.expect("every item is processed before the scope ends"))
+        .collect()
+}
@@ -0,0 +1,205 @@
+use savebutton_daemon::auth::{
+    basic_credentials, bearer_token, hash_password, issue_pairing_code, list_tokens,
+    redeem_pairing_code, revoke_token, verify_password, verify_token, Login, PasswordGuard,
+    LOGIN_MAX_FAILURES, LOGIN_WINDOW_SECS, PAIRING_MAX_ATTEMPTS, PASSWORD_CACHE_SECS,
+};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_pairing_code_redeems_once_for_a_valid_token() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let code = issue_pairing_code(dir.path()).unwrap();
+    let token = redeem_pairing_code(dir.path(), &code, "firefox")
+        .unwrap()
+        .expect("valid code should yield a token");
+
+    assert!(verify_token(dir.path(), &token).unwrap());
+    assert!(!verify_token(dir.path(), "not-a-token").unwrap());
+
+    // Codes are single-use
+    assert!(redeem_pairing_code(dir.path(), &code, "firefox")
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_pairing_code_is_case_and_separator_insensitive() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let code = issue_pairing_code(dir.path()).unwrap();
+    let typed = format!("{}-{}", &code[..4], &code[4..]).to_lowercase();
+
+    assert!(redeem_pairing_code(dir.path(), &typed, "chrome")
+        .unwrap()
+        .is_some());
+}
+
+#[test]
+fn test_pairing_code_discarded_after_too_many_failures() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let code = issue_pairing_code(dir.path()).unwrap();
+    for _ in 0..PAIRING_MAX_ATTEMPTS {
+        assert!(redeem_pairing_code(dir.path(), "WRONGCOD", "attacker")
+            .unwrap()
+            .is_none());
+    }
+
+    assert!(
+        redeem_pairing_code(dir.path(), &code, "chrome")
+            .unwrap()
+            .is_none(),
+        "correct code must not work once the attempt budget is spent"
+    );
+}
+
+#[test]
+fn test_revoked_token_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let code = issue_pairing_code(dir.path()).unwrap();
+    let token = redeem_pairing_code(dir.path(), &code, "edge")
+        .unwrap()
+        .unwrap();
+
+    let tokens = list_tokens(dir.path()).unwrap();
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0].label, "edge");
+    assert!(
+        !std::fs::read_to_string(dir.path().join(".tokens"))
+            .unwrap()
+            .contains(&token),
+        "plaintext token must never be stored"
+    );
+
+    assert!(revoke_token(dir.path(), &tokens[0].id).unwrap());
+    assert!(!verify_token(dir.path(), &token).unwrap());
+    assert!(!revoke_token(dir.path(), &tokens[0].id).unwrap());
+}
+
+#[test]
+fn test_bearer_token_parses_authorization_header() {
+    assert_eq!(bearer_token("Bearer abc123"), Some("abc123"));
+    assert_eq!(bearer_token("bearer  abc123 "), Some("abc123"));
+    assert_eq!(bearer_token("Basic dXNlcjpwYXNz"), None);
+    assert_eq!(bearer_token("abc123"), None);
+}
+
+#[test]
+fn test_password_hash_verifies_only_its_password() {
+    let stored = hash_password("correct horse");
+    assert!(stored.starts_with("pbkdf2-sha256$"));
+    assert!(verify_password(&stored, "correct horse"));
+    assert!(!verify_password(&stored, "correct horse "));
+    // Salted: the same password never hashes the same way twice
+    assert_ne!(stored, hash_password("correct horse"));
+    assert!(!verify_password("plaintext", "plaintext"));
+}
+
+#[test]
+fn test_password_guard_throttles_a_client_after_repeated_failures() {
+    let stored = hash_password("correct horse");
+    let guard = PasswordGuard::default();
+    let (client, other): (IpAddr, IpAddr) =
+        ("192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap());
+    let now = Instant::now();
+
+    for _ in 0..LOGIN_MAX_FAILURES {
+        assert_eq!(guard.check(client, &stored, "guess", now), Login::Refused);
+    }
+    // Not even the right password is checked until the window has passed
+    assert_eq!(
+        guard.check(client, &stored, "correct horse", now),
+        Login::Throttled
+    );
+    assert_eq!(
+        guard.check(other, &stored, "correct horse", now),
+        Login::Accepted
+    );
+    let later = now + Duration::from_secs(LOGIN_WINDOW_SECS);
+    assert_eq!(
+        guard.check(client, &stored, "correct horse", later),
+        Login::Accepted
+    );
+}
+
+#[test]
+fn test_password_guard_remembers_verified_passwords_for_a_while() {
+    let stored = hash_password("correct horse");
+    let guard = PasswordGuard::default();
+    let client: IpAddr = "192.0.2.1".parse().unwrap();
+    let now = Instant::now();
+
+    assert_eq!(
+        guard.check(client, &stored, "correct horse", now),
+        Login::Accepted
+    );
+    for _ in 0..LOGIN_MAX_FAILURES {
+        assert_eq!(guard.check(client, &stored, "guess", now), Login::Refused);
+    }
+    // The cached password is accepted without another PBKDF2 run; once it
+    // expires, the client's failures count again
+    assert_eq!(
+        guard.check(client, &stored, "correct horse", now),
+        Login::Accepted
+    );
+    let expired = now + Duration::from_secs(PASSWORD_CACHE_SECS);
+    assert_eq!(
+        guard.check(client, &stored, "correct horse", expired),
+        Login::Accepted
+    );
+    // A new password hash is checked afresh
+    let changed = hash_password("battery staple");
+    assert_eq!(
+        guard.check(client, &changed, "correct horse", expired),
+        Login::Refused
+    );
+}
+
+#[test]
+fn test_basic_credentials_parses_authorization_header() {
+    // "me@example.com:pa:ss"
+    assert_eq!(
+        basic_credentials("Basic bWVAZXhhbXBsZS5jb206cGE6c3M="),
+        Some(("me@example.com".to_string(), "pa:ss".to_string()))
+    );
+    assert_eq!(basic_credentials("Bearer abc123"), None);
+    assert_eq!(basic_credentials("Basic !!!"), None);
+}
+
+#[test]
+fn test_concurrent_pairings_keep_every_token() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let tokens: Vec<String> = std::thread::scope(|s| {
+        let workers: Vec<_> = (0..8)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut tokens = Vec::new();
+                    for _ in 0..10 {
+                        // Another worker may issue a new code in between
+                        let code = issue_pairing_code(dir.path()).unwrap();
+                        if let Some(token) = redeem_pairing_code(dir.path(), &code, "x").unwrap() {
+                            tokens.push(token);
+                        }
+                        // Readers never see a partly written file
+                        assert!(list_tokens(dir.path()).is_ok());
+                    }
+                    tokens
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|w| w.join().unwrap())
+            .collect()
+    });
+
+    assert!(!tokens.is_empty());
+    assert_eq!(list_tokens(dir.path()).unwrap().len(), tokens.len());
+    for token in &tokens {
+        assert!(verify_token(dir.path(), token).unwrap());
+    }
+}
@@ -0,0 +1,189 @@
+use reqwest::blocking::Client;
+use savebutton_daemon::backend::{BackendError, Directory, KayaServer, PutOutcome, SyncBackend};
+use savebutton_daemon::download::DownloadError;
+use savebutton_daemon::integrity::sha256_file;
+use savebutton_daemon::transfer::TokenBucket;
+use std::collections::HashSet;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tiny_http::{Response, Server};
+
+fn local_file(dir: &std::path::Path, name: &str, body: &str) -> (std::path::PathBuf, String) {
+    let path = dir.join(name);
+    fs::write(&path, body).unwrap();
+    let sha = sha256_file(&path).unwrap();
+    (path, sha)
+}
+
+#[test]
+fn test_directory_round_trip() {
+    let remote = tempfile::tempdir().unwrap();
+    let local = tempfile::tempdir().unwrap();
+    let backend = Directory::new(remote.path());
+    let anga = "2026-01-01T000000-page.url";
+
+    assert!(backend
+        .list(&["words", anga], None)
+        .unwrap()
+        .names
+        .is_empty());
+
+    let (src, sha) = local_file(local.path(), "text.md", "plain words");
+    assert_eq!(
+        backend
+            .put(&["words", anga, "text.md"], &src, &sha)
+            .unwrap(),
+        PutOutcome::Stored
+    );
+    // The same bytes again are not an error, and report the stored digest
+    assert_eq!(
+        backend
+            .put(&["words", anga, "text.md"], &src, &sha)
+            .unwrap(),
+        PutOutcome::Exists(Some(sha.clone()))
+    );
+    // Different bytes never replace the stored file
+    let (other, other_sha) = local_file(local.path(), "other.md", "other words");
+    assert_eq!(
+        backend
+            .put(&["words", anga, "text.md"], &other, &other_sha)
+            .unwrap(),
+        PutOutcome::Exists(Some(sha.clone()))
+    );
+
+    assert_eq!(
+        backend.list(&["words"], None).unwrap().names,
+        HashSet::from([anga.to_string()])
+    );
+    let listing = backend.list(&["words", anga], None).unwrap();
+    assert_eq!(listing.names, HashSet::from(["text.md".to_string()]));
+    assert!(!listing.delta);
+    assert_eq!(listing.cursor, None);
+
+    let dest = local.path().join("copy").join("text.md");
+    let downloaded = backend.get(&["words", anga, "text.md"], &dest).unwrap();
+    assert_eq!(fs::read_to_string(&dest).unwrap(), "plain words");
+    assert_eq!(downloaded.sha256, sha);
+    assert_eq!(downloaded.size, 11);
+
+    assert_eq!(
+        backend.digest(&["words", anga, "text.md"]).unwrap(),
+        Some(sha)
+    );
+    assert_eq!(
+        backend.digest(&["words", anga, "missing.md"]).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn test_directory_get_never_replaces_local_anga() {
+    let remote = tempfile::tempdir().unwrap();
+    let local = tempfile::tempdir().unwrap();
+    let backend = Directory::new(remote.path());
+    let name = "2026-01-01T000000-note.md";
+    fs::create_dir_all(remote.path().join("anga")).unwrap();
+    fs::write(remote.path().join("anga").join(name), "remote").unwrap();
+
+    let dest = local.path().join(name);
+    fs::write(&dest, "local").unwrap();
+    let err = backend.get(&["anga", name], &dest).unwrap_err();
+    assert!(
+        matches!(err, BackendError::Download(DownloadError::Conflict(_))),
+        "{:?}",
+        err
+    );
+    assert_eq!(fs::read_to_string(&dest).unwrap(), "local");
+
+    // Smart lists may be replaced
+    fs::create_dir_all(remote.path().join("smart")).unwrap();
+    fs::write(remote.path().join("smart").join("list.toml"), "remote").unwrap();
+    let dest = local.path().join("list.toml");
+    fs::write(&dest, "local").unwrap();
+    backend.get(&["smart", "list.toml"], &dest).unwrap();
+    assert_eq!(fs::read_to_string(&dest).unwrap(), "remote");
+}
+
+#[test]
+fn test_directory_refuses_paths_outside_its_root() {
+    let root = tempfile::tempdir().unwrap();
+    let backend = Directory::new(root.path().join("mirror"));
+    let (src, sha) = local_file(root.path(), "secret.md", "secret");
+
+    let err = backend
+        .put(&["anga", "../escape.md"], &src, &sha)
+        .unwrap_err();
+    assert!(matches!(err, BackendError::UnsafeName(_)));
+    assert!(!err.is_transient());
+    assert!(matches!(
+        backend.list(&[".."], None),
+        Err(BackendError::UnsafeName(_))
+    ));
+    assert!(!root.path().join("escape.md").exists());
+}
+
+#[test]
+fn test_kaya_server_encodes_paths_and_reports_conflicts() {
+    let server = Server::http("127.0.0.1:0").unwrap();
+    let addr = server.server_addr().to_string();
+    let urls = Arc::new(Mutex::new(Vec::new()));
+    let seen = urls.clone();
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            seen.lock().unwrap().push(request.url().to_string());
+            let response = if request.url().ends_with("/taken.md") {
+                Response::from_string("exists").with_status_code(409)
+            } else {
+                Response::from_string("")
+            };
+            let _ = request.respond(response);
+        }
+    });
+
+    let dir = tempfile::tempdir().unwrap();
+    let backend = KayaServer::new(
+        Client::new(),
+        &format!("http://{}/", addr),
+        "me@example.com",
+        "pw",
+        Arc::new(TokenBucket::new(0)),
+        dir.path(),
+    );
+    let (src, sha) = local_file(dir.path(), "note.md", "note");
+
+    assert_eq!(
+        backend.put(&["anga", "my note.md"], &src, &sha).unwrap(),
+        PutOutcome::Stored
+    );
+    assert_eq!(
+        backend.put(&["anga", "taken.md"], &src, &sha).unwrap(),
+        PutOutcome::Exists(None)
+    );
+    assert_eq!(
+        urls.lock().unwrap()[0],
+        "/api/v1/me%40example.com/anga/my%20note.md"
+    );
+}
+
+#[test]
+fn test_only_directories_accept_uploads_of_generated_collections() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = KayaServer::new(
+        Client::new(),
+        "http://127.0.0.1:1",
+        "me",
+        "pw",
+        Arc::new(TokenBucket::new(0)),
+        dir.path(),
+    );
+    let directory = Directory::new(dir.path());
+    for collection in ["anga", "meta", "words"] {
+        assert!(server.accepts_uploads(collection));
+    }
+    for collection in ["anga", "meta", "words", "smart", "cache"] {
+        assert!(directory.accepts_uploads(collection));
+    }
+    assert!(!server.accepts_uploads("smart"));
+    assert!(!server.accepts_uploads("cache"));
+}
@@ -0,0 +1,138 @@
+use savebutton_daemon::codec::{
+    canonical, canonical_listing, migrate_dir, server_name, url_segment,
+};
+use std::fs;
+
+#[test]
+fn test_canonical_fully_decodes_single_and_double_encoding() {
+    assert_eq!(
+        canonical("2025-01-01T120000-India Income Tax.pdf"),
+        "2025-01-01T120000-India Income Tax.pdf"
+    );
+    assert_eq!(
+        canonical("2025-01-01T120000-India%20Income%20Tax.pdf"),
+        "2025-01-01T120000-India Income Tax.pdf"
+    );
+    assert_eq!(
+        canonical("2025-01-01T120000-India%2520Income%2520Tax.pdf"),
+        "2025-01-01T120000-India Income Tax.pdf"
+    );
+    assert_eq!(
+        canonical("2026-01-27T171207-www-deobald-ca.url"),
+        "2026-01-27T171207-www-deobald-ca.url"
+    );
+}
+
+#[test]
+fn test_canonical_leaves_stray_percent_signs_alone() {
+    assert_eq!(
+        canonical("2026-01-27T171207-50% off.url"),
+        "2026-01-27T171207-50% off.url"
+    );
+}
+
+#[test]
+fn test_server_name_decodes_exactly_once() {
+    assert_eq!(server_name("X%2520Y.pdf"), "X%20Y.pdf");
+    assert_eq!(server_name("X%20Y.pdf"), "X Y.pdf");
+    assert_eq!(server_name("plain.pdf"), "plain.pdf");
+}
+
+#[test]
+fn test_url_segment_round_trips_through_server_name() {
+    for name in ["X Y.pdf", "X%20Y.pdf", "a/b.txt", "ä ö.md"] {
+        assert_eq!(server_name(&url_segment(name)), name);
+    }
+    assert_eq!(url_segment("X Y.pdf"), "X%20Y.pdf");
+    assert_eq!(url_segment("a/b"), "a%2Fb");
+}
+
+#[test]
+fn test_canonical_listing_collapses_duplicates_onto_one_server_name() {
+    let listing = canonical_listing(vec![
+        "2025-01-01T120000-India%2520Income%2520Tax.pdf".to_string(),
+        "2025-01-01T120000-India%20Income%20Tax.pdf".to_string(),
+        "2026-01-27T171207-www-deobald-ca.url".to_string(),
+    ]);
+
+    assert_eq!(listing.len(), 2);
+    // Sorted order puts the singly-encoded wire name ('%20' < '%25') first
+    assert_eq!(
+        listing["2025-01-01T120000-India Income Tax.pdf"],
+        "2025-01-01T120000-India Income Tax.pdf"
+    );
+    assert_eq!(
+        listing["2026-01-27T171207-www-deobald-ca.url"],
+        "2026-01-27T171207-www-deobald-ca.url"
+    );
+}
+
+#[test]
+fn test_migrate_dir_renames_merges_and_reports_conflicts() {
+    let dir = tempfile::tempdir().unwrap();
+    let d = dir.path();
+    // Only an encoded copy: renamed
+    fs::write(d.join("2026-01-01T000000-A%20B.pdf"), "a").unwrap();
+    // Decoded and encoded copies with the same bytes: merged
+    fs::write(d.join("2026-01-02T000000-C D.pdf"), "c").unwrap();
+    fs::write(d.join("2026-01-02T000000-C%20D.pdf"), "c").unwrap();
+    fs::write(d.join("2026-01-02T000000-C%2520D.pdf"), "c").unwrap();
+    // Same name, different bytes: conflict, both kept
+    fs::write(d.join("2026-01-03T000000-E F.pdf"), "e1").unwrap();
+    fs::write(d.join("2026-01-03T000000-E%20F.pdf"), "e2").unwrap();
+    // Decoding would produce a path separator: conflict
+    fs::write(d.join("2026-01-04T000000-G%2FH.pdf"), "g").unwrap();
+
+    let report = migrate_dir(d).unwrap();
+
+    assert_eq!(report.renamed, vec!["2026-01-01T000000-A%20B.pdf"]);
+    assert_eq!(
+        report.merged,
+        vec![
+            "2026-01-02T000000-C%20D.pdf",
+            "2026-01-02T000000-C%2520D.pdf"
+        ]
+    );
+    assert_eq!(
+        report.conflicts,
+        vec!["2026-01-03T000000-E%20F.pdf", "2026-01-04T000000-G%2FH.pdf"]
+    );
+
+    let mut names: Vec<String> = fs::read_dir(d)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        vec![
+            "2026-01-01T000000-A B.pdf",
+            "2026-01-02T000000-C D.pdf",
+            "2026-01-03T000000-E F.pdf",
+            "2026-01-03T000000-E%20F.pdf",
+            "2026-01-04T000000-G%2FH.pdf",
+        ]
+    );
+}
+
+#[test]
+fn test_migrate_dir_merges_words_directories() {
+    let dir = tempfile::tempdir().unwrap();
+    let words = dir.path();
+    let canonical_dir = words.join("2026-01-01T000000-A B.pdf");
+    let encoded_dir = words.join("2026-01-01T000000-A%20B.pdf");
+    fs::create_dir_all(&canonical_dir).unwrap();
+    fs::create_dir_all(&encoded_dir).unwrap();
+    fs::write(canonical_dir.join("page-1.txt"), "one").unwrap();
+    fs::write(encoded_dir.join("page-1.txt"), "one").unwrap();
+    fs::write(encoded_dir.join("page-2.txt"), "two").unwrap();
+
+    let report = migrate_dir(words).unwrap();
+
+    assert_eq!(report.merged, vec!["2026-01-01T000000-A%20B.pdf"]);
+    assert!(!encoded_dir.exists());
+    assert_eq!(
+        fs::read_to_string(canonical_dir.join("page-2.txt")).unwrap(),
+        "two"
+    );
+}
@@ -0,0 +1,53 @@
+use savebutton_daemon::cors::{is_extension_origin, origin_allowed};
+
+#[test]
+fn test_is_extension_origin_accepts_all_browser_schemes() {
+    assert!(is_extension_origin(
+        "chrome-extension://kpdhgjmpibjlajlhagbgmnpjifbdbjhd"
+    ));
+    assert!(is_extension_origin(
+        "moz-extension://0c1f5e2a-3b4d-4e5f-8a9b-1c2d3e4f5a6b"
+    ));
+    assert!(is_extension_origin(
+        "safari-web-extension://4A1B2C3D-0000-1111-2222-333344445555"
+    ));
+
+    assert!(!is_extension_origin("https://evil.example.com"));
+    assert!(!is_extension_origin("http://localhost:3000"));
+    assert!(!is_extension_origin("null"));
+    assert!(!is_extension_origin("chrome-extension://"));
+}
+
+#[test]
+fn test_origin_allowed_matches_exact_entries_only() {
+    let allowlist = vec!["chrome-extension://kpdhgjmpibjlajlhagbgmnpjifbdbjhd".to_string()];
+
+    assert!(origin_allowed(
+        "chrome-extension://kpdhgjmpibjlajlhagbgmnpjifbdbjhd",
+        &allowlist
+    ));
+    assert!(!origin_allowed(
+        "chrome-extension://aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+        &allowlist
+    ));
+    assert!(!origin_allowed("https://example.com", &allowlist));
+    assert!(!origin_allowed("https://example.com", &[]));
+}
+
+#[test]
+fn test_origin_allowed_scheme_wildcard_never_matches_web_pages() {
+    let allowlist = vec!["moz-extension://*".to_string(), "https://*".to_string()];
+
+    assert!(origin_allowed(
+        "moz-extension://0c1f5e2a-3b4d-4e5f-8a9b-1c2d3e4f5a6b",
+        &allowlist
+    ));
+    assert!(!origin_allowed(
+        "chrome-extension://kpdhgjmpibjlajlhagbgmnpjifbdbjhd",
+        &allowlist
+    ));
+    assert!(
+        !origin_allowed("https://evil.example.com", &allowlist),
+        "wildcards only apply to extension schemes"
+    );
+}
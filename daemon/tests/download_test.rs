@@ -0,0 +1,339 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use reqwest::blocking::Client;
+use ring::digest::{digest, SHA256};
+use savebutton_daemon::download::{parse_content_range, DownloadError, PartialStore};
+use savebutton_daemon::transfer::TokenBucket;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tiny_http::{Response, Server};
+
+/// Serves `body` at every path over raw HTTP/1.1, honouring `Range: bytes=N-`.
+/// The first `truncate_first` responses announce the full length but close the
+/// connection after half of it. `extra_headers` are added to every response.
+/// Returns the base URL and the Range headers seen.
+fn serve(
+    body: Vec<u8>,
+    truncate_first: usize,
+    extra_headers: &str,
+) -> (String, Arc<Mutex<Vec<Option<String>>>>) {
+    let extra_headers = extra_headers.to_string();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let ranges = Arc::new(Mutex::new(Vec::new()));
+    let seen = ranges.clone();
+
+    thread::spawn(move || {
+        for (served, stream) in listener.incoming().enumerate() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut range = None;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 || line == "\r\n" {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("range") {
+                        range = Some(value.trim().to_string());
+                    }
+                }
+            }
+            seen.lock().unwrap().push(range.clone());
+
+            let start: usize = range
+                .as_deref()
+                .and_then(|r| r.strip_prefix("bytes="))
+                .and_then(|r| r.trim_end_matches('-').parse().ok())
+                .unwrap_or(0);
+            let rest = &body[start..];
+            let mut head = if start > 0 {
+                format!(
+                    "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\n",
+                    start,
+                    body.len() - 1,
+                    body.len()
+                )
+            } else {
+                "HTTP/1.1 200 OK\r\n".to_string()
+            };
+            head += &format!(
+                "ETag: \"v1\"\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                extra_headers,
+                rest.len()
+            );
+            let sent = if served < truncate_first {
+                &rest[..rest.len() / 2]
+            } else {
+                rest
+            };
+            let _ = stream.write_all(head.as_bytes());
+            let _ = stream.write_all(sent);
+        }
+    });
+    (url, ranges)
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    digest(&SHA256, data)
+        .as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn repr_digest(data: &[u8]) -> String {
+    format!(
+        "Repr-Digest: sha-256=:{}:\r\n",
+        BASE64.encode(digest(&SHA256, data))
+    )
+}
+
+fn body(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+#[test]
+fn test_download_writes_complete_file_and_cleans_up() {
+    let data = body(100_000);
+    let (url, _) = serve(data.clone(), 0, "");
+    let kaya = tempfile::tempdir().unwrap();
+    let dest = kaya.path().join("anga").join("2026-01-01T000000-a.pdf");
+    let client = Client::new();
+    let store = PartialStore::new(kaya.path());
+
+    let downloaded = store
+        .download(
+            &client,
+            client.get(format!("{}/a", url)).build().unwrap(),
+            &dest,
+            &Arc::new(TokenBucket::new(0)),
+            None,
+        )
+        .unwrap();
+
+    assert_eq!(downloaded.size, 100_000);
+    assert_eq!(downloaded.sha256, sha256_hex(&data));
+    assert!(!downloaded.verified);
+    assert_eq!(fs::read(&dest).unwrap(), data);
+    assert_eq!(
+        fs::read_dir(kaya.path().join(".partial")).unwrap().count(),
+        0
+    );
+}
+
+#[test]
+fn test_immutable_download_never_replaces_an_existing_file() {
+    let data = body(10_000);
+    let (url, _) = serve(data.clone(), 0, "");
+    let kaya = tempfile::tempdir().unwrap();
+    let client = Client::new();
+    let store = PartialStore::new(kaya.path());
+    let bandwidth = Arc::new(TokenBucket::new(0));
+    let anga = kaya.path().join("anga");
+    fs::create_dir_all(&anga).unwrap();
+
+    // An identical file that landed meanwhile is kept
+    let same = anga.join("2026-01-01T000000-same.pdf");
+    fs::write(&same, &data).unwrap();
+    let downloaded = store
+        .download_immutable(
+            &client,
+            client.get(format!("{}/same", url)).build().unwrap(),
+            &same,
+            &bandwidth,
+            None,
+        )
+        .unwrap();
+    assert_eq!(downloaded.sha256, sha256_hex(&data));
+
+    // A different one is a conflict and stays as it was
+    let other = anga.join("2026-01-01T000000-other.pdf");
+    fs::write(&other, "uploaded").unwrap();
+    let err = store
+        .download_immutable(
+            &client,
+            client.get(format!("{}/other", url)).build().unwrap(),
+            &other,
+            &bandwidth,
+            None,
+        )
+        .unwrap_err();
+    assert!(matches!(err, DownloadError::Conflict(_)), "{:?}", err);
+    assert!(!err.is_transient());
+    assert_eq!(fs::read_to_string(&other).unwrap(), "uploaded");
+    assert_eq!(
+        fs::read_dir(kaya.path().join(".partial")).unwrap().count(),
+        0
+    );
+}
+
+#[test]
+fn test_interrupted_download_resumes_with_range() {
+    let data = body(200_000);
+    let (url, ranges) = serve(data.clone(), 1, "");
+    let kaya = tempfile::tempdir().unwrap();
+    let dest = kaya.path().join("anga").join("2026-01-01T000000-big.pdf");
+    let client = Client::new();
+    let store = PartialStore::new(kaya.path());
+    let bandwidth = Arc::new(TokenBucket::new(0));
+    let file_url = format!("{}/big", url);
+
+    let err = store
+        .download(
+            &client,
+            client.get(&file_url).build().unwrap(),
+            &dest,
+            &bandwidth,
+            None,
+        )
+        .unwrap_err();
+    assert!(err.is_transient(), "{:?}", err);
+    assert!(!dest.exists());
+    let kept = store.progress(&file_url, &dest).unwrap();
+    assert!(kept > 0 && kept < 200_000);
+
+    store
+        .download(
+            &client,
+            client.get(&file_url).build().unwrap(),
+            &dest,
+            &bandwidth,
+            None,
+        )
+        .unwrap();
+
+    assert_eq!(fs::read(&dest).unwrap(), data);
+    assert_eq!(
+        ranges.lock().unwrap().clone(),
+        vec![None, Some(format!("bytes={}-", kept))]
+    );
+}
+
+#[test]
+fn test_download_is_verified_against_server_digest() {
+    let data = body(50_000);
+    let (url, _) = serve(data.clone(), 0, &repr_digest(&data));
+    let kaya = tempfile::tempdir().unwrap();
+    let dest = kaya.path().join("2026-01-01T000000-a.pdf");
+    let client = Client::new();
+
+    let downloaded = PartialStore::new(kaya.path())
+        .download(
+            &client,
+            client.get(format!("{}/a", url)).build().unwrap(),
+            &dest,
+            &Arc::new(TokenBucket::new(0)),
+            None,
+        )
+        .unwrap();
+    assert!(downloaded.verified);
+}
+
+#[test]
+fn test_download_with_wrong_digest_is_discarded() {
+    let data = body(50_000);
+    let (url, _) = serve(data, 0, &repr_digest(b"something else"));
+    let kaya = tempfile::tempdir().unwrap();
+    let dest = kaya.path().join("2026-01-01T000000-a.pdf");
+    let client = Client::new();
+    let store = PartialStore::new(kaya.path());
+    let file_url = format!("{}/a", url);
+
+    let err = store
+        .download(
+            &client,
+            client.get(&file_url).build().unwrap(),
+            &dest,
+            &Arc::new(TokenBucket::new(0)),
+            None,
+        )
+        .unwrap_err();
+    assert!(
+        matches!(err, DownloadError::DigestMismatch { .. }),
+        "{:?}",
+        err
+    );
+    assert!(!dest.exists());
+    assert_eq!(store.progress(&file_url, &dest), None);
+}
+
+#[test]
+fn test_download_must_match_a_trusted_digest_over_the_servers() {
+    // The response vouches for its own body, but the caller expects other bytes
+    let data = body(50_000);
+    let (url, _) = serve(data.clone(), 0, &repr_digest(&data));
+    let kaya = tempfile::tempdir().unwrap();
+    let dest = kaya.path().join("anga").join("2026-01-01T000000-a.pdf");
+    let client = Client::new();
+
+    let err = PartialStore::new(kaya.path())
+        .download_immutable(
+            &client,
+            client.get(format!("{}/a", url)).build().unwrap(),
+            &dest,
+            &Arc::new(TokenBucket::new(0)),
+            Some(&sha256_hex(b"something else")),
+        )
+        .unwrap_err();
+    assert!(
+        matches!(err, DownloadError::DigestMismatch { .. }),
+        "{:?}",
+        err
+    );
+    assert!(!dest.exists());
+
+    let downloaded = PartialStore::new(kaya.path())
+        .download_immutable(
+            &client,
+            client.get(format!("{}/a", url)).build().unwrap(),
+            &dest,
+            &Arc::new(TokenBucket::new(0)),
+            Some(&sha256_hex(&data)),
+        )
+        .unwrap();
+    assert!(downloaded.verified);
+    assert_eq!(fs::read(&dest).unwrap(), data);
+}
+
+#[test]
+fn test_non_success_status_is_reported() {
+    let server = Server::http("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/missing", server.server_addr().to_ip().unwrap());
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let _ = request.respond(Response::empty(404));
+        }
+    });
+    let kaya = tempfile::tempdir().unwrap();
+    let dest = kaya.path().join("missing.pdf");
+    let client = Client::new();
+
+    let err = PartialStore::new(kaya.path())
+        .download(
+            &client,
+            client.get(&url).build().unwrap(),
+            &dest,
+            &Arc::new(TokenBucket::new(0)),
+            None,
+        )
+        .unwrap_err();
+    assert!(matches!(err, DownloadError::Status(s) if s.as_u16() == 404));
+    assert!(!err.is_transient());
+    assert!(!dest.exists());
+}
+
+#[test]
+fn test_parse_content_range() {
+    assert_eq!(
+        parse_content_range("bytes 100-199/200"),
+        Some((100, Some(200)))
+    );
+    assert_eq!(parse_content_range("bytes 0-9/*"), Some((0, None)));
+    assert_eq!(parse_content_range("bytes 10-5/20"), None);
+    assert_eq!(parse_content_range("bytes 0-19/10"), None);
+    assert_eq!(parse_content_range("bytes */200"), None);
+    assert_eq!(parse_content_range("items 0-1/2"), None);
+}
@@ -0,0 +1,97 @@
+use chrono::{TimeZone, Timelike, Utc};
+use savebutton_daemon::filename::{
+    anga_timestamp, mime_type_for, parse_anga_filename, parse_meta_filename, FilenameError,
+};
+
+#[test]
+fn test_parse_anga_filename_extracts_timestamp_slug_and_extension() {
+    let parsed = parse_anga_filename("2026-01-27T171207-bookmark.url").unwrap();
+
+    assert_eq!(
+        parsed.timestamp,
+        Utc.with_ymd_and_hms(2026, 1, 27, 17, 12, 7).unwrap()
+    );
+    assert_eq!(parsed.slug, "bookmark");
+    assert_eq!(parsed.extension, "url");
+}
+
+#[test]
+fn test_parse_anga_filename_reads_nanosecond_suffix() {
+    let parsed = parse_anga_filename("2026-01-21T164145_354000000-note.md").unwrap();
+
+    assert_eq!(parsed.timestamp.nanosecond(), 354_000_000);
+    assert_eq!(parsed.timestamp.second(), 45);
+    assert_eq!(parsed.slug, "note");
+}
+
+#[test]
+fn test_parse_anga_filename_accepts_plain_and_percent_encoded_slugs() {
+    let plain = parse_anga_filename("2025-01-01T120000-India Income Tax.pdf").unwrap();
+    assert_eq!(plain.slug, "India Income Tax");
+
+    let encoded = parse_anga_filename("2026-02-04T021925-AES%20-%20Cessation.pdf").unwrap();
+    assert_eq!(encoded.slug, "AES%20-%20Cessation");
+    assert_eq!(encoded.extension, "pdf");
+}
+
+#[test]
+fn test_parse_anga_filename_rejects_malformed_names() {
+    let cases = [
+        ("bookmark.url", FilenameError::Timestamp),
+        ("2026-13-27T171207-bookmark.url", FilenameError::Timestamp),
+        ("2026-01-27T251207-bookmark.url", FilenameError::Timestamp),
+        ("2026-01-27 171207-bookmark.url", FilenameError::Timestamp),
+        ("2026-01-27T171207_354-note.md", FilenameError::Nanoseconds),
+        ("2026-01-27T171207bookmark.url", FilenameError::MissingSlug),
+        ("2026-01-27T171207-.url", FilenameError::MissingSlug),
+        (
+            "2026-01-27T171207-bookmark",
+            FilenameError::MissingExtension,
+        ),
+        (
+            "2026-01-27T171207-bookmark.",
+            FilenameError::MissingExtension,
+        ),
+        (
+            "2026-01-27T171207-../../etc/passwd",
+            FilenameError::InvalidCharacter,
+        ),
+        (
+            "2026-01-27T171207-a\\b.url",
+            FilenameError::InvalidCharacter,
+        ),
+        (
+            "2026-01-27T171207-a\nb.url",
+            FilenameError::InvalidCharacter,
+        ),
+        ("", FilenameError::Timestamp),
+    ];
+
+    for (name, expected) in cases {
+        assert_eq!(
+            parse_anga_filename(name).unwrap_err(),
+            expected,
+            "{:?}",
+            name
+        );
+    }
+}
+
+#[test]
+fn test_parse_meta_filename_requires_toml() {
+    assert!(parse_meta_filename("2026-01-27T171207-note.toml").is_ok());
+    assert!(parse_meta_filename("2026-01-27T171207-note.md").is_err());
+}
+
+#[test]
+fn test_anga_timestamp_returns_none_for_malformed_names() {
+    assert!(anga_timestamp("2026-01-27T171207-bookmark.url").is_some());
+    assert!(anga_timestamp("not-an-anga.url").is_none());
+}
+
+#[test]
+fn test_mime_type_for_extensions() {
+    assert_eq!(mime_type_for("a.md"), "text/markdown");
+    assert_eq!(mime_type_for("a.JPG"), "image/jpeg");
+    assert_eq!(mime_type_for("a"), "application/octet-stream");
+}
@@ -0,0 +1,88 @@
+use reqwest::header::{HeaderMap, HeaderValue};
+use savebutton_daemon::integrity::{
+    parse_sha256_field, repr_digest, server_digest, sha256_file, HashIndex,
+};
+use std::fs;
+
+// SHA-256 of "hello"
+const HELLO_HEX: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+const HELLO_B64: &str = "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=";
+
+#[test]
+fn test_sha256_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("hello.txt");
+    fs::write(&path, "hello").unwrap();
+    assert_eq!(sha256_file(&path).unwrap(), HELLO_HEX);
+}
+
+#[test]
+fn test_parse_sha256_field_accepts_rfc_9530_and_3230_forms() {
+    assert_eq!(
+        parse_sha256_field(&format!("sha-256=:{}:", HELLO_B64)).as_deref(),
+        Some(HELLO_HEX)
+    );
+    assert_eq!(
+        parse_sha256_field(&format!("md5=:abc=:, SHA-256={}", HELLO_B64)).as_deref(),
+        Some(HELLO_HEX)
+    );
+    assert_eq!(parse_sha256_field("sha-512=:abc:"), None);
+    assert_eq!(parse_sha256_field("sha-256=:not base64:"), None);
+    assert_eq!(parse_sha256_field("sha-256=:YWJj:"), None);
+}
+
+#[test]
+fn test_repr_digest_round_trips() {
+    assert_eq!(repr_digest(HELLO_HEX), format!("sha-256=:{}:", HELLO_B64));
+    assert_eq!(
+        parse_sha256_field(&repr_digest(HELLO_HEX)).as_deref(),
+        Some(HELLO_HEX)
+    );
+}
+
+#[test]
+fn test_server_digest_prefers_digest_headers_then_sha256_etags() {
+    let mut headers = HeaderMap::new();
+    headers.insert("etag", HeaderValue::from_static("\"abc123\""));
+    assert_eq!(server_digest(&headers), None);
+
+    headers.insert(
+        "etag",
+        HeaderValue::from_str(&format!("W/\"{}\"", HELLO_HEX.to_uppercase())).unwrap(),
+    );
+    assert_eq!(server_digest(&headers).as_deref(), Some(HELLO_HEX));
+
+    headers.insert("etag", HeaderValue::from_static("\"abc123\""));
+    headers.insert(
+        "repr-digest",
+        HeaderValue::from_str(&format!("sha-256=:{}:", HELLO_B64)).unwrap(),
+    );
+    assert_eq!(server_digest(&headers).as_deref(), Some(HELLO_HEX));
+}
+
+#[test]
+fn test_hash_index_caches_until_file_changes_and_persists() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir_all(dir.path().join("anga")).unwrap();
+    let rel = "anga/2026-01-01T000000-note.md";
+    fs::write(dir.path().join(rel), "hello").unwrap();
+
+    let mut index = HashIndex::load(dir.path());
+    assert_eq!(index.fresh(rel), None);
+    assert_eq!(index.hash(rel).unwrap(), HELLO_HEX);
+    assert_eq!(index.fresh(rel).as_deref(), Some(HELLO_HEX));
+    index.save().unwrap();
+
+    let mut index = HashIndex::load(dir.path());
+    assert_eq!(index.fresh(rel).as_deref(), Some(HELLO_HEX));
+
+    fs::write(dir.path().join(rel), "hello, world").unwrap();
+    assert_eq!(index.fresh(rel), None);
+    assert_ne!(index.hash(rel).unwrap(), HELLO_HEX);
+
+    fs::remove_file(dir.path().join(rel)).unwrap();
+    index.save().unwrap();
+    let saved: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(dir.path().join(".hashes")).unwrap()).unwrap();
+    assert!(saved.get(rel).is_none());
+}
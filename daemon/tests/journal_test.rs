@@ -0,0 +1,91 @@
+use chrono::Utc;
+use savebutton_daemon::journal::{fingerprint, Direction, SyncJournal};
+use std::time::{Duration, SystemTime};
+
+fn names(list: &[&str]) -> Vec<String> {
+    list.iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn test_fingerprint_ignores_order_and_separates_names() {
+    assert_eq!(
+        fingerprint(&names(&["a.md", "b.md"])),
+        fingerprint(&names(&["b.md", "a.md"]))
+    );
+    assert_ne!(
+        fingerprint(&names(&["a.md", "b.md"])),
+        fingerprint(&names(&["a.mdb.md"]))
+    );
+    assert_ne!(
+        fingerprint(&names(&["a.md"])),
+        fingerprint(&names(&["a.md", "b.md"]))
+    );
+}
+
+#[test]
+fn test_collection_is_unchanged_only_with_same_names_and_local_mtime() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut journal = SyncJournal::load(dir.path());
+    let listing = fingerprint(&names(&["a.md"]));
+    let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+    assert!(!journal.is_unchanged("anga", &listing, Some(mtime)));
+
+    journal.mark_synced("anga", listing.clone(), Some(mtime), Utc::now());
+    assert!(journal.is_unchanged("anga", &listing, Some(mtime)));
+    assert!(!journal.is_unchanged("meta", &listing, Some(mtime)));
+    assert!(!journal.is_unchanged("anga", &fingerprint(&names(&["a.md", "b.md"])), Some(mtime)));
+    assert!(!journal.is_unchanged("anga", &listing, Some(mtime + Duration::from_secs(1))));
+    // A directory whose mtime cannot be read is always rescanned
+    assert!(!journal.is_unchanged("anga", &listing, None));
+}
+
+#[test]
+fn test_journal_persists_collections_words_and_transfers() {
+    let dir = tempfile::tempdir().unwrap();
+    let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    let listing = fingerprint(&names(&["a.md"]));
+
+    let mut journal = SyncJournal::load(dir.path());
+    journal.mark_synced("anga", listing.clone(), Some(mtime), Utc::now());
+    let words = fingerprint(&names(&["a.md"]));
+    journal.mark_clean(
+        "words/2026-01-01T000000-page.url",
+        words.clone(),
+        Some(mtime),
+    );
+    journal.record_transfer("anga/a.md", Direction::Upload, "abc123", Utc::now());
+    journal.save().unwrap();
+
+    let journal = SyncJournal::load(dir.path());
+    assert!(journal.is_unchanged("anga", &listing, Some(mtime)));
+    assert!(journal.is_clean("words/2026-01-01T000000-page.url", &words, Some(mtime)));
+    assert!(!journal.is_clean(
+        "words/2026-01-01T000000-page.url",
+        &words,
+        Some(mtime + Duration::from_secs(1))
+    ));
+    assert!(!journal.is_clean(
+        "words/2026-01-01T000000-page.url",
+        &fingerprint(&names(&["a.md", "b.md"])),
+        Some(mtime)
+    ));
+    assert!(!journal.is_clean("cache/2026-01-01T000000-page.url", &words, Some(mtime)));
+
+    // Transfers are kept for reading by hand
+    let saved: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(dir.path().join(".sync-state")).unwrap())
+            .unwrap();
+    assert_eq!(saved["files"]["anga/a.md"]["direction"], "upload");
+    assert_eq!(saved["files"]["anga/a.md"]["sha256"], "abc123");
+}
+
+#[test]
+fn test_unreadable_journal_starts_empty() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join(".sync-state"), "not json").unwrap();
+    let journal = SyncJournal::load(dir.path());
+    let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    assert!(!journal.is_unchanged("anga", &fingerprint(&names(&[])), Some(mtime)));
+    assert!(journal.server_listing("anga").is_none());
+}
@@ -0,0 +1,45 @@
+use savebutton_daemon::limits::{DeadlineReader, InFlightLimit};
+use std::io::{self, Read};
+use std::time::Duration;
+
+#[test]
+fn test_in_flight_limit_sheds_load_and_releases_on_drop() {
+    let limit = InFlightLimit::new(2);
+
+    let a = limit.try_acquire().expect("first slot");
+    let _b = limit.try_acquire().expect("second slot");
+    assert!(
+        limit.try_acquire().is_none(),
+        "third upload must be refused"
+    );
+    assert_eq!(limit.in_flight(), 2);
+
+    drop(a);
+    assert_eq!(limit.in_flight(), 1);
+    assert!(limit.try_acquire().is_some());
+}
+
+#[test]
+fn test_in_flight_limit_is_shared_between_clones() {
+    let limit = InFlightLimit::new(1);
+    let other = limit.clone();
+
+    let _slot = limit.try_acquire().unwrap();
+    assert!(other.try_acquire().is_none());
+}
+
+#[test]
+fn test_deadline_reader_passes_through_before_deadline() {
+    let mut reader = DeadlineReader::new(&b"hello"[..], Duration::from_secs(60));
+    let mut out = String::new();
+    reader.read_to_string(&mut out).unwrap();
+    assert_eq!(out, "hello");
+}
+
+#[test]
+fn test_deadline_reader_times_out_after_deadline() {
+    let mut reader = DeadlineReader::new(&b"hello"[..], Duration::ZERO);
+    let mut out = Vec::new();
+    let err = reader.read_to_end(&mut out).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+}
@@ -0,0 +1,155 @@
+use reqwest::blocking::Client;
+use savebutton_daemon::listing::{fetch, ListingError};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tiny_http::{Header, Response, Server};
+
+const NAMES: [&str; 5] = [
+    "2026-01-01T000000-a.md",
+    "2026-01-02T000000-b.md",
+    "2026-01-03T000000-c.md",
+    "2026-01-04T000000-d.md",
+    "2026-01-05T000000-e.md",
+];
+
+/// A stand-in server listing `NAMES`. With `incremental` it honours `since` and
+/// `limit` and paginates with `Kaya-Cursor` / `Kaya-More`; without, it ignores the
+/// query like a server that predates incremental listings. Returns the base URL
+/// and the query strings seen.
+fn serve(incremental: bool) -> (String, Arc<Mutex<Vec<String>>>) {
+    let server = Server::http("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/api/v1/me/anga", server.server_addr());
+    let queries = Arc::new(Mutex::new(Vec::new()));
+    let seen = queries.clone();
+
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let query = request
+                .url()
+                .split_once('?')
+                .map(|(_, q)| q.to_string())
+                .unwrap_or_default();
+            seen.lock().unwrap().push(query.clone());
+
+            if !incremental {
+                let _ = request.respond(Response::from_string(NAMES.join("\n")));
+                continue;
+            }
+
+            let mut since = String::new();
+            let mut limit = usize::MAX;
+            for (k, v) in query.split('&').filter_map(|p| p.split_once('=')) {
+                match k {
+                    "since" => since = urlencoding::decode(v).unwrap().into_owned(),
+                    "limit" => limit = v.parse().unwrap(),
+                    _ => {}
+                }
+            }
+            let newer: Vec<&str> = NAMES
+                .iter()
+                .copied()
+                .filter(|n| *n > since.as_str())
+                .collect();
+            let page = &newer[..newer.len().min(limit)];
+            let cursor = page.last().copied().unwrap_or(since.as_str()).to_string();
+            let more = newer.len() > page.len();
+            let response = Response::from_string(page.join("\n"))
+                .with_header(Header::from_bytes("Kaya-Cursor", cursor).unwrap())
+                .with_header(
+                    Header::from_bytes("Kaya-More", if more { "true" } else { "false" }).unwrap(),
+                );
+            let _ = request.respond(response);
+        }
+    });
+    (url, queries)
+}
+
+fn set(names: &[&str]) -> HashSet<String> {
+    names.iter().map(|s| s.to_string()).collect()
+}
+
+#[test]
+fn test_full_listing_from_server_without_incremental_support() {
+    let (url, queries) = serve(false);
+    let listing = fetch(
+        &Client::new(),
+        &url,
+        "me",
+        "pw",
+        Some("2026-01-03T000000-c.md"),
+        2,
+    )
+    .unwrap();
+
+    // The cursor is ignored, so this is everything, not a delta
+    assert_eq!(listing.names, set(&NAMES));
+    assert!(!listing.delta);
+    assert_eq!(listing.cursor, None);
+    assert_eq!(queries.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn test_incremental_listing_follows_pages() {
+    let (url, queries) = serve(true);
+    let listing = fetch(&Client::new(), &url, "me", "pw", None, 2).unwrap();
+
+    assert_eq!(listing.names, set(&NAMES));
+    assert!(!listing.delta);
+    assert_eq!(listing.cursor.as_deref(), Some("2026-01-05T000000-e.md"));
+    assert_eq!(queries.lock().unwrap().len(), 3);
+}
+
+#[test]
+fn test_incremental_listing_since_cursor_returns_only_new_names() {
+    let (url, queries) = serve(true);
+    let listing = fetch(
+        &Client::new(),
+        &url,
+        "me",
+        "pw",
+        Some("2026-01-03T000000-c.md"),
+        10,
+    )
+    .unwrap();
+
+    assert_eq!(
+        listing.names,
+        set(&["2026-01-04T000000-d.md", "2026-01-05T000000-e.md"])
+    );
+    assert!(listing.delta);
+    assert_eq!(listing.cursor.as_deref(), Some("2026-01-05T000000-e.md"));
+    assert!(queries.lock().unwrap()[0].contains("since=2026-01-03T000000-c.md"));
+
+    // Nothing new: an empty delta that keeps the cursor
+    let listing = fetch(
+        &Client::new(),
+        &url,
+        "me",
+        "pw",
+        Some("2026-01-05T000000-e.md"),
+        10,
+    )
+    .unwrap();
+    assert!(listing.names.is_empty());
+    assert!(listing.delta);
+    assert_eq!(listing.cursor.as_deref(), Some("2026-01-05T000000-e.md"));
+}
+
+#[test]
+fn test_stalled_cursor_is_an_error() {
+    let server = Server::http("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/anga", server.server_addr());
+    thread::spawn(move || {
+        for request in server.incoming_requests().take(5) {
+            let response = Response::from_string("2026-01-01T000000-a.md")
+                .with_header(Header::from_bytes("Kaya-Cursor", "same").unwrap())
+                .with_header(Header::from_bytes("Kaya-More", "true").unwrap());
+            let _ = request.respond(response);
+        }
+    });
+
+    let err = fetch(&Client::new(), &url, "me", "pw", None, 1).unwrap_err();
+    assert!(matches!(err, ListingError::StalledCursor(ref c) if c == "same"));
+    assert!(!err.is_transient());
+}
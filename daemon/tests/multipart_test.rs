@@ -0,0 +1,74 @@
+use savebutton_daemon::multipart::{boundary, file_part};
+use std::io::{self, Read};
+
+/// Hands out its bytes one at a time, so every delimiter straddles reads.
+struct Trickle<'a>(&'a [u8]);
+
+impl Read for Trickle<'_> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.0.is_empty() || out.is_empty() {
+            return Ok(0);
+        }
+        out[0] = self.0[0];
+        self.0 = &self.0[1..];
+        Ok(1)
+    }
+}
+
+fn form(boundary: &str, parts: &[(&str, &str)]) -> Vec<u8> {
+    let mut body = String::new();
+    for (name, content) in parts {
+        body.push_str(&format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"x.md\"\r\n\
+             Content-Type: text/markdown\r\n\r\n{}\r\n",
+            boundary, name, content
+        ));
+    }
+    body.push_str(&format!("--{}--\r\n", boundary));
+    body.into_bytes()
+}
+
+fn read_part(body: &[u8], field: &str) -> io::Result<String> {
+    let mut content = String::new();
+    file_part(Trickle(body), "XyZ", field)?.read_to_string(&mut content)?;
+    Ok(content)
+}
+
+#[test]
+fn test_boundary_from_content_type() {
+    assert_eq!(
+        boundary("multipart/form-data; boundary=XyZ").as_deref(),
+        Some("XyZ")
+    );
+    assert_eq!(
+        boundary("Multipart/Form-Data; charset=utf-8; BOUNDARY=\"a b\"").as_deref(),
+        Some("a b")
+    );
+    assert_eq!(boundary("multipart/form-data"), None);
+    assert_eq!(boundary("text/plain; boundary=XyZ"), None);
+}
+
+#[test]
+fn test_file_part_streams_content_across_reads() {
+    // Content that looks like, but is not, a delimiter stays in the file
+    let content = "line one\r\n--XyNot a boundary\r\n-";
+    let body = form("XyZ", &[("note", "skipped"), ("file", content)]);
+    assert_eq!(read_part(&body, "file").unwrap(), content);
+    assert_eq!(read_part(&body, "note").unwrap(), "skipped");
+}
+
+#[test]
+fn test_file_part_rejects_missing_and_truncated_parts() {
+    let body = form("XyZ", &[("note", "only a note")]);
+    assert_eq!(
+        read_part(&body, "file").unwrap_err().kind(),
+        io::ErrorKind::InvalidData
+    );
+
+    let body = form("XyZ", &[("file", "cut short")]);
+    let truncated = &body[..body.len() - 12];
+    assert_eq!(
+        read_part(truncated, "file").unwrap_err().kind(),
+        io::ErrorKind::UnexpectedEof
+    );
+}
@@ -0,0 +1,340 @@
+use reqwest::blocking::Client;
+use savebutton_daemon::backend::BackendError;
+use savebutton_daemon::backend::{PutOutcome, SyncBackend};
+use savebutton_daemon::integrity::{parse_sha256_field, repr_digest, sha256_bytes, sha256_file};
+use savebutton_daemon::peer::{
+    generate_nonce, sign, sign_response, verify, verify_response, Beacon, NonceCache, Peer,
+    PeerAuthError, PeerHeaders, MAX_CLOCK_SKEW_SECS, NONCE_HEADER, SIGNATURE_HEADER, TIME_HEADER,
+};
+use savebutton_daemon::transfer::TokenBucket;
+use std::collections::HashSet;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tiny_http::{Header, Response, Server};
+
+const SECRET: &str = "shared secret";
+const NOW: i64 = 1_800_000_000;
+
+#[test]
+fn test_signature_round_trips_and_covers_every_field() {
+    let signature = sign(SECRET, "POST", "/peer/anga/a.md", NOW, "n1", Some("abc"));
+    let time = NOW.to_string();
+    let check = |secret, method, path, nonce, sha256| {
+        verify(
+            secret,
+            method,
+            path,
+            PeerHeaders {
+                time: Some(&time),
+                nonce: Some(nonce),
+                signature: Some(&signature),
+            },
+            sha256,
+            NOW,
+        )
+    };
+
+    assert_eq!(
+        check(SECRET, "POST", "/peer/anga/a.md", "n1", Some("abc")),
+        Ok(())
+    );
+    assert_eq!(
+        check(SECRET, "post", "/peer/anga/a.md", "n1", Some("abc")),
+        Ok(())
+    );
+    for result in [
+        check("other secret", "POST", "/peer/anga/a.md", "n1", Some("abc")),
+        check(SECRET, "GET", "/peer/anga/a.md", "n1", Some("abc")),
+        check(SECRET, "POST", "/peer/anga/b.md", "n1", Some("abc")),
+        check(SECRET, "POST", "/peer/anga/a.md", "n2", Some("abc")),
+        check(SECRET, "POST", "/peer/anga/a.md", "n1", Some("def")),
+        check(SECRET, "POST", "/peer/anga/a.md", "n1", None),
+    ] {
+        assert_eq!(result, Err(PeerAuthError::BadSignature));
+    }
+}
+
+#[test]
+fn test_verify_refuses_missing_and_stale_signatures() {
+    let signature = sign(SECRET, "GET", "/peer/anga", NOW, "n1", None);
+    let time = NOW.to_string();
+    let headers = PeerHeaders {
+        time: Some(&time),
+        nonce: Some("n1"),
+        signature: Some(&signature),
+    };
+    let at = |headers, now| verify(SECRET, "GET", "/peer/anga", headers, None, now);
+
+    assert_eq!(at(headers, NOW + MAX_CLOCK_SKEW_SECS), Ok(()));
+    assert_eq!(at(headers, NOW - MAX_CLOCK_SKEW_SECS), Ok(()));
+    assert_eq!(
+        at(headers, NOW + MAX_CLOCK_SKEW_SECS + 1),
+        Err(PeerAuthError::Stale)
+    );
+    for missing in [
+        PeerHeaders {
+            time: None,
+            ..headers
+        },
+        PeerHeaders {
+            nonce: None,
+            ..headers
+        },
+        PeerHeaders {
+            nonce: Some(""),
+            ..headers
+        },
+        PeerHeaders {
+            signature: None,
+            ..headers
+        },
+    ] {
+        assert_eq!(at(missing, NOW), Err(PeerAuthError::Missing));
+    }
+}
+
+#[test]
+fn test_nonce_cache_refuses_replays_within_the_window() {
+    let nonces = NonceCache::default();
+    assert_eq!(nonces.check("n1", NOW), Ok(()));
+    assert_eq!(nonces.check("n2", NOW), Ok(()));
+    assert_eq!(nonces.check("n1", NOW + 1), Err(PeerAuthError::Replayed));
+    // Forgotten only once no request carrying it could still be in time
+    assert_eq!(
+        nonces.check("n1", NOW + 2 * MAX_CLOCK_SKEW_SECS),
+        Err(PeerAuthError::Replayed)
+    );
+    assert_eq!(
+        nonces.check("n1", NOW + 2 * MAX_CLOCK_SKEW_SECS + 1),
+        Ok(())
+    );
+    assert_ne!(generate_nonce(), generate_nonce());
+}
+
+#[test]
+fn test_response_signatures_are_bound_to_the_request() {
+    let signature = sign_response(SECRET, 200, "/peer/anga", NOW, "n1", Some("abc"));
+    let time = NOW.to_string();
+    let check = |status, path, nonce, sha256| {
+        verify_response(
+            SECRET,
+            status,
+            path,
+            nonce,
+            PeerHeaders {
+                time: Some(&time),
+                nonce: None,
+                signature: Some(&signature),
+            },
+            sha256,
+            NOW,
+        )
+    };
+
+    assert_eq!(check(200, "/peer/anga", "n1", Some("abc")), Ok(()));
+    assert_eq!(
+        check(409, "/peer/anga", "n1", Some("abc")),
+        Err(PeerAuthError::BadSignature)
+    );
+    assert_eq!(
+        check(200, "/peer/anga", "n2", Some("abc")),
+        Err(PeerAuthError::BadSignature)
+    );
+    assert_eq!(
+        check(200, "/peer/anga", "n1", Some("abd")),
+        Err(PeerAuthError::BadSignature)
+    );
+    // A request's signature is not a response's
+    let request = sign(SECRET, "GET", "/peer/anga", NOW, "n1", None);
+    assert_ne!(
+        request,
+        sign_response(SECRET, 200, "/peer/anga", NOW, "n1", None)
+    );
+}
+
+#[test]
+fn test_beacons_round_trip_and_are_signed() {
+    let beacon = Beacon::new(SECRET, "00000000deadbeef", 21421, NOW);
+    assert_eq!(
+        Beacon::parse(beacon.encode().as_bytes()),
+        Some(beacon.clone())
+    );
+    assert_eq!(Beacon::parse(b"kaya-peer abc 1 2 n"), None);
+    assert_eq!(Beacon::parse(b"kaya-peer abc 1 2 n sig extra"), None);
+    assert_eq!(Beacon::parse(b"hello abc 1 2 n sig"), None);
+    assert!(!beacon.encode().contains(SECRET));
+
+    assert_eq!(beacon.verify(SECRET, NOW), Ok(()));
+    assert_eq!(
+        beacon.verify("other secret", NOW),
+        Err(PeerAuthError::BadSignature)
+    );
+    assert_eq!(
+        beacon.verify(SECRET, NOW + MAX_CLOCK_SKEW_SECS + 1),
+        Err(PeerAuthError::Stale)
+    );
+    // The port is signed, so a beacon cannot be redirected
+    let redirected = Beacon {
+        port: 8080,
+        ..beacon.clone()
+    };
+    assert_eq!(
+        redirected.verify(SECRET, NOW),
+        Err(PeerAuthError::BadSignature)
+    );
+    // Repeating a beacon is caught like a replayed request
+    let nonces = NonceCache::default();
+    assert_eq!(nonces.check(&beacon.nonce, NOW), Ok(()));
+    assert_eq!(
+        nonces.check(&beacon.nonce, NOW),
+        Err(PeerAuthError::Replayed)
+    );
+    assert_ne!(
+        Beacon::new(SECRET, "00000000deadbeef", 21421, NOW).nonce,
+        beacon.nonce
+    );
+}
+
+/// A stand-in peer that checks every signature and nonce, lists `anga` and stores
+/// uploads, signing its responses with `secret`. Returns its URL and the uploaded
+/// bodies.
+fn serve(secret: &'static str) -> (String, Arc<Mutex<Vec<Vec<u8>>>>) {
+    let server = Server::http("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", server.server_addr());
+    let uploads = Arc::new(Mutex::new(Vec::new()));
+    let stored = uploads.clone();
+
+    thread::spawn(move || {
+        let nonces = NonceCache::default();
+        for mut request in server.incoming_requests() {
+            let header = |name: &'static str| {
+                request
+                    .headers()
+                    .iter()
+                    .find(|h| h.field.equiv(name))
+                    .map(|h| h.value.as_str().to_string())
+            };
+            let declared = header("Repr-Digest").and_then(|v| parse_sha256_field(&v));
+            let (time, nonce, signature) = (
+                header(TIME_HEADER),
+                header(NONCE_HEADER),
+                header(SIGNATURE_HEADER),
+            );
+            let now = chrono::Utc::now().timestamp();
+            let verified = verify(
+                SECRET,
+                request.method().as_str(),
+                request.url(),
+                PeerHeaders {
+                    time: time.as_deref(),
+                    nonce: nonce.as_deref(),
+                    signature: signature.as_deref(),
+                },
+                declared.as_deref(),
+                now,
+            )
+            .and_then(|()| nonces.check(nonce.as_deref().unwrap_or_default(), now));
+            let (status, body, sha256) = match (verified, request.url()) {
+                (Err(_), _) => (401, "", None),
+                (Ok(()), "/peer/anga") => {
+                    let listing = "2026-01-01T000000-a%20b.md";
+                    (200, listing, Some(sha256_bytes(listing.as_bytes())))
+                }
+                (Ok(()), "/peer/anga/2026-01-02T000000-taken.md") => (409, "", None),
+                (Ok(()), _) => {
+                    let mut body = Vec::new();
+                    request.as_reader().read_to_end(&mut body).unwrap();
+                    stored.lock().unwrap().push(body);
+                    (201, "", declared)
+                }
+            };
+            let time = chrono::Utc::now().timestamp();
+            let signature = sign_response(
+                secret,
+                status,
+                request.url(),
+                time,
+                nonce.as_deref().unwrap_or_default(),
+                sha256.as_deref(),
+            );
+            let mut response = Response::from_string(body)
+                .with_status_code(status)
+                .with_header(Header::from_bytes(TIME_HEADER, time.to_string()).unwrap())
+                .with_header(Header::from_bytes(SIGNATURE_HEADER, signature).unwrap());
+            if let (201, Some(sha256)) = (status, &sha256) {
+                response
+                    .add_header(Header::from_bytes("Repr-Digest", repr_digest(sha256)).unwrap());
+            }
+            let _ = request.respond(response);
+        }
+    });
+    (url, uploads)
+}
+
+#[test]
+fn test_peer_backend_signs_requests() {
+    let (url, uploads) = serve(SECRET);
+    let dir = tempfile::tempdir().unwrap();
+    let peer = |secret| {
+        Peer::new(
+            Client::new(),
+            &url,
+            secret,
+            Arc::new(TokenBucket::new(0)),
+            dir.path(),
+        )
+    };
+
+    let listing = peer(SECRET).list(&["anga"], None).unwrap();
+    assert_eq!(
+        listing.names,
+        HashSet::from(["2026-01-01T000000-a%20b.md".to_string()])
+    );
+    assert!(peer("wrong secret").list(&["anga"], None).is_err());
+
+    let src = dir.path().join("note.md");
+    fs::write(&src, "note").unwrap();
+    let sha = sha256_file(&src).unwrap();
+    assert_eq!(
+        peer(SECRET)
+            .put(&["anga", "2026-01-02T000000-note.md"], &src, &sha)
+            .unwrap(),
+        PutOutcome::Stored
+    );
+    assert_eq!(
+        peer(SECRET)
+            .put(&["anga", "2026-01-02T000000-taken.md"], &src, &sha)
+            .unwrap(),
+        PutOutcome::Exists(None)
+    );
+    assert_eq!(*uploads.lock().unwrap(), vec![b"note".to_vec()]);
+
+    assert!(peer(SECRET).accepts_uploads("words"));
+    assert!(!peer(SECRET).collections().contains(&"cache"));
+}
+
+#[test]
+fn test_peer_backend_refuses_responses_not_signed_with_the_secret() {
+    // A host that answers every request but does not hold the secret
+    let (url, _) = serve("other secret");
+    let dir = tempfile::tempdir().unwrap();
+    let peer = Peer::new(
+        Client::new(),
+        &url,
+        SECRET,
+        Arc::new(TokenBucket::new(0)),
+        dir.path(),
+    );
+
+    assert!(matches!(
+        peer.list(&["anga"], None),
+        Err(BackendError::PeerAuth(PeerAuthError::BadSignature))
+    ));
+    let dest = dir.path().join("anga").join("2026-01-01T000000-a b.md");
+    assert!(matches!(
+        peer.get(&["anga", "2026-01-01T000000-a%20b.md"], &dest),
+        Err(BackendError::PeerAuth(_))
+    ));
+    assert!(!dest.exists());
+}
@@ -0,0 +1,108 @@
+use chrono::{TimeZone, Utc};
+use savebutton_daemon::query::{type_matches, Clause, Query, QueryError};
+
+fn term(t: &str) -> Clause {
+    Clause::Term(t.to_string())
+}
+
+#[test]
+fn test_parse_terms_phrases_and_filters() {
+    let query = Query::parse(
+        r#"Democracy tag:Podcast type:PDF after:2026-01-01 before:2026-02-01T12:00:00+01:00 "Exact  phrase""#,
+    )
+    .unwrap();
+    assert_eq!(
+        query.include,
+        [
+            term("democracy"),
+            Clause::Tag("podcast".to_string()),
+            Clause::Type("pdf".to_string()),
+            Clause::After(Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap()),
+            Clause::Before(Utc.with_ymd_and_hms(2026, 2, 1, 11, 0, 0).unwrap()),
+            Clause::Phrase(vec!["exact".to_string(), "phrase".to_string()]),
+        ]
+    );
+    assert!(query.exclude.is_empty());
+}
+
+#[test]
+fn test_parse_exclusions_and_edge_cases() {
+    let query =
+        Query::parse(r#"-draft -tag:old -"to do" - well-known "one" "unterminated quote"#).unwrap();
+    assert_eq!(
+        query.exclude,
+        [
+            term("draft"),
+            Clause::Tag("old".to_string()),
+            Clause::Phrase(vec!["to".to_string(), "do".to_string()]),
+        ]
+    );
+    // A lone '-' is no clause; hyphenated words split; a one-word phrase is a term;
+    // an open quote runs to the end
+    assert_eq!(
+        query.include,
+        [
+            term("well"),
+            term("known"),
+            term("one"),
+            Clause::Phrase(vec!["unterminated".to_string(), "quote".to_string()]),
+        ]
+    );
+    // Unknown keys are plain words
+    assert_eq!(
+        Query::parse("see:also").unwrap().include,
+        [term("see"), term("also")]
+    );
+    assert!(Query::parse("  ").unwrap().is_empty());
+}
+
+#[test]
+fn test_parse_rejects_bad_filter_values() {
+    assert!(matches!(
+        Query::parse("after:yesterday"),
+        Err(QueryError::Date { key: "after", .. })
+    ));
+    assert_eq!(Query::parse("tag:"), Err(QueryError::MissingValue("tag")));
+}
+
+#[test]
+fn test_type_matches_extension_and_mime_type() {
+    let pdf = "2026-01-10T090000-report.PDF";
+    for kind in ["pdf", "application/pdf", "application"] {
+        assert!(type_matches(pdf, kind), "{}", kind);
+    }
+    assert!(type_matches("2026-01-10T090000-photo.jpg", "image"));
+    assert!(type_matches("2026-01-10T090000-photo.jpg", "jpeg"));
+    assert!(type_matches("2026-01-10T090000-note.md", "markdown"));
+    assert!(!type_matches(pdf, "image"));
+}
+
+#[test]
+fn test_parse_prefixes() {
+    let prefix = |t: &str| Clause::Prefix(t.to_string());
+    assert_eq!(
+        Query::parse("coop* demo").unwrap().include,
+        [prefix("coop"), term("demo")]
+    );
+    // As typed, the last word is unfinished unless a space follows it
+    assert_eq!(
+        Query::parse_as_typed("worker coop").unwrap().include,
+        [term("worker"), prefix("coop")]
+    );
+    assert_eq!(
+        Query::parse_as_typed("worker coop ").unwrap().include,
+        [term("worker"), term("coop")]
+    );
+    // Only a plain word can be unfinished
+    let query = Query::parse_as_typed("coop tag:pod").unwrap();
+    assert_eq!(query.include[0], term("coop"));
+    assert_eq!(Query::parse_as_typed("\"a b\"").unwrap().include.len(), 1);
+    assert_eq!(
+        Query::parse_as_typed("coop -draft").unwrap().exclude,
+        [term("draft")]
+    );
+    assert_eq!(
+        Query::parse_as_typed("coop").unwrap().include,
+        [Clause::Prefix("coop".to_string())]
+    );
+}
@@ -0,0 +1,123 @@
+use chrono::{TimeDelta, Utc};
+use savebutton_daemon::retry::{FailureLog, RetryPolicy};
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::time::Duration;
+
+const NO_WAIT: RetryPolicy = RetryPolicy {
+    max_attempts: 3,
+    base_delay: Duration::ZERO,
+    max_delay: Duration::ZERO,
+};
+
+#[test]
+fn test_delay_grows_exponentially_with_jitter_and_cap() {
+    let policy = RetryPolicy {
+        max_attempts: 10,
+        base_delay: Duration::from_millis(100),
+        max_delay: Duration::from_secs(1),
+    };
+    for _ in 0..50 {
+        let first = policy.delay(1);
+        assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+        let third = policy.delay(3);
+        assert!(third >= Duration::from_millis(200) && third <= Duration::from_millis(400));
+        let capped = policy.delay(40);
+        assert!(capped >= Duration::from_millis(500) && capped <= Duration::from_secs(1));
+    }
+}
+
+#[test]
+fn test_run_retries_transient_errors_until_success() {
+    let calls = Cell::new(0);
+    let result: Result<&str, &str> = NO_WAIT.run(
+        || {
+            calls.set(calls.get() + 1);
+            if calls.get() < 3 {
+                Err("transient")
+            } else {
+                Ok("done")
+            }
+        },
+        |_| true,
+    );
+    assert_eq!(result, Ok("done"));
+    assert_eq!(calls.get(), 3);
+}
+
+#[test]
+fn test_run_gives_up_after_max_attempts_or_permanent_error() {
+    let calls = Cell::new(0);
+    let result: Result<(), &str> = NO_WAIT.run(
+        || {
+            calls.set(calls.get() + 1);
+            Err("still down")
+        },
+        |_| true,
+    );
+    assert_eq!(result, Err("still down"));
+    assert_eq!(calls.get(), 3);
+
+    calls.set(0);
+    let result: Result<(), &str> = NO_WAIT.run(
+        || {
+            calls.set(calls.get() + 1);
+            Err("not found")
+        },
+        |e| *e != "not found",
+    );
+    assert_eq!(result, Err("not found"));
+    assert_eq!(calls.get(), 1);
+}
+
+#[test]
+fn test_failure_log_defers_with_doubling_cooldown_and_clears_on_success() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut log = FailureLog::load(dir.path()).unwrap();
+    let now = Utc::now();
+
+    let entry = log.record_failure("anga", "2026-01-01T000000-a.pdf", "HTTP 500", now);
+    assert_eq!(entry.failures, 1);
+    assert_eq!(entry.retry_after, now + TimeDelta::minutes(1));
+    let entry = log.record_failure("anga", "2026-01-01T000000-a.pdf", "HTTP 500", now);
+    assert_eq!(entry.retry_after, now + TimeDelta::minutes(2));
+
+    assert!(log.is_deferred("anga", "2026-01-01T000000-a.pdf", now));
+    assert!(!log.is_deferred(
+        "anga",
+        "2026-01-01T000000-a.pdf",
+        now + TimeDelta::minutes(2)
+    ));
+    assert!(!log.is_deferred("meta", "2026-01-01T000000-a.pdf", now));
+
+    for _ in 0..40 {
+        log.record_failure("anga", "2026-01-01T000000-a.pdf", "HTTP 500", now);
+    }
+    assert!(!log.is_deferred("anga", "2026-01-01T000000-a.pdf", now + TimeDelta::hours(6)));
+
+    log.record_success("anga", "2026-01-01T000000-a.pdf");
+    assert_eq!(log.entries().count(), 0);
+}
+
+#[test]
+fn test_failure_log_persists_and_forgets_files_no_longer_pending() {
+    let dir = tempfile::tempdir().unwrap();
+    let now = Utc::now();
+    let mut log = FailureLog::load(dir.path()).unwrap();
+    log.record_failure("anga", "2026-01-01T000000-a.pdf", "timed out", now);
+    log.record_failure("anga", "2026-01-02T000000-b.pdf", "timed out", now);
+    log.record_failure("meta", "2026-01-01T000000-a.toml", "timed out", now);
+    log.save().unwrap();
+
+    let mut log = FailureLog::load(dir.path()).unwrap();
+    assert_eq!(log.entries().count(), 3);
+    assert_eq!(log.entries().next().unwrap().last_error, "timed out");
+
+    let pending: HashSet<String> = ["2026-01-02T000000-b.pdf".to_string()].into();
+    log.retain_pending("anga", &pending);
+    let names: Vec<_> = log.entries().map(|e| e.name.as_str()).collect();
+    assert_eq!(
+        names,
+        vec!["2026-01-02T000000-b.pdf", "2026-01-01T000000-a.toml"]
+    );
+}
@@ -0,0 +1,85 @@
+use savebutton_daemon::sanitize::{check_component, safe_join, Quarantine, UnsafeName};
+use std::path::Path;
+
+#[test]
+fn test_check_component_accepts_ordinary_names() {
+    for name in [
+        "2026-01-27T171207-bookmark.url",
+        "2025-01-01T120000-India Income Tax.pdf",
+        "2026-02-04T021925-AES%20-%20Cessation.pdf",
+        "plaintext.txt",
+        "console.log",
+    ] {
+        assert_eq!(check_component(name), Ok(()), "{:?}", name);
+    }
+}
+
+#[test]
+fn test_check_component_rejects_traversal_and_platform_hazards() {
+    let cases = [
+        ("", UnsafeName::Empty),
+        ("..", UnsafeName::DotName),
+        (".", UnsafeName::DotName),
+        (".config", UnsafeName::Hidden),
+        ("../../.bashrc", UnsafeName::Hidden),
+        ("anga/../../etc", UnsafeName::Separator),
+        ("/etc/passwd", UnsafeName::Separator),
+        ("..\\..\\Windows", UnsafeName::Hidden),
+        ("a\\b", UnsafeName::Separator),
+        ("bad\0name", UnsafeName::Control),
+        ("bad\nname", UnsafeName::Control),
+        ("NUL", UnsafeName::Reserved),
+        ("com1.txt", UnsafeName::Reserved),
+        ("trailing.", UnsafeName::TrailingDotOrSpace),
+        ("trailing ", UnsafeName::TrailingDotOrSpace),
+    ];
+    for (name, expected) in cases {
+        assert_eq!(check_component(name), Err(expected), "{:?}", name);
+    }
+
+    assert_eq!(check_component(&"a".repeat(256)), Err(UnsafeName::TooLong));
+}
+
+#[test]
+fn test_check_component_colon_only_rejected_on_windows() {
+    let result = check_component("2026-01-27T171207-File:Foo.png");
+    if cfg!(windows) {
+        assert_eq!(result, Err(UnsafeName::Separator));
+    } else {
+        assert_eq!(result, Ok(()));
+    }
+}
+
+#[test]
+fn test_safe_join_stays_inside_directory() {
+    let dir = Path::new("/home/user/.kaya/anga");
+
+    assert_eq!(
+        safe_join(dir, "2026-01-27T171207-bookmark.url").unwrap(),
+        dir.join("2026-01-27T171207-bookmark.url")
+    );
+    assert!(safe_join(dir, "../meta/x.toml").is_err());
+    assert!(safe_join(dir, "/tmp/x").is_err());
+}
+
+#[test]
+fn test_quarantine_records_each_name_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let quarantine = Quarantine::new(dir.path());
+
+    assert!(quarantine
+        .record("anga", "../../.bashrc", "name is hidden")
+        .unwrap());
+    assert!(!quarantine
+        .record("anga", "../../.bashrc", "name is hidden")
+        .unwrap());
+    assert!(quarantine
+        .record("words", "../../.bashrc", "name is hidden")
+        .unwrap());
+
+    let entries = quarantine.entries().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].collection, "anga");
+    assert_eq!(entries[0].name, "../../.bashrc");
+    assert_eq!(entries[1].collection, "words");
+}
@@ -0,0 +1,394 @@
+use savebutton_daemon::query::Query;
+use savebutton_daemon::search::{Hit, SearchIndex};
+use std::fs;
+use std::path::Path;
+
+const BOOKMARK: &str = "2026-01-28T205208-bookmark.url";
+const PDF: &str = "2026-01-10T090000-report.pdf";
+const NOTE: &str = "2026-01-27T171207-note.md";
+
+fn write(root: &Path, rel: &str, body: &str) {
+    let path = root.join(rel);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, body).unwrap();
+}
+
+/// A `~/.kaya` with a bookmark's words, a PDF's words, a note and meta for the
+/// bookmark.
+fn sample() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    write(
+        root,
+        &format!("anga/{}", BOOKMARK),
+        "[InternetShortcut]\nURL=https://example.com\n",
+    );
+    write(
+        root,
+        &format!("words/{}/text.md", BOOKMARK),
+        "Worker cooperatives share ownership. A podcast about democracy at work.",
+    );
+    write(
+        root,
+        &format!("words/{}/text.md", PDF),
+        "Quarterly report on cooperatives, cooperatives and more cooperatives.",
+    );
+    write(
+        root,
+        &format!("anga/{}", NOTE),
+        "Remember to call the bakery.",
+    );
+    write(
+        root,
+        "meta/2026-01-29T100000-tags.toml",
+        &format!(
+            "[anga]\nfilename = \"{}\"\n\n[meta]\ntags = [\"podcast\", \"guest\"]\nnote = '''I was a guest on this show.'''\n",
+            BOOKMARK
+        ),
+    );
+    dir
+}
+
+/// Replaces a file the way the daemon does, by renaming a new one into place.
+fn replace(root: &Path, rel: &str, body: &str) {
+    let path = root.join(rel);
+    let temp = path.with_file_name(".replacement");
+    fs::write(&temp, body).unwrap();
+    fs::rename(temp, path).unwrap();
+}
+
+fn search(index: &SearchIndex, query: &str, limit: usize) -> Vec<Hit> {
+    index.search(&Query::parse(query).unwrap(), limit)
+}
+
+fn angas(index: &SearchIndex, query: &str) -> Vec<String> {
+    search(index, query, 10)
+        .into_iter()
+        .map(|h| h.anga)
+        .collect()
+}
+
+#[test]
+fn test_search_ranks_words_notes_and_meta() {
+    let dir = sample();
+    let mut index = SearchIndex::load(dir.path());
+    // Every anga is a source by name, notes by their text too
+    assert_eq!(index.refresh().unwrap(), 5);
+    assert_eq!(index.documents(), 3);
+
+    assert_eq!(angas(&index, "bakery"), [NOTE]);
+    // Meta tags and notes find the anga they describe
+    assert_eq!(angas(&index, "guest"), [BOOKMARK]);
+    // More occurrences rank higher; either anga matches
+    assert_eq!(angas(&index, "cooperatives"), [PDF, BOOKMARK]);
+    assert_eq!(angas(&index, "podcast cooperatives")[0], BOOKMARK);
+    assert!(angas(&index, "zebra").is_empty());
+    assert!(angas(&index, "  ").is_empty());
+}
+
+#[test]
+fn test_hits_carry_snippet_and_source() {
+    let dir = sample();
+    let mut index = SearchIndex::load(dir.path());
+    index.refresh().unwrap();
+
+    let hit = &search(&index, "democracy", 1)[0];
+    assert_eq!(hit.anga, BOOKMARK);
+    assert_eq!(hit.source, format!("words/{}/text.md", BOOKMARK));
+    assert!(hit.snippet.contains("democracy at work"));
+    assert!(hit.score > 0.0);
+    // Prose is preferred over a tag list matching as many terms
+    let hit = &search(&index, "democracy podcast", 1)[0];
+    assert_eq!(hit.source, format!("words/{}/text.md", BOOKMARK));
+
+    // Long text is cut around the match
+    let long = format!("{} needle {}", "filler ".repeat(100), "tail ".repeat(100));
+    write(dir.path(), &format!("words/{}/long.txt", PDF), &long);
+    index.update(&format!("words/{}/long.txt", PDF)).unwrap();
+    let snippet = &search(&index, "needle", 1)[0].snippet;
+    assert!(snippet.starts_with("… ") && snippet.ends_with(" …"));
+    assert!(snippet.contains("needle") && snippet.chars().count() < 200);
+}
+
+#[test]
+fn test_refresh_picks_up_new_changed_and_removed_files() {
+    let dir = sample();
+    let root = dir.path();
+    let mut index = SearchIndex::load(root);
+    index.refresh().unwrap();
+    assert_eq!(index.refresh().unwrap(), 0);
+
+    let words = format!("words/{}/text.md", PDF);
+    replace(root, &words, "Annual figures about bicycles.");
+    write(
+        root,
+        &format!("words/{}/extra.md", NOTE),
+        "sourdough recipe",
+    );
+    fs::remove_file(root.join(format!("anga/{}", NOTE))).unwrap();
+    assert_eq!(index.refresh().unwrap(), 3);
+
+    assert_eq!(angas(&index, "bicycles"), [PDF]);
+    assert_eq!(angas(&index, "cooperatives"), [BOOKMARK]);
+    assert!(angas(&index, "bakery").is_empty());
+    assert_eq!(angas(&index, "sourdough"), [NOTE]);
+
+    // A single file is updated without a refresh, and dropped once gone
+    fs::remove_file(root.join(&words)).unwrap();
+    assert!(index.update(&words).unwrap());
+    assert!(angas(&index, "bicycles").is_empty());
+    assert!(!index.update("anga/not-an-anga.md").unwrap());
+}
+
+#[test]
+fn test_index_persists_across_loads() {
+    let dir = sample();
+    let mut index = SearchIndex::load(dir.path());
+    index.refresh().unwrap();
+    index.save().unwrap();
+    assert!(dir.path().join(".search-index").is_file());
+
+    let mut reloaded = SearchIndex::load(dir.path());
+    assert_eq!(reloaded.documents(), 3);
+    assert_eq!(angas(&reloaded, "guest"), [BOOKMARK]);
+    assert_eq!(reloaded.refresh().unwrap(), 0);
+
+    // A source removed after loading takes its terms, and the words written for
+    // them, with it
+    let words = format!("words/{}/text.md", BOOKMARK);
+    fs::remove_file(dir.path().join(&words)).unwrap();
+    assert!(reloaded.update(&words).unwrap());
+    assert!(reloaded
+        .search(&Query::parse_as_typed("democ").unwrap(), 10)
+        .is_empty());
+    assert_eq!(angas(&reloaded, "cooperatives"), [PDF]);
+    assert_eq!(angas(&reloaded, "podcast"), [BOOKMARK]);
+
+    // A corrupt index is rebuilt rather than trusted
+    fs::write(dir.path().join(".search-index"), "{not json").unwrap();
+    assert_eq!(SearchIndex::load(dir.path()).documents(), 0);
+}
+
+#[test]
+fn test_filters_narrow_and_exclude() {
+    let dir = sample();
+    let mut index = SearchIndex::load(dir.path());
+    index.refresh().unwrap();
+
+    // Filters alone list every match, newest first
+    assert_eq!(angas(&index, "after:2026-01-01"), [BOOKMARK, NOTE, PDF]);
+    assert_eq!(angas(&index, "tag:Podcast"), [BOOKMARK]);
+    assert_eq!(angas(&index, "type:pdf"), [PDF]);
+    assert_eq!(angas(&index, "type:text"), [BOOKMARK, NOTE]);
+    assert_eq!(angas(&index, "after:2026-01-27 before:2026-01-28"), [NOTE]);
+    assert_eq!(angas(&index, "before:2026-01-28T20:52:08Z"), [NOTE, PDF]);
+
+    assert_eq!(angas(&index, "cooperatives -tag:podcast"), [PDF]);
+    assert_eq!(angas(&index, "cooperatives -democracy"), [PDF]);
+    assert_eq!(angas(&index, "cooperatives type:url"), [BOOKMARK]);
+    assert!(angas(&index, "tag:podcast -type:url").is_empty());
+}
+
+#[test]
+fn test_phrases_match_words_in_order() {
+    let dir = sample();
+    let mut index = SearchIndex::load(dir.path());
+    index.refresh().unwrap();
+
+    assert_eq!(angas(&index, "\"democracy at work\""), [BOOKMARK]);
+    assert!(angas(&index, "\"work at democracy\"").is_empty());
+    assert_eq!(angas(&index, "cooperatives -\"share ownership\""), [PDF]);
+    // Phrases rank by their words, and are required alongside plain terms
+    assert_eq!(angas(&index, "cooperatives \"more cooperatives\""), [PDF]);
+}
+
+#[test]
+fn test_phrases_fill_the_limit_past_better_ranked_anga_without_them() {
+    let dir = sample();
+    let root = dir.path();
+    let jumbled = "2026-02-01T080000-jumbled.url";
+    write(
+        root,
+        &format!("words/{}/text.md", jumbled),
+        "Work, work and work: democracy here, democracy there.",
+    );
+    let mut index = SearchIndex::load(root);
+    index.refresh().unwrap();
+
+    // The jumbled anga ranks first on its words, but has no phrase
+    assert_eq!(search(&index, "democracy work", 1)[0].anga, jumbled);
+    let hits = search(&index, "\"democracy at work\"", 1);
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].anga, BOOKMARK);
+    assert!(hits[0].snippet.contains("democracy at work"));
+    assert!(search(&index, "work -\"democracy at work\"", 10)
+        .iter()
+        .all(|h| h.anga != BOOKMARK));
+
+    // Only what is left after the index is released reads files
+    let query = Query::parse("\"democracy at work\"").unwrap();
+    let candidates = index.candidates(&query, 1);
+    fs::remove_file(root.join(format!("words/{}/text.md", BOOKMARK))).unwrap();
+    assert!(candidates.hits().is_empty());
+}
+
+#[test]
+fn test_matches_inflections_and_unspaced_scripts() {
+    let dir = sample();
+    let root = dir.path();
+    let german = "2026-02-01T080000-genossenschaft.url";
+    let hindi = "2026-02-02T080000-lekh.url";
+    let japanese = "2026-02-03T080000-tokyo.url";
+    write(
+        root,
+        &format!("words/{}/text.md", german),
+        "Die Genossenschaften gehören ihren Mitgliedern und arbeiten demokratisch.",
+    );
+    write(
+        root,
+        &format!("words/{}/text.md", hindi),
+        "लड़कियों ने स्कूल में किताबें पढ़ीं और खेल खेले।",
+    );
+    write(
+        root,
+        &format!("words/{}/text.md", japanese),
+        "東京タワーに行きました。とても高かったです。",
+    );
+    let mut index = SearchIndex::load(root);
+    index.refresh().unwrap();
+
+    // Each document is stemmed in its own language
+    assert_eq!(angas(&index, "cooperative"), [PDF, BOOKMARK]);
+    assert_eq!(angas(&index, "Genossenschaft"), [german]);
+    assert_eq!(angas(&index, "किताब"), [hindi]);
+    assert_eq!(angas(&index, "लड़की"), [hindi]);
+    // Japanese words are found inside unspaced text, and only whole
+    assert_eq!(angas(&index, "タワー"), [japanese]);
+    assert_eq!(angas(&index, "東京"), [japanese]);
+    assert!(angas(&index, "京都").is_empty());
+    assert_eq!(angas(&index, "東"), [japanese]);
+    // A lone character also matches the pair it ends a run with
+    assert_eq!(angas(&index, "す"), [japanese]);
+    // Full-width query text matches too
+    assert_eq!(angas(&index, "ＰＯＤＣＡＳＴ"), [BOOKMARK]);
+
+    let hit = &search(&index, "行きました", 1)[0];
+    assert!(hit.snippet.contains("東京タワーに行きました"));
+
+    let words = format!("words/{}/text.md", japanese);
+    fs::remove_file(root.join(&words)).unwrap();
+    index.update(&words).unwrap();
+    assert!(angas(&index, "す").is_empty());
+    assert!(angas(&index, "東").is_empty());
+}
+
+#[test]
+fn test_prefixes_and_typos_match_ranked_below_exact() {
+    let dir = sample();
+    let root = dir.path();
+    let mut index = SearchIndex::load(root);
+    index.refresh().unwrap();
+    let typed = |index: &SearchIndex, query: &str| -> Vec<String> {
+        index
+            .search(&Query::parse_as_typed(query).unwrap(), 10)
+            .into_iter()
+            .map(|h| h.anga)
+            .collect()
+    };
+
+    // Unfinished words match what they start, through their stems too
+    assert_eq!(typed(&index, "democ"), [BOOKMARK]);
+    assert_eq!(typed(&index, "cooperat"), [PDF, BOOKMARK]);
+    assert_eq!(angas(&index, "bake*"), [NOTE]);
+    assert!(angas(&index, "democ").is_empty());
+
+    // Typos are forgiven, more in longer words
+    assert_eq!(angas(&index, "democarcy"), [BOOKMARK]);
+    assert_eq!(angas(&index, "cooperatvies"), [PDF, BOOKMARK]);
+    assert_eq!(angas(&index, "bakrey"), [NOTE]);
+    assert!(angas(&index, "ork").is_empty());
+    // ...but not when excluding or in phrases
+    assert_eq!(angas(&index, "cooperatives -democarcy"), [PDF, BOOKMARK]);
+    assert!(angas(&index, "\"democarcy at work\"").is_empty());
+
+    // An exact match outranks a typo, however often the typo occurs
+    write(
+        root,
+        &format!("words/{}/baker.md", PDF),
+        "The baker, the baker and the baker again.",
+    );
+    index.refresh().unwrap();
+    assert_eq!(angas(&index, "bakery"), [NOTE, PDF]);
+    assert_eq!(angas(&index, "baker"), [PDF, NOTE]);
+    let hit = &search(&index, "bakery", 2)[1];
+    assert!(hit.snippet.starts_with("The baker"));
+}
+
+#[test]
+fn test_related_combines_text_tags_and_time() {
+    let dir = sample();
+    let root = dir.path();
+    let text = "Worker cooperatives share ownership. A podcast about democracy at work.";
+    let near = "2026-01-29T120000-coops.url";
+    let far = "2025-06-01T120000-coops.url";
+    write(root, &format!("words/{}/text.md", near), text);
+    write(root, &format!("words/{}/text.md", far), text);
+    write(
+        root,
+        "meta/2026-03-01T000000-interview.toml",
+        "[anga]\nfilename = \"2026-03-01T000000-interview.url\"\n\n[meta]\ntags = [\"Podcast\"]\n",
+    );
+    let mut index = SearchIndex::load(root);
+    index.refresh().unwrap();
+
+    let related = index.related(BOOKMARK, 10).unwrap();
+    let names: Vec<&str> = related.iter().map(|r| r.anga.as_str()).collect();
+    // The same text ranks the anga saved closer in time first; nothing is shared
+    // with the note
+    assert_eq!(names[..2], [near, far]);
+    assert_eq!(related[0].text, related[1].text);
+    assert!(related[0].text > 0.5 && related[0].days < 1.0);
+    assert!(names.contains(&PDF));
+    assert!(!names.contains(&NOTE) && !names.contains(&BOOKMARK));
+    // Tags alone relate anga, ignoring case
+    let interview = related
+        .iter()
+        .find(|r| r.anga == "2026-03-01T000000-interview.url")
+        .unwrap();
+    assert_eq!(
+        (interview.text, interview.tags.as_slice()),
+        (0.0, &["podcast".to_string()][..])
+    );
+
+    assert_eq!(index.related(BOOKMARK, 1).unwrap().len(), 1);
+    assert!(index.related("2026-01-01T000000-unknown.url", 10).is_none());
+
+    // Similarity follows changes to the index
+    fs::remove_file(root.join(format!("words/{}/text.md", near))).unwrap();
+    index.refresh().unwrap();
+    assert_eq!(index.related(BOOKMARK, 1).unwrap()[0].anga, far);
+}
+
+#[test]
+fn test_related_reads_only_the_index() {
+    let dir = sample();
+    let root = dir.path();
+    let text = "Worker cooperatives share ownership. A podcast about democracy at work.";
+    let copy = "2026-01-29T120000-coops.url";
+    write(root, &format!("words/{}/text.md", copy), text);
+    let mut index = SearchIndex::load(root);
+    index.refresh().unwrap();
+    index.save().unwrap();
+    let before = index.related(BOOKMARK, 10).unwrap();
+
+    // Until the next refresh, the files are not looked at again, even by an
+    // index loaded from disk
+    fs::remove_dir_all(root.join("words")).unwrap();
+    assert_eq!(index.related(BOOKMARK, 10).unwrap(), before);
+    assert_eq!(
+        SearchIndex::load(root).related(BOOKMARK, 10).unwrap(),
+        before
+    );
+    assert_eq!(before[0].anga, copy);
+    assert!(before[0].text > 0.5);
+}
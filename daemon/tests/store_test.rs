@@ -0,0 +1,160 @@
+use savebutton_daemon::integrity::sha256_bytes;
+use savebutton_daemon::store::{
+    files_equal, write_immutable, write_immutable_digest, write_streamed, StoreError, WriteOutcome,
+};
+use std::fs;
+
+fn entries(dir: &std::path::Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .unwrap()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().into_string().ok())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn test_write_streamed_writes_file_and_leaves_no_temp() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let n = write_streamed(
+        dir.path(),
+        "2026-01-27T171207-note.md",
+        &b"hello world"[..],
+        1024,
+    )
+    .unwrap();
+
+    assert_eq!(n, 11);
+    assert_eq!(
+        fs::read(dir.path().join("2026-01-27T171207-note.md")).unwrap(),
+        b"hello world"
+    );
+    assert_eq!(entries(dir.path()), vec!["2026-01-27T171207-note.md"]);
+}
+
+#[test]
+fn test_write_streamed_rejects_oversized_body_without_writing() {
+    let dir = tempfile::tempdir().unwrap();
+    let body = vec![b'x'; 2048];
+
+    let err = write_streamed(dir.path(), "2026-01-27T171207-big.pdf", &body[..], 1024).unwrap_err();
+
+    assert!(matches!(err, StoreError::TooLarge { limit: 1024 }));
+    assert!(
+        entries(dir.path()).is_empty(),
+        "neither the file nor its temp file may remain"
+    );
+}
+
+#[test]
+fn test_write_streamed_accepts_body_exactly_at_limit() {
+    let dir = tempfile::tempdir().unwrap();
+    let body = vec![b'x'; 1024];
+
+    assert_eq!(
+        write_streamed(dir.path(), "exact.bin", &body[..], 1024).unwrap(),
+        1024
+    );
+}
+
+#[test]
+fn test_write_streamed_creates_missing_directories() {
+    let dir = tempfile::tempdir().unwrap();
+    let nested = dir
+        .path()
+        .join("words")
+        .join("2026-01-27T171207-www-deobald-ca.url");
+
+    write_streamed(&nested, "plaintext.txt", &b"words"[..], 1024).unwrap();
+
+    assert_eq!(fs::read(nested.join("plaintext.txt")).unwrap(), b"words");
+}
+
+#[test]
+fn test_write_immutable_creates_new_file() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let outcome =
+        write_immutable(dir.path(), "2026-01-27T171207-note.md", &b"v1"[..], 1024).unwrap();
+
+    assert_eq!(outcome, WriteOutcome::Created);
+    assert_eq!(
+        fs::read(dir.path().join("2026-01-27T171207-note.md")).unwrap(),
+        b"v1"
+    );
+}
+
+#[test]
+fn test_write_immutable_identical_content_is_a_no_op() {
+    let dir = tempfile::tempdir().unwrap();
+    write_immutable(dir.path(), "2026-01-27T171207-note.md", &b"v1"[..], 1024).unwrap();
+
+    let outcome =
+        write_immutable(dir.path(), "2026-01-27T171207-note.md", &b"v1"[..], 1024).unwrap();
+
+    assert_eq!(outcome, WriteOutcome::Unchanged);
+    assert_eq!(entries(dir.path()), vec!["2026-01-27T171207-note.md"]);
+}
+
+#[test]
+fn test_write_immutable_refuses_to_overwrite_different_content() {
+    let dir = tempfile::tempdir().unwrap();
+    write_immutable(dir.path(), "2026-01-27T171207-note.md", &b"v1"[..], 1024).unwrap();
+
+    let err =
+        write_immutable(dir.path(), "2026-01-27T171207-note.md", &b"v2"[..], 1024).unwrap_err();
+
+    assert!(matches!(err, StoreError::Conflict(_)));
+    assert_eq!(
+        fs::read(dir.path().join("2026-01-27T171207-note.md")).unwrap(),
+        b"v1",
+        "original content must survive"
+    );
+    assert_eq!(entries(dir.path()), vec!["2026-01-27T171207-note.md"]);
+}
+
+#[test]
+fn test_write_immutable_digest_never_names_a_mismatched_body() {
+    let dir = tempfile::tempdir().unwrap();
+    let name = "2026-01-27T171207-note.md";
+
+    let err = write_immutable_digest(
+        dir.path(),
+        name,
+        &b"corrupt"[..],
+        1024,
+        &sha256_bytes(b"v1"),
+    )
+    .unwrap_err();
+    assert!(matches!(err, StoreError::DigestMismatch { .. }));
+    assert!(
+        entries(dir.path()).is_empty(),
+        "temp file must be cleaned up"
+    );
+
+    let outcome =
+        write_immutable_digest(dir.path(), name, &b"v1"[..], 1024, &sha256_bytes(b"v1")).unwrap();
+    assert_eq!(outcome, WriteOutcome::Created);
+    assert_eq!(fs::read(dir.path().join(name)).unwrap(), b"v1");
+}
+
+#[test]
+fn test_files_equal_compares_content() {
+    let dir = tempfile::tempdir().unwrap();
+    let (a, b, c) = (
+        dir.path().join("a"),
+        dir.path().join("b"),
+        dir.path().join("c"),
+    );
+    let big = vec![7u8; 20_000];
+    let mut other = big.clone();
+    other[19_999] = 8;
+    fs::write(&a, &big).unwrap();
+    fs::write(&b, &big).unwrap();
+    fs::write(&c, &other).unwrap();
+
+    assert!(files_equal(&a, &b).unwrap());
+    assert!(!files_equal(&a, &c).unwrap());
+}
@@ -1,4 +1,11 @@
+use savebutton_daemon::integrity::{repr_digest, sha256_bytes};
 use savebutton_daemon::parse_server_file_listing;
+use savebutton_daemon::peer::{sign_response, NONCE_HEADER, SIGNATURE_HEADER, TIME_HEADER};
+use std::fs;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tiny_http::{Header, Response, Server};
 
 #[test]
 fn test_parse_server_file_listing_preserves_url_encoding() {
@@ -35,3 +42,66 @@ fn test_parse_server_file_listing_skips_empty_lines() {
     assert!(files.contains("file1.url"));
     assert!(files.contains("file2.url"));
 }
+
+#[test]
+fn test_sync_fetches_files_added_to_an_already_synced_words_directory() {
+    let anga = "2026-01-01T000000-page.url";
+    let server = Server::http("127.0.0.1:0").unwrap();
+    let addr = server.server_addr().to_string();
+    let words = Arc::new(Mutex::new(vec!["a.md"]));
+    let served = words.clone();
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let url = request.url().to_string();
+            let segments: Vec<&str> = url.trim_start_matches("/peer/").split('/').collect();
+            let body = match segments.as_slice() {
+                ["words"] => format!("{}\n", anga),
+                ["words", dir] if *dir == anga => served.lock().unwrap().join("\n"),
+                ["words", _, name] => format!("words in {}", name),
+                _ => String::new(),
+            };
+            let sha256 = sha256_bytes(body.as_bytes());
+            let nonce = request
+                .headers()
+                .iter()
+                .find(|h| h.field.equiv(NONCE_HEADER))
+                .map(|h| h.value.to_string())
+                .unwrap_or_default();
+            let time = chrono::Utc::now().timestamp();
+            let signature = sign_response("secret", 200, &url, time, &nonce, Some(&sha256));
+            let response = Response::from_string(body)
+                .with_header(Header::from_bytes("Repr-Digest", repr_digest(&sha256)).unwrap())
+                .with_header(Header::from_bytes(TIME_HEADER, time.to_string()).unwrap())
+                .with_header(Header::from_bytes(SIGNATURE_HEADER, signature).unwrap());
+            let _ = request.respond(response);
+        }
+    });
+
+    let root = tempfile::tempdir().unwrap();
+    fs::write(
+        root.path().join(".config"),
+        format!("peer_secret = \"secret\"\npeers = [\"http://{}\"]\n", addr),
+    )
+    .unwrap();
+    let sync = || {
+        let status = Command::new(env!("CARGO_BIN_EXE_savebutton-daemon"))
+            .arg("--root")
+            .arg(root.path())
+            .arg("sync")
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+    let words_dir = root.path().join("words").join(anga);
+
+    sync();
+    assert!(words_dir.join("a.md").exists());
+
+    // The directory is unchanged locally; only the server gained a file
+    words.lock().unwrap().push("b.md");
+    sync();
+    assert_eq!(
+        fs::read_to_string(words_dir.join("b.md")).unwrap(),
+        "words in b.md"
+    );
+}
@@ -0,0 +1,106 @@
+use savebutton_daemon::text::{
+    analyze, detect_language, fold, stem, tokenize, within_edits, Language,
+};
+use std::collections::BTreeMap;
+
+fn terms(text: &str) -> Vec<String> {
+    tokenize(text).into_iter().map(|t| t.term).collect()
+}
+
+#[test]
+fn test_tokenize_lowercases_words_with_offsets() {
+    let tokens = tokenize("Hello, Wörld! 42x");
+    let terms: Vec<&str> = tokens.iter().map(|t| t.term.as_str()).collect();
+    assert_eq!(terms, ["hello", "wörld", "42x"]);
+    assert_eq!((tokens[1].start, tokens[1].end), (7, 13));
+    assert!(tokenize(&"a".repeat(65)).is_empty());
+}
+
+#[test]
+fn test_tokenize_normalizes_and_keeps_combining_marks() {
+    // Full-width letters, ligatures and decomposed accents fold to plain text
+    assert_eq!(terms("ＡＢＣ ﬁle Cafe\u{301}"), ["abc", "file", "café"]);
+    assert_eq!(fold("Cafe\u{301}"), fold("CAFÉ"));
+    // Vowel signs and viramas stay inside Devanagari words
+    assert_eq!(terms("हिन्दी में किताबें"), ["हिन्दी", "में", "किताबें"]);
+}
+
+#[test]
+fn test_tokenize_pairs_cjk_characters() {
+    let tokens = tokenize("東京タワー, Tokyo");
+    let pairs: Vec<&str> = tokens.iter().map(|t| t.term.as_str()).collect();
+    assert_eq!(pairs, ["東京", "京タ", "タワ", "ワー", "tokyo"]);
+    assert_eq!((tokens[1].start, tokens[1].end), (3, 9));
+    // A lone character is itself, and half-width kana fold to full width
+    assert_eq!(terms("猫 ｶﾀｶﾅ"), ["猫", "カタ", "タカ", "カナ"]);
+}
+
+#[test]
+fn test_detects_language_of_longer_text() {
+    let english = "Worker cooperatives share ownership. A podcast about democracy at work.";
+    let german = "Die Genossenschaften gehören ihren Mitgliedern und arbeiten demokratisch.";
+    let hindi = "लड़कियों ने स्कूल में किताबें पढ़ीं और खेल खेले।";
+    assert_eq!(detect_language(english), Some(Language::English));
+    assert_eq!(detect_language(german), Some(Language::German));
+    assert_eq!(detect_language(hindi), Some(Language::Hindi));
+    // Too short to tell, or a language that is not stemmed
+    assert_eq!(detect_language("podcast guest"), None);
+    assert_eq!(
+        detect_language("東京タワーに行きました。とても高かったです。"),
+        None
+    );
+}
+
+#[test]
+fn test_stems_inflected_forms_together() {
+    let same = |a: &str, b: &str, language| {
+        assert_eq!(
+            stem(&fold(a), Some(language)),
+            stem(&fold(b), Some(language))
+        )
+    };
+    same("cooperatives", "Cooperative", Language::English);
+    same("Genossenschaften", "Genossenschaft", Language::German);
+    same("Häuser", "Haus", Language::German);
+    same("लड़कियों", "लड़की", Language::Hindi);
+    same("किताबें", "किताब", Language::Hindi);
+    // Unknown languages and CJK pairs are left alone
+    assert_eq!(stem("cooperatives", None), "cooperatives");
+    assert_eq!(stem("東京", Some(Language::English)), "東京");
+
+    let tokens = analyze("Running dogs", Some(Language::English));
+    assert_eq!(tokens[0].term, "run");
+    assert_eq!((tokens[1].start, tokens[1].end), (8, 12));
+}
+
+#[test]
+fn test_finds_words_within_edits() {
+    let words: BTreeMap<String, ()> = [
+        "cat", "deceive", "receipt", "receive", "received", "receiver", "recipe", "relieve",
+    ]
+    .iter()
+    .map(|w| (w.to_string(), ()))
+    .collect();
+
+    // A swap of neighbours is one edit
+    assert_eq!(
+        within_edits(&words, "recieve", 1),
+        [("receive", 1), ("relieve", 1)]
+    );
+    assert_eq!(
+        within_edits(&words, "recieve", 2),
+        [
+            ("deceive", 2),
+            ("receive", 1),
+            ("received", 2),
+            ("receiver", 2),
+            ("recipe", 2),
+            ("relieve", 1)
+        ]
+    );
+    assert!(within_edits(&words, "receive", 1).contains(&("receive", 0)));
+    assert!(within_edits(&words, "dog", 1).is_empty());
+    // Characters beyond ASCII are single characters
+    let words: BTreeMap<String, ()> = [("straße".to_string(), ())].into();
+    assert_eq!(within_edits(&words, "strase", 1), [("straße", 1)]);
+}
@@ -0,0 +1,72 @@
+use savebutton_daemon::transfer::{map_parallel, ThrottledReader, TokenBucket};
+use std::io::Read;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_unlimited_bucket_never_waits() {
+    let bucket = TokenBucket::new(0);
+    assert_eq!(bucket.rate(), None);
+    let start = Instant::now();
+    bucket.take(usize::MAX);
+    assert!(start.elapsed() < Duration::from_millis(50));
+}
+
+#[test]
+fn test_bucket_holds_long_run_rate() {
+    // One second of burst, then 100 KB more at 200 KB/s should take ~0.5s
+    let bucket = TokenBucket::new(200_000);
+    let start = Instant::now();
+    bucket.take(200_000);
+    assert!(start.elapsed() < Duration::from_millis(100));
+    bucket.take(100_000);
+    assert!(start.elapsed() >= Duration::from_millis(400));
+}
+
+#[test]
+fn test_throttled_reader_passes_data_through_and_shares_the_cap() {
+    let bucket = Arc::new(TokenBucket::new(100_000));
+    bucket.take(100_000);
+
+    let start = Instant::now();
+    let handles: Vec<_> = (0..2)
+        .map(|_| {
+            let bucket = bucket.clone();
+            thread::spawn(move || {
+                let data = vec![7u8; 25_000];
+                let mut out = Vec::new();
+                ThrottledReader::new(&data[..], bucket)
+                    .read_to_end(&mut out)
+                    .unwrap();
+                out == data
+            })
+        })
+        .collect();
+    for h in handles {
+        assert!(h.join().unwrap());
+    }
+    // 50 KB across both readers at 100 KB/s
+    assert!(start.elapsed() >= Duration::from_millis(400));
+}
+
+#[test]
+fn test_map_parallel_preserves_order_and_caps_concurrency() {
+    let items: Vec<usize> = (0..40).collect();
+    let running = AtomicUsize::new(0);
+    let peak = AtomicUsize::new(0);
+
+    let results = map_parallel(&items, 3, |i| {
+        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+        peak.fetch_max(now, Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(2));
+        running.fetch_sub(1, Ordering::SeqCst);
+        i * 2
+    });
+
+    assert_eq!(results, items.iter().map(|i| i * 2).collect::<Vec<_>>());
+    assert!(peak.load(Ordering::SeqCst) <= 3);
+    assert!(peak.load(Ordering::SeqCst) > 1);
+    assert!(map_parallel(&Vec::<usize>::new(), 3, |i| *i).is_empty());
+}
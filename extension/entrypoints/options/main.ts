@@ -1,5 +1,9 @@
 import { browser } from "wxt/browser";
-import { pushConfigToDaemon } from "@/utils/daemon";
+import {
+  isDaemonPaired,
+  pairWithDaemon,
+  pushConfigToDaemon,
+} from "@/utils/daemon";
 import { loadConfig, saveConfig } from "@/utils/config";
 
 const serverInput = document.getElementById("server") as HTMLInputElement;
@@ -7,6 +11,10 @@ const emailInput = document.getElementById("email") as HTMLInputElement;
 const passwordInput = document.getElementById("password") as HTMLInputElement;
 const saveBtn = document.getElementById("save-btn") as HTMLButtonElement;
 const testBtn = document.getElementById("test-btn") as HTMLButtonElement;
+const daemonCodeInput = document.getElementById(
+  "daemon-code",
+) as HTMLInputElement;
+const pairBtn = document.getElementById("pair-btn") as HTMLButtonElement;
 const statusDiv = document.getElementById("status")!;
 
 const PASSWORD_SENTINEL = "\u2022\u2022\u2022\u2022\u2022\u2022\u2022\u2022";
@@ -32,6 +40,9 @@ async function loadSettings() {
       passwordInput.value = PASSWORD_SENTINEL;
       passwordChanged = false;
     }
+    if (await isDaemonPaired()) {
+      daemonCodeInput.placeholder = "Paired (enter a new code to re-pair)";
+    }
   } catch (error) {
     console.error("Failed to load settings:", error);
   }
@@ -118,7 +129,34 @@ async function doTestConnection() {
   }
 }
 
+async function doPairDaemon() {
+  const code = daemonCodeInput.value.trim();
+  if (!code) {
+    showStatus("Pairing code is required", "error");
+    return;
+  }
+
+  showStatus("Pairing with daemon...", "info");
+
+  try {
+    await pairWithDaemon(code);
+    daemonCodeInput.value = "";
+    daemonCodeInput.placeholder = "Paired (enter a new code to re-pair)";
+
+    // Now that writes are authorized, hand the daemon our server config
+    const config = await loadConfig();
+    if (config.configured) {
+      pushConfigToDaemon(config);
+    }
+
+    showStatus("Daemon paired successfully", "success");
+  } catch (error: any) {
+    showStatus("Pairing failed: " + error.message, "error");
+  }
+}
+
 saveBtn.addEventListener("click", saveSettings);
+pairBtn.addEventListener("click", doPairDaemon);
 testBtn.addEventListener("click", doTestConnection);
 
 loadSettings();
@@ -1,3 +1,4 @@
+import { browser } from "wxt/browser";
 import { type Config } from "./config";
 
 const DAEMON_BASE = "http://localhost:21420";
@@ -16,6 +17,38 @@ async function fetchWithTimeout(
   }
 }
 
+async function authHeaders(): Promise<Record<string, string>> {
+  const result = await browser.storage.local.get("daemonToken");
+  const token = result.daemonToken as string | undefined;
+  return token ? { Authorization: `Bearer ${token}` } : {};
+}
+
+export async function isDaemonPaired(): Promise<boolean> {
+  const result = await browser.storage.local.get("daemonToken");
+  return !!result.daemonToken;
+}
+
+/**
+ * Trades a one-time pairing code (from `savebutton-daemon pair`) for a bearer
+ * token, which is stored and sent with every write to the daemon.
+ */
+export async function pairWithDaemon(code: string): Promise<void> {
+  const response = await fetchWithTimeout(`${DAEMON_BASE}/pair`, {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({ code, label: navigator.userAgent }),
+  });
+  if (!response.ok) {
+    throw new Error(
+      response.status === 403
+        ? "Invalid or expired pairing code"
+        : `Daemon returned ${response.status}`,
+    );
+  }
+  const { token } = (await response.json()) as { token: string };
+  await browser.storage.local.set({ daemonToken: token });
+}
+
 export async function isDaemonRunning(): Promise<boolean> {
   try {
     const response = await fetchWithTimeout(`${DAEMON_BASE}/health`);
@@ -37,6 +70,7 @@ export async function pushFileToDaemon(
       `${DAEMON_BASE}/${collection}/${encodeURIComponent(filename)}`,
       {
         method: "POST",
+        headers: await authHeaders(),
         body,
       },
     );
@@ -55,6 +89,7 @@ export async function pushWordsFileToDaemon(
       `${DAEMON_BASE}/words/${encodeURIComponent(anga)}/${encodeURIComponent(filename)}`,
       {
         method: "POST",
+        headers: await authHeaders(),
         body: new TextEncoder().encode(content),
       },
     );
@@ -67,7 +102,10 @@ export async function pushConfigToDaemon(config: Config): Promise<void> {
   try {
     await fetchWithTimeout(`${DAEMON_BASE}/config`, {
       method: "POST",
-      headers: { "Content-Type": "application/json" },
+      headers: {
+        "Content-Type": "application/json",
+        ...(await authHeaders()),
+      },
       body: JSON.stringify({
         server: config.server,
         email: config.email,